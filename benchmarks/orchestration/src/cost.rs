@@ -0,0 +1,253 @@
+//! Keeping track of what experiments cost.
+//!
+//! Every EC2 instance an experiment spawns goes into the run's [`Ledger`] when it is launched and
+//! again when it is terminated, along with the experiment it was for. At the end of the run, the
+//! ledger is written out to `usage.tsv` in the run's directory, with one line per machine, and
+//! summarized per experiment in `cost.txt`.
+
+use crate::provider::Setup;
+use color_eyre::{eyre::WrapErr, Report};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// On-demand hourly prices in us-east-1, in dollars.
+const PRICES: &[(&str, f64)] = &[
+    ("m5n.large", 0.119),
+    ("m5n.xlarge", 0.238),
+    ("m5n.2xlarge", 0.476),
+    ("m5n.4xlarge", 0.952),
+    ("m5n.8xlarge", 1.904),
+    ("m5n.12xlarge", 2.856),
+    ("m5n.16xlarge", 3.808),
+    ("r5n.large", 0.149),
+    ("r5n.xlarge", 0.298),
+    ("r5n.2xlarge", 0.596),
+    ("r5n.4xlarge", 1.192),
+    ("r5n.8xlarge", 2.384),
+    ("r5n.12xlarge", 3.576),
+    ("r5n.16xlarge", 4.768),
+];
+
+pub(crate) fn price(instance_type: &str) -> Option<f64> {
+    PRICES
+        .iter()
+        .find(|&&(t, _)| t == instance_type)
+        .map(|&(_, price)| price)
+}
+
+#[derive(Debug)]
+struct Usage {
+    benchmark: &'static str,
+    parameters: String,
+    machine: String,
+    role: String,
+    instance_type: String,
+    spot: bool,
+    launched: SystemTime,
+    /// `None` if we never managed to terminate the machine.
+    terminated: Option<SystemTime>,
+}
+
+impl Usage {
+    fn hours(&self, now: SystemTime) -> f64 {
+        let end = self.terminated.unwrap_or(now);
+        let up = end.duration_since(self.launched).unwrap_or_default();
+        up.as_secs_f64() / 3600.0
+    }
+}
+
+/// Machines that were launched together, and will be terminated together.
+#[derive(Debug)]
+#[must_use]
+pub(crate) struct Launched(std::ops::Range<usize>);
+
+#[derive(Debug)]
+pub(crate) struct Ledger {
+    /// Whether the machines we spawn cost anything.
+    billed: bool,
+    usage: Mutex<Vec<Usage>>,
+}
+
+impl Ledger {
+    pub(crate) fn new(billed: bool) -> Self {
+        Ledger {
+            billed,
+            usage: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Note that the given machines are being launched for an experiment.
+    pub(crate) fn launched(
+        &self,
+        benchmark: &'static str,
+        parameters: &dyn std::fmt::Debug,
+        spot: bool,
+        machines: &[(String, Setup)],
+    ) -> Launched {
+        let mut usage = self.usage.lock().unwrap();
+        let start = usage.len();
+        if !self.billed {
+            return Launched(start..start);
+        }
+
+        let now = SystemTime::now();
+        let parameters = format!("{:?}", parameters);
+        usage.extend(machines.iter().map(|(name, setup)| Usage {
+            benchmark,
+            parameters: parameters.clone(),
+            machine: name.clone(),
+            role: setup.role_name().unwrap_or(name).to_string(),
+            instance_type: setup.instance().unwrap_or("unknown").to_string(),
+            spot,
+            launched: now,
+            terminated: None,
+        }));
+        Launched(start..usage.len())
+    }
+
+    /// Note that the given machines have all been terminated.
+    ///
+    /// Machines that were already noted as terminated keep their original termination time.
+    pub(crate) fn terminated(&self, launched: &Launched) {
+        let now = SystemTime::now();
+        for usage in &mut self.usage.lock().unwrap()[launched.0.clone()] {
+            usage.terminated.get_or_insert(now);
+        }
+    }
+
+    /// Write out every machine to `usage`, and what each experiment cost to `report`.
+    ///
+    /// Does nothing if no machines were launched.
+    pub(crate) fn write(
+        &self,
+        usage: impl AsRef<Path>,
+        report: impl AsRef<Path>,
+    ) -> Result<(), Report> {
+        let all = self.usage.lock().unwrap();
+        if all.is_empty() {
+            return Ok(());
+        }
+        let now = SystemTime::now();
+
+        let usage = usage.as_ref();
+        let mut f = std::fs::File::create(usage)
+            .wrap_err_with(|| format!("failed to create {}", usage.display()))?;
+        let r: Result<(), std::io::Error> = try {
+            writeln!(
+                f,
+                "#benchmark\tparameters\tmachine\trole\tinstance\tspot\tlaunched\tterminated\thours"
+            )?;
+            for u in all.iter() {
+                writeln!(
+                    f,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.3}",
+                    u.benchmark,
+                    u.parameters,
+                    u.machine,
+                    u.role,
+                    u.instance_type,
+                    u.spot,
+                    epoch(u.launched),
+                    u.terminated
+                        .map(|t| epoch(t).to_string())
+                        .unwrap_or_default(),
+                    u.hours(now)
+                )?;
+            }
+        };
+        r.wrap_err_with(|| format!("failed to write {}", usage.display()))?;
+
+        // experiments that were re-provisioned show up more than once, and they should add up
+        let mut experiments: Vec<Experiment<'_>> = Vec::new();
+        let mut index = HashMap::new();
+        for u in all.iter() {
+            let i = *index
+                .entry((u.benchmark, &*u.parameters))
+                .or_insert_with(|| {
+                    experiments.push(Experiment {
+                        benchmark: u.benchmark,
+                        parameters: &u.parameters,
+                        machines: 0,
+                        hours: 0.0,
+                        cost: 0.0,
+                        unpriced: Vec::new(),
+                        running: 0,
+                        spot: false,
+                    });
+                    experiments.len() - 1
+                });
+            let e = &mut experiments[i];
+            let hours = u.hours(now);
+            e.machines += 1;
+            e.hours += hours;
+            if let Some(price) = price(&u.instance_type) {
+                e.cost += hours * price;
+            } else if !e.unpriced.contains(&&*u.instance_type) {
+                e.unpriced.push(&u.instance_type);
+            }
+            if u.terminated.is_none() {
+                e.running += 1;
+            }
+            e.spot |= u.spot;
+        }
+        experiments.sort_by(|a, b| b.cost.partial_cmp(&a.cost).expect("costs are never NaN"));
+
+        let report = report.as_ref();
+        let mut f = std::fs::File::create(report)
+            .wrap_err_with(|| format!("failed to create {}", report.display()))?;
+        let r: Result<(), std::io::Error> = try {
+            writeln!(f, "# at on-demand prices, most expensive first")?;
+            let mut total_hours = 0.0;
+            let mut total_cost = 0.0;
+            for e in &experiments {
+                write!(
+                    f,
+                    "{} {}: {} machine(s), {:.1} instance-hours, ${:.2}",
+                    e.benchmark, e.parameters, e.machines, e.hours, e.cost
+                )?;
+                if e.spot {
+                    write!(f, " (partly on spot instances)")?;
+                }
+                if !e.unpriced.is_empty() {
+                    write!(f, " (no price for {})", e.unpriced.join(", "))?;
+                }
+                if e.running != 0 {
+                    write!(f, " ({} machine(s) may still be running!)", e.running)?;
+                }
+                writeln!(f)?;
+                total_hours += e.hours;
+                total_cost += e.cost;
+            }
+            writeln!(
+                f,
+                "total: {:.1} instance-hours, ${:.2}",
+                total_hours, total_cost
+            )?;
+        };
+        r.wrap_err_with(|| format!("failed to write {}", report.display()))?;
+        Ok(())
+    }
+}
+
+/// What one experiment cost in total.
+struct Experiment<'a> {
+    benchmark: &'static str,
+    parameters: &'a str,
+    machines: usize,
+    hours: f64,
+    cost: f64,
+    unpriced: Vec<&'a str>,
+    /// Machines that were never terminated.
+    running: usize,
+    spot: bool,
+}
+
+/// Seconds since the epoch.
+fn epoch(t: SystemTime) -> u64 {
+    t.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs()
+}
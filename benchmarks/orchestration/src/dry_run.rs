@@ -0,0 +1,175 @@
+//! Describing what a run would do, and roughly what it would cost, without doing any of it.
+
+use crate::repeat::Repetitions;
+use crate::spec::Search;
+use std::time::Duration;
+
+/// Every load point primes for a minute and then runs the benchmark for 320 seconds.
+const PER_POINT: Duration = Duration::from_secs(60 + 320);
+
+/// Booting, `git pull`, and building noria in release mode takes a while.
+const SETUP: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug)]
+enum Runs {
+    Once(Duration),
+    Points {
+        points: Vec<usize>,
+        assuming: Option<String>,
+        repetitions: Repetitions,
+    },
+}
+
+/// What a single call to a benchmark's `one` would do.
+#[derive(Debug)]
+pub(crate) struct Experiment {
+    parameters: String,
+    /// Role, instance type, and count.
+    machines: Vec<(&'static str, String, usize)>,
+    runs: Runs,
+}
+
+impl Experiment {
+    /// An experiment that runs the points of the given search.
+    pub(crate) fn searching(parameters: &impl std::fmt::Debug, search: &Search) -> Self {
+        let (points, assuming) = search.expected();
+        Experiment {
+            parameters: format!("{:?}", parameters),
+            machines: Vec::new(),
+            runs: Runs::Points {
+                points,
+                assuming,
+                repetitions: Repetitions::default(),
+            },
+        }
+    }
+
+    /// Run each point as many times as the given repetitions say.
+    pub(crate) fn repeated(mut self, with: &Repetitions) -> Self {
+        if let Runs::Points {
+            ref mut repetitions,
+            ..
+        } = self.runs
+        {
+            *repetitions = with.clone();
+        }
+        self
+    }
+
+    /// An experiment that runs the benchmark just once, for the given amount of time.
+    pub(crate) fn once(runtime: Duration) -> Self {
+        Experiment {
+            parameters: String::from("()"),
+            machines: Vec::new(),
+            runs: Runs::Once(runtime),
+        }
+    }
+
+    pub(crate) fn machine(mut self, role: &'static str, instance_type: &str, count: usize) -> Self {
+        self.machines.push((role, instance_type.to_string(), count));
+        self
+    }
+
+    fn runtime(&self) -> Duration {
+        SETUP
+            + match self.runs {
+                Runs::Once(runtime) => runtime,
+                Runs::Points {
+                    ref points,
+                    ref repetitions,
+                    ..
+                } => {
+                    let runs = (points.len() * repetitions.runs) as u32;
+                    // every run but the first of each point sets up machines of its own
+                    let setups = if repetitions.fresh_instances {
+                        (points.len() * (repetitions.runs - 1)) as u32
+                    } else {
+                        0
+                    };
+                    PER_POINT * runs + SETUP * setups
+                }
+            }
+    }
+}
+
+fn hours(d: Duration) -> f64 {
+    d.as_secs_f64() / 3600.0
+}
+
+/// Print the plan for one benchmark, and return its instance-hours and cost.
+///
+/// The cost only includes instance-hours whose price we know.
+pub(crate) fn print(benchmark: &str, experiments: &[Experiment]) -> (f64, f64) {
+    println!("{}: {} experiment(s)", benchmark, experiments.len());
+
+    let mut total_hours = 0.0;
+    let mut total_cost = 0.0;
+    for experiment in experiments {
+        println!("  {}", experiment.parameters);
+
+        let machines: Vec<_> = experiment
+            .machines
+            .iter()
+            .map(|&(role, ref instance_type, count)| {
+                format!("{}x {} ({})", count, instance_type, role)
+            })
+            .collect();
+        println!("    machines: {}", machines.join(", "));
+
+        match experiment.runs {
+            Runs::Once(runtime) => {
+                println!("    runs once for {}s", runtime.as_secs());
+            }
+            Runs::Points {
+                ref points,
+                ref assuming,
+                ref repetitions,
+            } => {
+                let points: Vec<_> = points.iter().map(|p| p.to_string()).collect();
+                print!("    points: {}", points.join(" "));
+                if let Some(ref assuming) = *assuming {
+                    print!(" ({})", assuming);
+                }
+                println!();
+                if repetitions.runs > 1 {
+                    print!(
+                        "    each run {} times, good if {} are",
+                        repetitions.runs,
+                        repetitions.quorum()
+                    );
+                    if repetitions.fresh_instances {
+                        print!(", on fresh instances");
+                    }
+                    println!();
+                }
+            }
+        }
+
+        let runtime = hours(experiment.runtime());
+        let mut instance_hours = 0.0;
+        let mut cost = 0.0;
+        let mut unpriced = Vec::new();
+        for &(_, ref instance_type, count) in &experiment.machines {
+            let h = runtime * count as f64;
+            instance_hours += h;
+            if let Some(price) = crate::cost::price(instance_type) {
+                cost += h * price;
+            } else {
+                unpriced.push(&**instance_type);
+            }
+        }
+        print!(
+            "    ~{:.1}h, {:.1} instance-hours, ${:.2}",
+            runtime, instance_hours, cost
+        );
+        if !unpriced.is_empty() {
+            print!(" (no price for {})", unpriced.join(", "));
+        }
+        println!();
+
+        total_hours += instance_hours;
+        total_cost += cost;
+    }
+
+    (total_hours, total_cost)
+}
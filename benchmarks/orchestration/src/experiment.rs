@@ -0,0 +1,552 @@
+//! The lifecycle that every cliff-searching benchmark shares.
+//!
+//! Each benchmark spins up a server and some clients, connects to them, searches for the load at
+//! which the server falls over, and then tears everything down again. Only the machines, how to
+//! start and stop the server, and how to drive the clients differ between them. Those are
+//! described by an [`Experiment`], and [`run`] takes care of the rest.
+
+use crate::host::{Child, Host, Session};
+use crate::provider::{Launcher, Setup};
+use crate::retry::{Backoff, Scope};
+use crate::spec::{Plan, Search};
+use crate::Context;
+use color_eyre::{eyre::WrapErr, Report};
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing_futures::Instrument;
+use tsunami::providers::aws;
+use tsunami::Tsunami;
+
+/// The machines spawned for a single experiment, by role.
+pub(crate) struct Machines(HashMap<String, Host>);
+
+impl Machines {
+    pub(crate) fn get(&self, role: &str) -> &Host {
+        self.0
+            .get(role)
+            .unwrap_or_else(|| panic!("no machine with role {}", role))
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &Host)> {
+        self.0.iter().map(|(name, host)| (&**name, host))
+    }
+
+    /// The sessions of the clients spawned by [`clients`].
+    pub(crate) fn clients(&self, n: usize) -> Vec<&Session> {
+        (0..n)
+            .map(|clienti| &self.get(&format!("client{}", clienti)).session)
+            .collect()
+    }
+}
+
+/// `n` client machines, named `client0` through `client{n-1}`.
+pub(crate) fn clients(n: usize, setup: Setup) -> impl Iterator<Item = (String, Setup)> {
+    (0..n).map(move |clienti| (format!("client{}", clienti), setup.clone()))
+}
+
+/// A single benchmark configuration, whose cliff [`run`] will search for.
+pub(crate) trait Experiment: Send + Sync {
+    /// The name of the benchmark, as given on the command line.
+    const NAME: &'static str;
+
+    /// The parameters of this experiment, which name its search state.
+    fn parameters(&self) -> &(dyn std::fmt::Debug + Sync);
+
+    /// The search to use, unless the plan says otherwise.
+    fn search(&self) -> Search;
+
+    /// The machines to spawn, by role.
+    fn machines(&self, ctx: &Context) -> Vec<(String, Setup)>;
+
+    /// Set up the machines once they are all connected.
+    fn prepare<'a>(&'a self, _: &'a Machines) -> BoxFuture<'a, Result<(), Report>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// A point to run before the search starts, whose outcome is still reported to the search.
+    fn baseline(&self) -> Option<usize> {
+        None
+    }
+
+    /// Turn a point yielded by the search into the point to run.
+    ///
+    /// This may also reconfigure the experiment for all the points that follow.
+    fn adjust(&mut self, point: usize) -> usize {
+        point
+    }
+
+    /// The directory of a run that the results go in.
+    ///
+    /// See [`crate::results`].
+    fn results(&self) -> &'static str;
+
+    /// The prefix of the result files for the given point.
+    fn prefix(&self, point: usize) -> String;
+
+    /// Whether the results of the given point at `prefix` are complete, and if so, whether they
+    /// were overloaded.
+    fn completed(&self, point: usize, prefix: &str) -> Option<bool>;
+
+    /// If the given point is known to be overloaded, why.
+    fn known_bad(&self, _: usize) -> Option<&'static str> {
+        None
+    }
+
+    /// Start the server for the given point.
+    ///
+    /// If the server runs as a process of its own, that process is returned.
+    fn start<'a>(
+        &'a self,
+        point: usize,
+        machines: &'a Machines,
+    ) -> BoxFuture<'a, Result<Option<Child<'a>>, Report>>;
+
+    /// Drive the clients at the given point, and write the results to `prefix`.
+    fn invoke<'a>(
+        &'a self,
+        point: usize,
+        prefix: &'a str,
+        on_overloaded: &'a mut (dyn FnMut() + Send),
+        machines: &'a Machines,
+        ctx: &'a mut Context,
+    ) -> BoxFuture<'a, Result<(), Report>>;
+
+    /// The system services the server runs as, if it does not run as a process of its own.
+    ///
+    /// What these log while a target runs goes into its server log.
+    fn services(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Stop the server that `start` started.
+    fn stop<'a>(
+        &'a self,
+        server: Option<Child<'a>>,
+        machines: &'a Machines,
+    ) -> BoxFuture<'a, Result<(), Report>>;
+}
+
+/// How often to retry a transient failure at any one scope.
+const RETRIES: usize = 3;
+
+/// Run the given experiment, and return the last point that was not overloaded.
+///
+/// Transient failures are retried on fresh machines, and the search then continues from where it
+/// left off.
+pub(crate) async fn run<E: Experiment>(
+    mut experiment: E,
+    plan: Plan,
+    mut ctx: Context,
+) -> Result<usize, Report> {
+    let mut backoff = Backoff::new(Duration::from_secs(60), RETRIES);
+    let mut reclaimed = Backoff::new(crate::spot::REPROVISION_DELAY, crate::spot::REPROVISIONS);
+    loop {
+        let e = match attempt(&mut experiment, &plan, &mut ctx).await {
+            Ok(last_good_point) => return Ok(last_good_point),
+            Err(e) => e,
+        };
+
+        if *ctx.exit.borrow() {
+            return Err(e);
+        }
+        if e.downcast_ref::<crate::spot::Interrupted>().is_some() {
+            // being reclaimed is just what spot instances do, so this does not count as a retry, but
+            // there is no point in asking for more right away if capacity is short
+            tracing::warn!("re-provisioning reclaimed spot instances");
+            if !reclaimed.wait().await {
+                return Err(e.wrap_err("giving up after spot instances were reclaimed too often"));
+            }
+        } else if crate::retry::classify(&e).is_some() {
+            tracing::warn!("re-spawning instances after transient failure: {:?}", e);
+            if !backoff.wait().await {
+                return Err(e.wrap_err("giving up after too many transient failures"));
+            }
+        } else {
+            return Err(e);
+        }
+
+        // pick up the search where the failed attempt left off
+        ctx.resume = true;
+    }
+}
+
+/// Whether there are complete results for the given point at `prefix`, or for the same target in
+/// the archived results tree.
+///
+/// Returns where the complete results are, and whether the point was overloaded.
+fn completed<E: Experiment>(
+    experiment: &E,
+    results: &crate::results::Layout,
+    point: usize,
+    prefix: &str,
+) -> Option<(String, bool)> {
+    if let Some(good) = experiment.completed(point, prefix) {
+        return Some((prefix.to_string(), good));
+    }
+
+    let name = std::path::Path::new(prefix).file_name()?.to_str()?;
+    let archived = results.archived(experiment.results(), name)?;
+    let archived = archived.to_string_lossy().into_owned();
+    let good = experiment.completed(point, &archived)?;
+    tracing::debug!(%archived, "found archived results");
+    Some((archived, good))
+}
+
+/// Run the experiment on a single set of machines.
+async fn attempt<E: Experiment>(
+    experiment: &mut E,
+    plan: &Plan,
+    ctx: &mut Context,
+) -> Result<usize, Report> {
+    let mut last_good_point = 0;
+
+    let (mut aws, mut launched) = spawn(&*experiment, ctx).await?;
+
+    // try to ensure we do AWS cleanup
+    let result: Result<_, Report> = try {
+        tracing::debug!("connecting");
+        let mut machines = Machines(aws.hosts().await?);
+        tracing::debug!("connected");
+
+        let result: Result<(), Report> = try {
+            experiment.prepare(&machines).await?;
+
+            let points = plan.search_or(|| experiment.search()).searcher();
+            let mut points = crate::search::Recorded::new(
+                ctx.results.run(),
+                E::NAME,
+                &experiment.parameters(),
+                plan,
+                points,
+                ctx.resume,
+            )?;
+            if let Some(point) = points.last_good() {
+                last_good_point = point;
+            }
+
+            let dir = ctx.results.dir(experiment.results())?;
+            let repetitions = &plan.repetitions;
+            // whether the current machines have run anything yet
+            let mut used = false;
+            let mut baseline = experiment.baseline();
+            let mut successful_point = None;
+            'points: while let Some(point) = baseline.take().or_else(|| points.next()) {
+                let point = experiment.adjust(point);
+
+                if let Some(point) = successful_point.take() {
+                    // last run succeeded at the given point
+                    last_good_point = point;
+                }
+                successful_point = Some(point);
+
+                let prefix = dir
+                    .join(experiment.prefix(point))
+                    .to_string_lossy()
+                    .into_owned();
+                let runs = repetitions.prefixes(&prefix);
+                let previous = plan.skip(point).or_else(|| {
+                    let good: Option<Vec<_>> = runs
+                        .iter()
+                        .map(|p| Some(completed(&*experiment, &ctx.results, point, p)?.1))
+                        .collect();
+                    good.map(|good| repetitions.passed(&good))
+                });
+                if let Some(good) = previous {
+                    if !good {
+                        points.overloaded();
+                        successful_point.take();
+                    }
+                    tracing::info!(%point, good, "skipping point we already know the outcome of");
+                    points.finished();
+                    continue;
+                }
+
+                if let Some(reason) = experiment.known_bad(point) {
+                    tracing::warn!(%point, reason, "skipping known-bad point");
+                    points.overloaded();
+                    successful_point.take();
+                    points.finished();
+                    continue;
+                }
+
+                let mut good = Vec::new();
+                // where the results of each run are, for the summary
+                let mut found = runs.clone();
+                for (run, run_prefix) in runs.iter().enumerate() {
+                    if let Some((at, previous)) =
+                        completed(&*experiment, &ctx.results, point, run_prefix)
+                    {
+                        tracing::info!(%point, run = run + 1, good = previous, "skipping run we already did");
+                        good.push(previous);
+                        found[run] = at;
+                        continue;
+                    }
+
+                    if *ctx.exit.borrow() {
+                        tracing::info!("exiting as instructed");
+                        break 'points;
+                    }
+
+                    if used && repetitions.fresh_instances {
+                        replace(&*experiment, ctx, &mut aws, &mut launched, &mut machines).await?;
+                    }
+                    used = true;
+
+                    let point_span =
+                        tracing::info_span!("point", point, run = tracing::field::Empty);
+                    if runs.len() > 1 {
+                        point_span.record("run", &(run + 1));
+                    }
+                    let overloaded =
+                        run_point(&*experiment, point, run_prefix, &mut machines, &aws, ctx)
+                            .instrument(point_span)
+                            .await?;
+                    if *ctx.exit.borrow() {
+                        // the run was cut short, so its outcome tells us nothing
+                        tracing::info!("exiting as instructed");
+                        break 'points;
+                    }
+                    good.push(!overloaded);
+                }
+
+                if runs.len() > 1 {
+                    let summary = repetitions
+                        .summarize(&prefix, &found, &good)
+                        .wrap_err("failed to summarize repetitions")?;
+                    tracing::info!(%point, %summary, good = ?good, "ran all repetitions");
+                }
+                if !repetitions.passed(&good) {
+                    points.overloaded();
+                    successful_point.take();
+                }
+                points.finished();
+            }
+        };
+
+        tracing::debug!("cleaning up");
+        close(machines).await;
+
+        result?
+    };
+
+    tracing::trace!("cleaning up instances");
+    let cleanup = aws.terminate_all().await;
+    if cleanup.is_ok() {
+        ctx.ledger.terminated(&launched);
+    }
+    tracing::debug!("done");
+    let _ = result?;
+    let _ = cleanup.wrap_err("cleanup failed")?;
+    Ok(last_good_point)
+}
+
+/// Close the ssh connections to all of the given machines.
+async fn close(machines: Machines) {
+    tracing::trace!("cleaning up ssh connections");
+    for (name, host) in machines.0 {
+        let host_span = tracing::trace_span!("ssh_close", name = &*name);
+        async {
+            tracing::trace!("closing connection");
+            if let Err(e) = host.session.close().await {
+                tracing::warn!("ssh connection failed: {:?}", e);
+            }
+        }
+        .instrument(host_span)
+        .await
+    }
+}
+
+/// Swap the experiment's machines for freshly spawned ones.
+async fn replace<E: Experiment>(
+    experiment: &E,
+    ctx: &mut Context,
+    aws: &mut Launcher,
+    launched: &mut crate::cost::Launched,
+    machines: &mut Machines,
+) -> Result<(), Report> {
+    tracing::info!("replacing machines");
+    close(std::mem::replace(machines, Machines(HashMap::new()))).await;
+    // if spawning fails, the caller cleans up what is left, which is then nothing
+    let old = std::mem::replace(aws, crate::launcher(&ctx.provider));
+    old.terminate_all().await.wrap_err("cleanup failed")?;
+    ctx.ledger.terminated(launched);
+
+    let (new_aws, new_launched) = spawn(experiment, ctx).await?;
+    *aws = new_aws;
+    *launched = new_launched;
+    tracing::debug!("connecting");
+    *machines = Machines(aws.hosts().await?);
+    tracing::debug!("connected");
+    experiment.prepare(machines).await
+}
+
+/// Spawn the experiment's machines, all in the same availability zone.
+///
+/// The candidate zones are tried in order until one has room for all of the machines. The zone
+/// that was used is left in `ctx.az`, and the instance types of the server and the clients in
+/// `ctx.server_type` and `ctx.client_type`.
+async fn spawn<E: Experiment>(
+    experiment: &E,
+    ctx: &mut Context,
+) -> Result<(Launcher, crate::cost::Launched), Report> {
+    spawn_in_zones(ctx, E::NAME, &experiment.parameters(), |ctx| {
+        let machines = experiment.machines(ctx);
+        // the results should say what the machines actually ran on, which is up to their roles
+        for (name, setup) in &machines {
+            if let Some(instance) = setup.instance() {
+                if name == "server" {
+                    ctx.server_type = instance.to_string();
+                } else if name.starts_with("client") {
+                    ctx.client_type = instance.to_string();
+                }
+            }
+        }
+        machines
+    })
+    .await
+}
+
+/// Spawn the machines that `machines` sets up for the zone in `ctx.az`, all in the same
+/// availability zone.
+///
+/// The candidate zones are tried in order until one has room for all of the machines, and the zone
+/// that was used is left in `ctx.az`.
+pub(crate) async fn spawn_in_zones(
+    ctx: &mut Context,
+    benchmark: &'static str,
+    parameters: &(dyn std::fmt::Debug + Sync),
+    mut machines: impl FnMut(&mut Context) -> Vec<(String, Setup)>,
+) -> Result<(Launcher, crate::cost::Launched), Report> {
+    let mut zones = ctx.zones.clone().into_iter().peekable();
+    loop {
+        if let Some(zone) = zones.next() {
+            ctx.az = aws::AvailabilityZoneSpec::Specify(zone);
+        }
+
+        let machines = machines(ctx);
+        let mut aws = crate::launcher(&ctx.provider);
+        aws.set_mode(crate::spot::launch_mode(ctx.spot));
+        tracing::info!(az = ?ctx.az, "spinning up aws instances");
+        let launched = ctx
+            .ledger
+            .launched(benchmark, parameters, ctx.spot, &machines);
+        let e = match aws.spawn(machines, None).await {
+            Ok(()) => return Ok((aws, launched)),
+            Err(e) => e.wrap_err("failed to start instances"),
+        };
+
+        // don't leave behind whatever machines did start
+        match aws.terminate_all().await {
+            Ok(()) => ctx.ledger.terminated(&launched),
+            Err(e) => tracing::warn!("failed to clean up after failed spawn: {:?}", e),
+        }
+        if zones.peek().is_none() || !crate::retry::zone_specific(&e) {
+            return Err(e);
+        }
+        tracing::warn!(az = ?ctx.az, "trying the next availability zone: {:?}", e);
+    }
+}
+
+/// Run a single point, and return whether it was overloaded.
+///
+/// Transient failures that do not call for fresh machines are retried here.
+async fn run_point<E: Experiment>(
+    experiment: &E,
+    point: usize,
+    prefix: &str,
+    machines: &mut Machines,
+    aws: &Launcher,
+    ctx: &mut Context,
+) -> Result<bool, Report> {
+    let mut backoff = Backoff::new(Duration::from_secs(10), RETRIES);
+    loop {
+        // only overloads from a run that completed should make it to the search
+        let mut overloaded = false;
+        let result: Result<(), Report> = try {
+            tracing::info!("start benchmark target");
+            let mut log = crate::server::Log::start(
+                prefix,
+                &machines.get("server").session,
+                experiment.services(),
+            )
+            .await?;
+            let mut server = experiment.start(point, machines).await?;
+            if let Some(ref mut server) = server {
+                log.capture(server);
+            }
+
+            let spot = ctx.spot;
+            let mut on_overloaded = || overloaded = true;
+            let invoked = experiment.invoke(point, prefix, &mut on_overloaded, machines, ctx);
+            let result = if spot {
+                tokio::select! {
+                    r = invoked => r,
+                    () = crate::spot::watch(machines) => Err(Report::new(crate::spot::Interrupted)),
+                }
+            } else {
+                invoked.await
+            };
+            let reclaimed = matches!(result, Err(ref e) if e.is::<crate::spot::Interrupted>());
+
+            // stop the server even if the benchmark failed, so that a retry starts from scratch,
+            // unless the machines are going away anyway
+            if !*ctx.exit.borrow() && !reclaimed {
+                tracing::debug!("stopping server");
+                let stopped = experiment.stop(server, machines).await;
+                let logged = log.finish().await;
+                if result.is_ok() {
+                    stopped?;
+                    tracing::trace!("server stopped");
+                    logged?;
+                } else {
+                    if let Err(e) = stopped {
+                        tracing::warn!("failed to stop server: {:?}", e);
+                    }
+                    if let Err(e) = logged {
+                        tracing::warn!("failed to save server log: {:?}", e);
+                    }
+                }
+            }
+
+            result?
+        };
+
+        let reclaimed = match result {
+            Err(ref e) if e.is::<crate::spot::Interrupted>() => true,
+            _ => ctx.spot && crate::spot::interrupted(machines).await,
+        };
+        if reclaimed {
+            // whatever happened to the target, it was not the benchmark's fault
+            crate::spot::discard(prefix)?;
+            return Err(Report::new(crate::spot::Interrupted));
+        }
+
+        let e = match result {
+            Ok(()) => return Ok(overloaded),
+            Err(e) => e,
+        };
+
+        if let Some(crashed) = e.downcast_ref::<crate::watchdog::Crashed>() {
+            // the results are saved, and a crash is as overloaded as it gets
+            tracing::warn!("{}", crashed);
+            return Ok(true);
+        }
+
+        let scope = match crate::retry::classify(&e) {
+            Some(scope) if scope < Scope::Instances && !*ctx.exit.borrow() => scope,
+            _ => return Err(e),
+        };
+        tracing::warn!(?scope, "retrying target after transient failure: {:?}", e);
+        if !backoff.wait().await {
+            return Err(e.wrap_err("giving up on target after too many transient failures"));
+        }
+
+        if scope == Scope::Connection {
+            tracing::debug!("reconnecting");
+            close(std::mem::replace(machines, Machines(HashMap::new()))).await;
+            *machines = Machines(aws.hosts().await?);
+            tracing::debug!("reconnected");
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! A uniform way to run commands on the machines of an experiment.
+//!
+//! Most of the time, those machines are on the other end of an ssh connection. In local mode,
+//! they are all just the machine we are running on, and commands are executed as regular child
+//! processes in a working directory that stands in for the remote home directory.
+
+use color_eyre::{eyre::WrapErr, Report};
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Output, Stdio};
+use tokio::process::{ChildStderr, ChildStdin, ChildStdout};
+
+/// A machine that is part of an experiment.
+#[derive(Debug)]
+pub(crate) struct Host {
+    pub(crate) nickname: String,
+    pub(crate) private_ip: Option<String>,
+    pub(crate) session: Session,
+}
+
+impl From<tsunami::Machine<'_>> for Host {
+    fn from(vm: tsunami::Machine<'_>) -> Self {
+        let tsunami::Machine {
+            nickname,
+            private_ip,
+            ssh,
+            ..
+        } = vm;
+        Host {
+            nickname,
+            private_ip,
+            session: Session::Ssh(ssh),
+        }
+    }
+}
+
+impl Host {
+    pub(crate) fn local(nickname: String, dir: PathBuf) -> Self {
+        Host {
+            nickname,
+            private_ip: Some(String::from("127.0.0.1")),
+            session: Session::Local(dir),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Session {
+    Ssh(openssh::Session),
+    /// Run commands locally, in the given directory.
+    Local(PathBuf),
+}
+
+impl Session {
+    pub(crate) fn command<'a, S: Into<std::borrow::Cow<'a, str>>>(
+        &self,
+        program: S,
+    ) -> Command<'_> {
+        match *self {
+            Session::Ssh(ref ssh) => Command::Ssh(ssh.command(program)),
+            Session::Local(ref dir) => {
+                let mut cmd = tokio::process::Command::new(&*program.into());
+                cmd.current_dir(dir).kill_on_drop(true);
+                Command::Local(cmd)
+            }
+        }
+    }
+
+    pub(crate) fn shell<S: AsRef<str>>(&self, command: S) -> Command<'_> {
+        match *self {
+            Session::Ssh(ref ssh) => Command::Ssh(ssh.shell(command)),
+            Session::Local(ref dir) => {
+                let mut cmd = tokio::process::Command::new("sh");
+                cmd.arg("-c")
+                    .arg(command.as_ref())
+                    .current_dir(dir)
+                    .kill_on_drop(true);
+                Command::Local(cmd)
+            }
+        }
+    }
+
+    /// Open a file on the host for reading.
+    ///
+    /// Relative paths are resolved against the home directory (or the working directory, for
+    /// local sessions).
+    pub(crate) async fn read_from(
+        &self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Box<dyn tokio::io::AsyncRead + Unpin + Send + '_>, Report> {
+        match *self {
+            Session::Ssh(ref ssh) => Ok(Box::new(ssh.sftp().read_from(path).await?)),
+            Session::Local(ref dir) => Ok(Box::new(
+                tokio::fs::File::open(dir.join(path))
+                    .await
+                    .wrap_err("failed to open local file")?,
+            )),
+        }
+    }
+
+    pub(crate) async fn check(&self) -> Result<(), Report> {
+        match *self {
+            Session::Ssh(ref ssh) => Ok(ssh.check().await?),
+            Session::Local(_) => Ok(()),
+        }
+    }
+
+    pub(crate) async fn close(self) -> Result<(), Report> {
+        match self {
+            Session::Ssh(ssh) => Ok(ssh.close().await?),
+            Session::Local(_) => Ok(()),
+        }
+    }
+}
+
+pub(crate) enum Command<'s> {
+    Ssh(openssh::Command<'s>),
+    Local(tokio::process::Command),
+}
+
+impl<'s> Command<'s> {
+    pub(crate) fn arg<S: AsRef<str>>(&mut self, arg: S) -> &mut Self {
+        match *self {
+            Command::Ssh(ref mut cmd) => {
+                cmd.arg(arg);
+            }
+            Command::Local(ref mut cmd) => {
+                cmd.arg(arg.as_ref());
+            }
+        }
+        self
+    }
+
+    /// Add an argument that the remote shell should _not_ escape.
+    ///
+    /// Local commands are not run through a shell, so there this is the same as `arg`.
+    pub(crate) fn raw_arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        match *self {
+            Command::Ssh(ref mut cmd) => {
+                cmd.raw_arg(arg);
+            }
+            Command::Local(ref mut cmd) => {
+                cmd.arg(arg);
+            }
+        }
+        self
+    }
+
+    pub(crate) fn stdin<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        match *self {
+            Command::Ssh(ref mut cmd) => {
+                cmd.stdin(cfg);
+            }
+            Command::Local(ref mut cmd) => {
+                cmd.stdin(cfg);
+            }
+        }
+        self
+    }
+
+    pub(crate) fn stdout<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        match *self {
+            Command::Ssh(ref mut cmd) => {
+                cmd.stdout(cfg);
+            }
+            Command::Local(ref mut cmd) => {
+                cmd.stdout(cfg);
+            }
+        }
+        self
+    }
+
+    pub(crate) fn stderr<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        match *self {
+            Command::Ssh(ref mut cmd) => {
+                cmd.stderr(cfg);
+            }
+            Command::Local(ref mut cmd) => {
+                cmd.stderr(cfg);
+            }
+        }
+        self
+    }
+
+    pub(crate) fn spawn(&mut self) -> Result<Child<'s>, Report> {
+        match *self {
+            Command::Ssh(ref mut cmd) => Ok(Child::Ssh(cmd.spawn()?)),
+            Command::Local(ref mut cmd) => Ok(Child::Local(cmd.spawn()?)),
+        }
+    }
+
+    pub(crate) async fn output(&mut self) -> Result<Output, Report> {
+        match *self {
+            Command::Ssh(ref mut cmd) => Ok(cmd.output().await?),
+            Command::Local(ref mut cmd) => Ok(cmd.output().await?),
+        }
+    }
+
+    pub(crate) async fn status(&mut self) -> Result<ExitStatus, Report> {
+        match *self {
+            Command::Ssh(ref mut cmd) => Ok(cmd.status().await?),
+            Command::Local(ref mut cmd) => Ok(cmd.status().await?),
+        }
+    }
+}
+
+pub(crate) enum Child<'s> {
+    Ssh(openssh::RemoteChild<'s>),
+    Local(tokio::process::Child),
+}
+
+impl Child<'_> {
+    pub(crate) fn stdin(&mut self) -> &mut Option<ChildStdin> {
+        match *self {
+            Child::Ssh(ref mut child) => child.stdin(),
+            Child::Local(ref mut child) => &mut child.stdin,
+        }
+    }
+
+    pub(crate) fn stdout(&mut self) -> &mut Option<ChildStdout> {
+        match *self {
+            Child::Ssh(ref mut child) => child.stdout(),
+            Child::Local(ref mut child) => &mut child.stdout,
+        }
+    }
+
+    pub(crate) fn stderr(&mut self) -> &mut Option<ChildStderr> {
+        match *self {
+            Child::Ssh(ref mut child) => child.stderr(),
+            Child::Local(ref mut child) => &mut child.stderr,
+        }
+    }
+
+    pub(crate) async fn wait(self) -> Result<ExitStatus, Report> {
+        match self {
+            Child::Ssh(child) => Ok(child.wait().await?),
+            Child::Local(child) => Ok(child.await?),
+        }
+    }
+
+    pub(crate) async fn wait_with_output(self) -> Result<Output, Report> {
+        match self {
+            Child::Ssh(child) => Ok(child.wait_with_output().await?),
+            Child::Local(child) => Ok(child.wait_with_output().await?),
+        }
+    }
+}
+
+impl<'s> From<openssh::Command<'s>> for Command<'s> {
+    fn from(cmd: openssh::Command<'s>) -> Self {
+        Command::Ssh(cmd)
+    }
+}
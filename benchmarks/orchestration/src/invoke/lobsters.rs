@@ -14,22 +14,80 @@ pub(crate) enum Backend {
     Mysql { optimized: bool },
 }
 
+/// Check whether `run` already produced complete results for the given prefix.
+///
+/// See [`crate::resume::completed`].
+pub(crate) fn completed(prefix: &str, backend: Backend) -> Option<bool> {
+    let histograms = [format!("{}.hist", prefix)];
+    crate::resume::completed(
+        prefix,
+        &histograms,
+        backend == Backend::Noria,
+        crate::overload::OverloadPolicy::lobsters(),
+        None,
+    )
+}
+
 pub(crate) async fn run(
     prefix: &str,
+    parameters: serde_json::Value,
     scale: usize,
     mut on_overloaded: impl FnMut(),
-    c: &openssh::Session,
-    server: &tsunami::Machine<'_>,
+    c: &crate::host::Session,
+    server: &crate::host::Host,
     backend: Backend,
     ctx: &mut Context,
 ) -> Result<(), Report> {
+    let mut manifest = crate::manifest::Manifest::new(prefix, parameters, ctx);
+    let mut overload =
+        crate::overload::Detector::new(ctx, crate::overload::OverloadPolicy::lobsters());
     let Context {
         ref server_type,
         ref client_type,
         ref mut exit,
+        sample_clients,
+        profile,
+        steady_state,
         ..
     } = *ctx;
-    let s = &server.ssh;
+    let s = &server.session;
+    let (server_process, client_process) = match backend {
+        Backend::Noria => ("noria-server", "lobsters-noria"),
+        Backend::Mysql { .. } => ("mysqld", "lobsters-mysql"),
+    };
+
+    // remember why we overloaded, so we can record it along with the results
+    let mut overload_reasons = Vec::new();
+    let mut on_overloaded = |reason: &str| {
+        overload_reasons.push(reason.to_string());
+        on_overloaded();
+    };
+
+    tracing::debug!("start sampling resource use");
+    let mut sampler = crate::sampler::Sampler::new(prefix);
+    sampler
+        .watch("server", s, server_process)
+        .await
+        .wrap_err("failed to sample server")?;
+    if sample_clients {
+        sampler
+            .watch("client", c, client_process)
+            .await
+            .wrap_err("failed to sample client")?;
+    }
+
+    // without a client that can measure adaptively, fall back to the fixed durations
+    let steady_state = if steady_state.is_some()
+        && !crate::steady::client_supports(crate::noria_bin(c, client_process)).await?
+    {
+        tracing::warn!(
+            client = client_process,
+            "client cannot report on windows, so not measuring adaptively"
+        );
+        None
+    } else {
+        steady_state
+    };
 
     tracing::debug!("prime");
     let mut prime = lobsters_client(c, server, scale, backend);
@@ -51,13 +109,17 @@ pub(crate) async fn run(
         }
     };
     let prime_took = prime_start.elapsed();
+    manifest.prime_time = Some(prime_took.as_secs_f64());
 
     if !prime.status.success() {
         tracing::warn!(
             "priming failed:\n{}",
             String::from_utf8_lossy(&prime.stderr)
         );
-        on_overloaded();
+        on_overloaded("priming failed");
+        let (file, _) = sampler.finish().await?;
+        manifest.files.extend(file);
+        manifest.finish(overload_reasons, overload).await?;
         return Ok(());
     }
 
@@ -67,14 +129,52 @@ pub(crate) async fn run(
         return Ok(());
     }
 
+    let mut network = crate::network::Network::new(prefix);
+    network
+        .watch("server", s)
+        .await
+        .wrap_err("failed to snapshot server network")?;
+    network
+        .watch("client", c)
+        .await
+        .wrap_err("failed to snapshot client network")?;
+
+    let mut measurement = match steady_state {
+        Some(steady_state) => {
+            tracing::debug!("warm up");
+            let warmup = steady_state
+                .warm_up(|seconds| Box::pin(warm_up(c, server, scale, backend, seconds)))
+                .await
+                .wrap_err("failed to warm up")?;
+            manifest.warmup = Some(warmup);
+            Some(steady_state.measure(&warmup))
+        }
+        None => None,
+    };
+    let runtime = match steady_state {
+        Some(steady_state) => steady_state.client_args().to_vec(),
+        None => vec![String::from("--runtime=320")],
+    };
+
+    let watchdog = crate::watchdog::Watchdog::start(s, &[server_process])
+        .await
+        .wrap_err("failed to watch server")?;
+
     tracing::debug!("benchmark");
-    let mut bench = lobsters_client(c, server, scale, backend)
-        .arg("--runtime=320")
+    let mut bench = lobsters_client(c, server, scale, backend);
+    for arg in &runtime {
+        bench.arg(arg);
+    }
+    let mut bench = bench
         .arg("--histogram=benchmark.hist")
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped())
         .spawn()
         .wrap_err("failed to start client")?;
+    let profiler = match profile {
+        Some(window) => crate::profile::start(s, server_process, window).await,
+        None => None,
+    };
 
     // save normal output
     tracing::trace!("saving client output");
@@ -84,6 +184,7 @@ pub(crate) async fn run(
     let mut results = tokio::io::BufWriter::new(results);
     let mut target = None;
     let mut actual = None;
+    let mut converged = false;
     let fin = async {
         while let Some(line) = stdout.next().await {
             let line = line.wrap_err("failed to read client output")?;
@@ -91,6 +192,15 @@ pub(crate) async fn run(
             results.write_all(line.as_bytes()).await?;
             results.write_all(b"\n").await?;
 
+            if let Some(ref mut measurement) = measurement {
+                if !converged && measurement.line(&line) {
+                    converged = true;
+                    crate::watchdog::finish_clients(&[c], client_process)
+                        .await
+                        .wrap_err("failed to finish client")?;
+                }
+            }
+
             if target.is_none() || actual.is_none() {
                 if line.starts_with("# target ops/s") {
                     target = Some(line.rsplitn(2, ' ').next().unwrap().parse::<f64>()?);
@@ -98,9 +208,9 @@ pub(crate) async fn run(
                     actual = Some(line.rsplitn(2, ' ').next().unwrap().parse::<f64>()?);
                 }
                 if let (Some(target), Some(actual)) = (target, actual) {
-                    if actual < target * 4.0 / 5.0 {
+                    if let Some(reason) = overload.throughput(actual, target) {
                         tracing::error!(%actual, %target, "low throughput");
-                        on_overloaded();
+                        on_overloaded(&reason);
                     }
                 }
             }
@@ -125,16 +235,12 @@ pub(crate) async fn run(
                     continue;
                 }
 
-                let pct = if let Some(pct) = fields.next() {
+                let pct: u32 = if let Some(Ok(pct)) = fields.next().map(str::parse) {
                     pct
                 } else {
                     tracing::error!(case = "bad line", message = &*line);
                     continue;
                 };
-                if pct != "50" {
-                    assert!(pct == "95" || pct == "99" || pct == "100", "{}", pct);
-                    continue;
-                }
 
                 let us = if let Some(us) = fields.next() {
                     us
@@ -148,9 +254,9 @@ pub(crate) async fn run(
                     tracing::error!(case = "bad line", message = &*line);
                     continue;
                 };
-                if us > 200_000.0 {
-                    tracing::error!(endpoint = field, sojourn = %us, "high sojourn latency");
-                    on_overloaded();
+                if let Some(reason) = overload.sojourn(field, pct, us) {
+                    tracing::error!(endpoint = field, sojourn = %us, pct, "high sojourn latency");
+                    on_overloaded(&reason);
                 }
             }
         }
@@ -172,6 +278,7 @@ pub(crate) async fn run(
         Ok::<_, Report>(stderr)
     });
 
+    let mut crashed = None;
     tokio::select! {
         r = fin => {
             let _ = r?;
@@ -179,15 +286,26 @@ pub(crate) async fn run(
                 return Ok(());
             }
         }
+        reason = watchdog.crashed() => {
+            let reason = reason?;
+            tracing::error!(%reason, "server crashed -- stopping client");
+            crate::watchdog::stop_clients(&[c], client_process)
+                .await
+                .wrap_err("failed to stop client")?;
+            on_overloaded(&format!("server crashed: {}", reason));
+            crashed = Some(reason);
+        }
         _ = exit.recv() => {
             tracing::warn!("exiting benchmark early as requested");
             return Ok(());
         }
     };
 
+    manifest.measured = measurement.map(|measurement| measurement.finish(converged));
+
     if target.is_none() || actual.is_none() {
         tracing::warn!("missing throughput line, probably overloaded");
-        on_overloaded();
+        on_overloaded("missing throughput line");
     }
 
     tracing::trace!("gathering stderr");
@@ -196,10 +314,60 @@ pub(crate) async fn run(
     let status = bench.wait().await?;
     if !status.success() {
         tracing::warn!("benchmark failed:\n{}", stderr);
-        on_overloaded();
+        on_overloaded("client failed");
+    }
+    if let (Some(measured), None) = (manifest.measured, &crashed) {
+        measured.check(status.success() && target.is_some() && actual.is_some())?;
+    }
+
+    tracing::trace!("saving network counters");
+    let file = network
+        .finish()
+        .await
+        .wrap_err("failed to save network counters")?;
+    manifest.files.push(file);
+
+    tracing::trace!("saving resource samples");
+    let (file, resources) = sampler
+        .finish()
+        .await
+        .wrap_err("failed to save resource samples")?;
+    manifest.files.extend(file);
+    manifest.server_peak_memory_kb = resources.peak_rss("server", server_process);
+    if let Some(profiler) = profiler {
+        manifest
+            .files
+            .extend(crate::profile::finish(profiler, prefix).await);
     }
 
     tracing::debug!("saving meta-info");
+    if let Some(warmup) = manifest.warmup {
+        results
+            .write_all(
+                format!(
+                    "# warm-up: {} windows, steady: {}\n",
+                    warmup.windows, warmup.steady
+                )
+                .as_bytes(),
+            )
+            .await?;
+    }
+    if let Some(measured) = manifest.measured {
+        results
+            .write_all(
+                format!(
+                    "# measured: {} windows, converged: {}, runtime: {}s\n",
+                    measured.windows, measured.converged, measured.runtime
+                )
+                .as_bytes(),
+            )
+            .await?;
+    }
+    if let Some(ref reason) = crashed {
+        results
+            .write_all(format!("# server crashed: {}\n", reason).as_bytes())
+            .await?;
+    }
     tracing::trace!("saving context");
     results
         .write_all(format!("# server type: {}\n", server_type).as_bytes())
@@ -207,6 +375,11 @@ pub(crate) async fn run(
     results
         .write_all(format!("# client type: {}\n", client_type).as_bytes())
         .await?;
+    if let Some(ref az) = manifest.az {
+        results
+            .write_all(format!("# availability zone: {}\n", az).as_bytes())
+            .await?;
+    }
     results
         .write_all(format!("# prime time: {}\n", prime_took.as_secs_f64()).as_bytes())
         .await?;
@@ -217,22 +390,19 @@ pub(crate) async fn run(
     results
         .write_all(format!("# commit: {}\n", commit).as_bytes())
         .await?;
+    manifest.commit = Some(commit);
     tracing::trace!("saving load metrics");
     let (sload1, sload5) = crate::load(s).await.wrap_err("failed to get server load")?;
     results
         .write_all(format!("# server load: {} {}\n", sload1, sload5).as_bytes())
         .await?;
-    if sload1 > 15.5 {
+    manifest.server_load = Some((sload1, sload5));
+    if let Some(reason) = overload.server_load(sload1) {
         tracing::warn!(%sload5, "high server load -- assuming overloaded");
-        on_overloaded();
+        on_overloaded(&reason);
     }
 
-    let vmrss_for = if let Backend::Noria = backend {
-        "noria-server"
-    } else {
-        "mysqld"
-    };
-    let vmrss = crate::server::vmrss_for(s, vmrss_for)
+    let vmrss = crate::server::vmrss_for(s, server_process)
         .await
         .wrap_err("failed to get server memory use");
     match vmrss {
@@ -240,31 +410,35 @@ pub(crate) async fn run(
             results
                 .write_all(format!("# server memory (kB): {}\n", vmrss).as_bytes())
                 .await?;
+            manifest.server_memory_kb = Some(vmrss);
+            if let Some(peak) = manifest.server_peak_memory_kb {
+                results
+                    .write_all(format!("# server peak memory (kB): {}\n", peak).as_bytes())
+                    .await?;
+            }
         }
         Err(e) => {
             // the server process probably crashed
             let _ = server
-                .ssh
+                .session
                 .check()
                 .await
                 .wrap_err("check after vmrss failure")?;
             // connection still good, so just mark this as bad and move on
             tracing::warn!("{:?}", e);
-            on_overloaded();
+            on_overloaded("server memory use unavailable");
         }
     }
     let (cload1, cload5) = crate::load(c).await.wrap_err("failed to get client load")?;
     results
         .write_all(format!("# client load: {} {}\n", cload1, cload5).as_bytes())
         .await?;
-    results.flush().await?;
-    drop(results);
+    manifest.client_load = Some((cload1, cload5));
 
     // only try to extract info about processes if things exited nicely
     if status.success() {
         tracing::trace!("saving histogram");
         let mut histogram = c
-            .sftp()
             .read_from("benchmark.hist")
             .await
             .wrap_err("failed to read remote histogram")?;
@@ -275,6 +449,7 @@ pub(crate) async fn run(
             .await
             .wrap_err("failed to save remote histogram")?;
         drop(results);
+        manifest.files.push(format!("{}.hist", prefix));
 
         if let Backend::Noria = backend {
             tracing::trace!("saving server stats");
@@ -286,24 +461,66 @@ pub(crate) async fn run(
                 .wrap_err("failed to save server stats")?;
             if timed_out {
                 tracing::warn!("timed out when fetching server stats");
-                on_overloaded();
+                on_overloaded("timed out fetching server stats");
             }
             results.flush().await?;
+            manifest.files.push(format!("{}-statistics.json", prefix));
         }
         tracing::debug!("all results saved");
     } else {
         tracing::debug!("partial results saved");
     }
 
-    Ok(())
+    tracing::trace!("saving verdict");
+    let overloaded = !overload_reasons.is_empty();
+    results
+        .write_all(format!("{}{}\n", crate::resume::VERDICT, overloaded).as_bytes())
+        .await?;
+    results.flush().await?;
+    drop(results);
+    manifest.files.insert(0, format!("{}.log", prefix));
+    // only complete once the server has stopped, but there is always one
+    manifest.files.push(crate::server::log_file(prefix));
+    manifest.crashed = crashed.clone();
+    manifest.finish(overload_reasons, overload).await?;
+
+    match crashed {
+        Some(reason) => Err(Report::new(crate::watchdog::Crashed(reason))),
+        None => Ok(()),
+    }
+}
+
+/// Run the client at the target load for the given number of seconds, and return its output.
+async fn warm_up(
+    c: &crate::host::Session,
+    server: &crate::host::Host,
+    scale: usize,
+    backend: Backend,
+    seconds: u64,
+) -> Result<String, Report> {
+    let window = lobsters_client(c, server, scale, backend)
+        .arg(format!("--runtime={}", seconds))
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .output()
+        .await
+        .wrap_err("failed to run warm-up window")?;
+    if !window.status.success() {
+        // the measurement will tell whether this is because the target is overloaded
+        tracing::warn!(
+            "warm-up window failed:\n{}",
+            String::from_utf8_lossy(&window.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&window.stdout).to_string())
 }
 
 fn lobsters_client<'c>(
-    ssh: &'c openssh::Session,
-    server: &'c tsunami::Machine<'c>,
+    ssh: &'c crate::host::Session,
+    server: &'c crate::host::Host,
     scale: usize,
     backend: Backend,
-) -> openssh::Command<'c> {
+) -> crate::host::Command<'c> {
     let mut cmd = match backend {
         Backend::Noria => {
             let mut cmd = crate::noria_bin(ssh, "lobsters-noria");
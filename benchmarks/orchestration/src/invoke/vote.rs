@@ -1,11 +1,32 @@
 use crate::Context;
 use color_eyre::{eyre::WrapErr, Report};
+use std::time::Instant;
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt},
     stream::StreamExt,
 };
 use tracing_futures::Instrument;
 
+/// How many articles the vote clients populate the database with.
+pub(crate) const ARTICLES: usize = 10_000_000;
+
+/// The key distribution the vote clients draw from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Distribution {
+    Uniform,
+    Skewed,
+}
+
+impl Distribution {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match *self {
+            Distribution::Uniform => "uniform",
+            Distribution::Skewed => "skewed",
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub(crate) enum Backend {
     Netsoup { join: bool },
@@ -13,34 +34,113 @@ pub(crate) enum Backend {
     Hybrid,
 }
 
+impl Backend {
+    /// The processes that make up the server, the one that serves reads first.
+    fn processes(self) -> &'static [&'static str] {
+        match self {
+            Backend::Netsoup { .. } => &["noria-server"],
+            Backend::Redis => &["redis-server"],
+            Backend::Hybrid => &["redis-server", "mysqld"],
+        }
+    }
+}
+
+/// Check whether `run` already produced complete results for the given prefix.
+///
+/// See [`crate::resume::completed`].
+pub(crate) fn completed(
+    prefix: &str,
+    target: usize,
+    nclients: usize,
+    backend: Backend,
+) -> Option<bool> {
+    let histograms: Vec<_> = (0..nclients)
+        .map(|clienti| format!("{}-client{}.hist", prefix, clienti))
+        .collect();
+    let statistics = matches!(backend, Backend::Netsoup { .. });
+    // each client reports the throughput it generated on its own
+    let target_per_client = (target as f64 / nclients as f64).ceil();
+    crate::resume::completed(
+        prefix,
+        &histograms,
+        statistics,
+        crate::overload::OverloadPolicy::vote(),
+        Some(target_per_client),
+    )
+}
+
 pub(crate) async fn run(
     prefix: &str,
+    parameters: serde_json::Value,
     target: usize,
     distribution: &str,
     write_every: usize,
     mut on_overloaded: impl FnMut(),
-    cs: &[&openssh::Session],
-    server: &tsunami::Machine<'_>,
+    cs: &[&crate::host::Session],
+    server: &crate::host::Host,
     backend: Backend,
     ctx: &mut Context,
 ) -> Result<(), Report> {
+    let mut manifest = crate::manifest::Manifest::new(prefix, parameters, ctx);
+    let mut overload = crate::overload::Detector::new(ctx, crate::overload::OverloadPolicy::vote());
     let Context {
         ref server_type,
         ref client_type,
         ref mut exit,
+        sample_clients,
+        profile,
+        steady_state,
         ..
     } = *ctx;
 
-    let s = &server.ssh;
+    // remember why we overloaded, so we can record it along with the results
+    let mut overload_reasons = Vec::new();
+    let mut on_overloaded = |reason: &str| {
+        overload_reasons.push(reason.to_string());
+        on_overloaded();
+    };
+
+    let s = &server.session;
     let target_per_client = (target as f64 / cs.len() as f64).ceil() as usize;
 
+    tracing::debug!("start sampling resource use");
+    let mut sampler = crate::sampler::Sampler::new(prefix);
+    for &process in backend.processes() {
+        sampler
+            .watch("server", s, process)
+            .await
+            .wrap_err("failed to sample server")?;
+    }
+    if sample_clients {
+        for (clienti, &c) in cs.iter().enumerate() {
+            sampler
+                .watch(&format!("client{}", clienti), c, "vote")
+                .await
+                .wrap_err("failed to sample client")?;
+        }
+    }
+
+    // without a client that can measure adaptively, fall back to the fixed durations
+    let steady_state = if steady_state.is_some()
+        && !crate::steady::client_supports(crate::noria_bin(cs[0], "vote")).await?
+    {
+        tracing::warn!("vote client cannot report on windows, so not measuring adaptively");
+        None
+    } else {
+        steady_state
+    };
+
     tracing::debug!("prime");
+    // in adaptive mode, warming up happens at the target load instead
+    let prime_runtime = if steady_state.is_some() { 0 } else { 60 };
+    // this includes the time spent warming, if any
+    let prime_start = Instant::now();
     let prime = vote_client(cs[0], server, backend, |cmd| {
-        cmd.arg("--runtime=60")
+        cmd.arg(format!("--runtime={}", prime_runtime))
             .arg("--target=500000") // also warm a bit
             .arg("-d")
             .arg(distribution)
-            .arg("--articles=10000000")
+            .arg(format!("--articles={}", ARTICLES))
             .arg("--write-every")
             .arg(write_every.to_string());
     })
@@ -48,35 +148,85 @@ pub(crate) async fn run(
     .output()
     .await
     .wrap_err("failed to prime")?;
+    let prime_took = prime_start.elapsed();
+    manifest.prime_time = Some(prime_took.as_secs_f64());
 
     if !prime.status.success() {
         tracing::warn!(
             "priming failed:\n{}",
             String::from_utf8_lossy(&prime.stderr)
         );
-        on_overloaded();
+        on_overloaded("priming failed");
+        let (file, _) = sampler.finish().await?;
+        manifest.files.extend(file);
+        manifest.finish(overload_reasons, overload).await?;
         return Ok(());
     }
 
-    tracing::trace!("priming succeeded");
+    tracing::trace!(time = ?prime_took, "priming succeeded");
 
     if *exit.borrow() {
         return Ok(());
     }
 
+    let mut network = crate::network::Network::new(prefix);
+    network
+        .watch("server", s)
+        .await
+        .wrap_err("failed to snapshot server network")?;
+    for (clienti, &c) in cs.iter().enumerate() {
+        network
+            .watch(&format!("client{}", clienti), c)
+            .await
+            .wrap_err("failed to snapshot client network")?;
+    }
+
+    let mut measurement = match steady_state {
+        Some(steady_state) => {
+            tracing::debug!("warm up");
+            let warmup = steady_state
+                .warm_up(|seconds| {
+                    Box::pin(warm_up(
+                        cs,
+                        server,
+                        backend,
+                        seconds,
+                        target_per_client,
+                        distribution,
+                        write_every,
+                    ))
+                })
+                .await
+                .wrap_err("failed to warm up")?;
+            manifest.warmup = Some(warmup);
+            Some(steady_state.measure(&warmup))
+        }
+        None => None,
+    };
+    let runtime = match steady_state {
+        Some(steady_state) => steady_state.client_args().to_vec(),
+        None => vec![String::from("--runtime=320")],
+    };
+
+    let watchdog = crate::watchdog::Watchdog::start(s, backend.processes())
+        .await
+        .wrap_err("failed to watch server")?;
+
     tracing::debug!("benchmark");
     let mut benches = cs
         .iter()
         .map(|c| {
             vote_client(c, server, backend, |cmd| {
-                cmd.arg("--no-prime")
-                    .arg("--runtime=320")
-                    .arg("--histogram=benchmark.hist")
+                cmd.arg("--no-prime");
+                for arg in &runtime {
+                    cmd.arg(arg);
+                }
+                cmd.arg("--histogram=benchmark.hist")
                     .arg("--target")
                     .arg(target_per_client.to_string())
                     .arg("-d")
                     .arg(distribution)
-                    .arg("--articles=10000000")
+                    .arg(format!("--articles={}", ARTICLES))
                     .arg("--write-every")
                     .arg(write_every.to_string());
             })
@@ -86,12 +236,17 @@ pub(crate) async fn run(
         })
         .collect::<Result<Vec<_>, _>>()
         .wrap_err("failed to start client")?;
+    let profiler = match profile {
+        Some(window) => crate::profile::start(s, backend.processes()[0], window).await,
+        None => None,
+    };
 
     tracing::trace!("saving client output");
     let results = tokio::fs::File::create(format!("{}.log", prefix));
     let results = results.await.wrap_err("failed to create local log file")?;
     let mut results = tokio::io::BufWriter::new(results);
     let mut got_lines = false;
+    let mut converged = false;
     let fin = async {
         for bench in &mut benches {
             let mut stdout = tokio::io::BufReader::new(bench.stdout().take().unwrap()).lines();
@@ -100,6 +255,16 @@ pub(crate) async fn run(
                 results.write_all(line.as_bytes()).await?;
                 results.write_all(b"\n").await?;
 
+                // the clients all run at the same load, so the first one speaks for all of them
+                if let Some(ref mut measurement) = measurement {
+                    if !converged && measurement.line(&line) {
+                        converged = true;
+                        crate::watchdog::finish_clients(cs, "vote")
+                            .await
+                            .wrap_err("failed to finish clients")?;
+                    }
+                }
+
                 if !line.starts_with('#') {
                     let mut fields = line.split_whitespace();
                     let field = fields.next().unwrap();
@@ -112,16 +277,14 @@ pub(crate) async fn run(
                         if let (Ok(pct), Ok(sjrn)) = (pct, sjrn) {
                             got_lines = true;
 
-                            if (field == "read" && pct == 95) || (field == "write" && pct == 50) {
-                                if sjrn > 20_000.0 {
-                                    tracing::error!(
-                                        endpoint = field,
-                                        latency = %sjrn,
-                                        pct,
-                                        "high sojourn latency"
-                                    );
-                                    on_overloaded();
-                                }
+                            if let Some(reason) = overload.sojourn(field, pct, sjrn) {
+                                tracing::error!(
+                                    endpoint = field,
+                                    latency = %sjrn,
+                                    pct,
+                                    "high sojourn latency"
+                                );
+                                on_overloaded(&reason);
                             }
                             continue;
                         }
@@ -131,9 +294,9 @@ pub(crate) async fn run(
                 {
                     let mut fields = line.split_whitespace();
                     let rate: f64 = fields.next_back().unwrap().parse().unwrap();
-                    if target_per_client as f64 - rate > 0.05 * target_per_client as f64 {
+                    if let Some(reason) = overload.throughput(rate, target_per_client as f64) {
                         tracing::error!(%rate, bar = %target_per_client, "low throughput");
-                        on_overloaded();
+                        on_overloaded(&reason);
                     }
                 }
             }
@@ -142,6 +305,7 @@ pub(crate) async fn run(
         Ok::<_, Report>(())
     };
 
+    let mut crashed = None;
     tokio::select! {
         r = fin => {
             let _ = r?;
@@ -149,15 +313,26 @@ pub(crate) async fn run(
                 return Ok(());
             }
         }
+        reason = watchdog.crashed() => {
+            let reason = reason?;
+            tracing::error!(%reason, "server crashed -- stopping clients");
+            crate::watchdog::stop_clients(cs, "vote")
+                .await
+                .wrap_err("failed to stop clients")?;
+            on_overloaded(&format!("server crashed: {}", reason));
+            crashed = Some(reason);
+        }
         _ = exit.recv() => {
             tracing::warn!("exiting benchmark early as requested");
             return Ok(());
         }
     };
 
+    manifest.measured = measurement.map(|measurement| measurement.finish(converged));
+
     if !got_lines {
         tracing::warn!("missing throughput line, probably overloaded");
-        on_overloaded();
+        on_overloaded("missing throughput line");
     }
 
     let mut all_ok = true;
@@ -174,35 +349,99 @@ pub(crate) async fn run(
         let status = bench.wait().await?;
         if !status.success() {
             tracing::warn!(client = clienti, "benchmark failed:\n{}", stderr);
-            on_overloaded();
+            on_overloaded(&format!("client {} failed", clienti));
             all_ok = false;
         }
         clients.push(status);
     }
     tracing::debug!("benchmark completed");
+    if let (Some(measured), None) = (manifest.measured, &crashed) {
+        measured.check(all_ok && got_lines)?;
+    }
+
+    tracing::trace!("saving network counters");
+    let file = network
+        .finish()
+        .await
+        .wrap_err("failed to save network counters")?;
+    manifest.files.push(file);
+
+    tracing::trace!("saving resource samples");
+    let (file, resources) = sampler
+        .finish()
+        .await
+        .wrap_err("failed to save resource samples")?;
+    manifest.files.extend(file);
+    manifest.server_peak_memory_kb = resources.peak_rss("server", backend.processes()[0]);
+    if let Some(profiler) = profiler {
+        manifest
+            .files
+            .extend(crate::profile::finish(profiler, prefix).await);
+    }
 
     tracing::debug!("saving meta-info");
+    if let Some(warmup) = manifest.warmup {
+        results
+            .write_all(
+                format!(
+                    "# warm-up: {} windows, steady: {}\n",
+                    warmup.windows, warmup.steady
+                )
+                .as_bytes(),
+            )
+            .await?;
+    }
+    if let Some(measured) = manifest.measured {
+        results
+            .write_all(
+                format!(
+                    "# measured: {} windows, converged: {}, runtime: {}s\n",
+                    measured.windows, measured.converged, measured.runtime
+                )
+                .as_bytes(),
+            )
+            .await?;
+    }
+    if let Some(ref reason) = crashed {
+        results
+            .write_all(format!("# server crashed: {}\n", reason).as_bytes())
+            .await?;
+    }
     tracing::trace!("saving context");
+    // like lobsters, give the target each client was held against
+    results
+        .write_all(format!("# target ops/s: {}\n", target_per_client).as_bytes())
+        .await?;
     results
         .write_all(format!("# server type: {}\n", server_type).as_bytes())
         .await?;
     results
         .write_all(format!("# client type: {}\n", client_type).as_bytes())
         .await?;
+    if let Some(ref az) = manifest.az {
+        results
+            .write_all(format!("# availability zone: {}\n", az).as_bytes())
+            .await?;
+    }
+    results
+        .write_all(format!("# prime time: {}\n", prime_took.as_secs_f64()).as_bytes())
+        .await?;
     let commit = crate::noria_commit(s)
         .await
         .wrap_err("failed to get noria commit")?;
     results
         .write_all(format!("# commit: {}\n", commit).as_bytes())
         .await?;
+    manifest.commit = Some(commit);
     tracing::trace!("saving load metrics");
     let (sload1, sload5) = crate::load(s).await.wrap_err("failed to get server load")?;
     results
         .write_all(format!("# server load: {} {}\n", sload1, sload5).as_bytes())
         .await?;
-    if sload1 > 15.5 {
+    manifest.server_load = Some((sload1, sload5));
+    if let Some(reason) = overload.server_load(sload1) {
         tracing::warn!(%sload5, "high server load -- assuming overloaded");
-        on_overloaded();
+        on_overloaded(&reason);
     }
 
     let vmrss_for = match backend {
@@ -215,6 +454,7 @@ pub(crate) async fn run(
             results
                 .write_all(format!("# backend memory (kB): {}\n", vmrss).as_bytes())
                 .await?;
+            manifest.backend_memory_kb = Some(vmrss);
             "redis-server"
         }
     };
@@ -226,17 +466,23 @@ pub(crate) async fn run(
             results
                 .write_all(format!("# server memory (kB): {}\n", vmrss).as_bytes())
                 .await?;
+            manifest.server_memory_kb = Some(vmrss);
+            if let Some(peak) = manifest.server_peak_memory_kb {
+                results
+                    .write_all(format!("# server peak memory (kB): {}\n", peak).as_bytes())
+                    .await?;
+            }
         }
         Err(e) => {
             // the server process probably crashed
             let _ = server
-                .ssh
+                .session
                 .check()
                 .await
                 .wrap_err("check after vmrss failure")?;
             // connection still good, so just mark this as bad and move on
             tracing::warn!("{:?}", e);
-            on_overloaded();
+            on_overloaded("server memory use unavailable");
         }
     }
 
@@ -246,8 +492,7 @@ pub(crate) async fn run(
     results
         .write_all(format!("# client[0] load: {} {}\n", cload1, cload5).as_bytes())
         .await?;
-    results.flush().await?;
-    drop(results);
+    manifest.client_load = Some((cload1, cload5));
 
     tracing::trace!("saving histograms");
     for (clienti, &c) in cs.iter().enumerate() {
@@ -261,17 +506,18 @@ pub(crate) async fn run(
         async {
             tracing::trace!("saving histogram");
             let mut histogram = c
-                .sftp()
                 .read_from("benchmark.hist")
                 .await
                 .wrap_err("failed to read remote histogram")?;
-            let mut results = tokio::fs::File::create(format!("{}-client{}.hist", prefix, clienti))
+            let file = format!("{}-client{}.hist", prefix, clienti);
+            let mut results = tokio::fs::File::create(&file)
                 .await
                 .wrap_err("failed to create local histogram copy")?;
             tokio::io::copy(&mut histogram, &mut results)
                 .await
                 .wrap_err("failed to save remote histogram")?;
             drop(results);
+            manifest.files.push(file);
             Ok::<_, Report>(())
         }
         .instrument(client_span)
@@ -289,24 +535,90 @@ pub(crate) async fn run(
                 .wrap_err("failed to save server stats")?;
             if timed_out {
                 tracing::warn!("timed out when fetching server stats");
-                on_overloaded();
+                on_overloaded("timed out fetching server stats");
             }
             results.flush().await?;
+            manifest.files.push(format!("{}-statistics.json", prefix));
         }
         tracing::debug!("all results saved");
     } else {
         tracing::debug!("partial results saved");
     }
 
-    Ok(())
+    tracing::trace!("saving verdict");
+    let overloaded = !overload_reasons.is_empty();
+    results
+        .write_all(format!("{}{}\n", crate::resume::VERDICT, overloaded).as_bytes())
+        .await?;
+    results.flush().await?;
+    drop(results);
+    manifest.files.insert(0, format!("{}.log", prefix));
+    // only complete once the server has stopped, but there is always one
+    manifest.files.push(crate::server::log_file(prefix));
+    manifest.crashed = crashed.clone();
+    manifest.finish(overload_reasons, overload).await?;
+
+    match crashed {
+        Some(reason) => Err(Report::new(crate::watchdog::Crashed(reason))),
+        None => Ok(()),
+    }
+}
+
+/// Run the clients at the target load for the given number of seconds, and return their output.
+async fn warm_up(
+    cs: &[&crate::host::Session],
+    server: &crate::host::Host,
+    backend: Backend,
+    seconds: u64,
+    target_per_client: usize,
+    distribution: &str,
+    write_every: usize,
+) -> Result<String, Report> {
+    let windows = cs.iter().map(|c| async move {
+        let mut client = vote_client(c, server, backend, |cmd| {
+            cmd.arg("--no-prime")
+                .arg(format!("--runtime={}", seconds))
+                .arg("--target")
+                .arg(target_per_client.to_string())
+                .arg("-d")
+                .arg(distribution)
+                .arg(format!("--articles={}", ARTICLES))
+                .arg("--write-every")
+                .arg(write_every.to_string());
+        });
+        client
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .output()
+            .await
+    });
+
+    let mut output = String::new();
+    for (clienti, window) in futures_util::future::try_join_all(windows)
+        .await
+        .wrap_err("failed to run warm-up window")?
+        .into_iter()
+        .enumerate()
+    {
+        if !window.status.success() {
+            // the measurement will tell whether this is because the target is overloaded
+            tracing::warn!(
+                client = clienti,
+                "warm-up window failed:\n{}",
+                String::from_utf8_lossy(&window.stderr)
+            );
+        }
+        output.push_str(&String::from_utf8_lossy(&window.stdout));
+    }
+    Ok(output)
 }
 
 fn vote_client<'c>(
-    ssh: &'c openssh::Session,
-    server: &'c tsunami::Machine<'c>,
+    ssh: &'c crate::host::Session,
+    server: &'c crate::host::Host,
     backend: Backend,
-    add_args: impl FnOnce(&mut openssh::Command<'_>),
-) -> openssh::Command<'c> {
+    add_args: impl FnOnce(&mut crate::host::Command<'_>),
+) -> crate::host::Command<'c> {
     let mut cmd = crate::noria_bin(ssh, "vote");
     // vote args need to go _before_ the backend arguments
     add_args(&mut cmd);
@@ -1,241 +1,296 @@
+use crate::experiment::{Experiment, Machines};
+use crate::host::Child;
+use crate::role::Role;
+use crate::spec::Search;
 use crate::Context;
 use color_eyre::{eyre, eyre::WrapErr, Report};
+use futures_util::future::BoxFuture;
+use result_key::ResultKey;
 use tokio::io::AsyncWriteExt;
 use tracing::instrument;
 use tracing_futures::Instrument;
-use tsunami::providers::aws;
-use tsunami::Tsunami;
 
 const MYSQL_CONFIG: &str = "\
 transaction_isolation = 'READ-UNCOMMITTED'
 max_prepared_stmt_count = 131056
 ";
 
+/// The parameters of a single lobsters-mysql experiment, as given in a spec file.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Parameters {
+    optimized: bool,
+}
+
+impl Parameters {
+    fn into_tuple(self) -> (bool,) {
+        (self.optimized,)
+    }
+}
+
+/// The experiments to run, from the spec if it has any.
+fn experiments(ctx: &Context) -> Result<Vec<((bool,), crate::spec::Plan)>, Report> {
+    if let Some(experiments) =
+        crate::spec::experiments(ctx, "lobsters-mysql", Parameters::into_tuple)?
+    {
+        return Ok(experiments);
+    }
+
+    Ok(vec![(true,), (false,)]
+        .into_iter()
+        .map(|parameters| (parameters, crate::spec::Plan::default()))
+        .collect())
+}
+
+/// The search to use for the given parameters, unless the spec says otherwise.
+fn search(parameters: (bool,)) -> Search {
+    let (optimized,) = parameters;
+    if !optimized {
+        Search::Exponential {
+            start: 1,
+            min_width: 2,
+            fill_left: false,
+        }
+    } else {
+        Search::Exponential {
+            start: 32,
+            min_width: 32,
+            fill_left: false,
+        }
+    }
+}
+
 /// lobsters-mysql; requires two machines: a client and a server
 #[instrument(name = "lobsters-mysql", skip(ctx))]
 pub(crate) async fn main(ctx: Context) -> Result<(), Report> {
-    crate::explore!([(true,), (false,),], one, ctx, false)
+    crate::explore!(@plans experiments(&ctx)?, one, ctx)
 }
 
-#[instrument(err, skip(ctx))]
-pub(crate) async fn one(
-    parameters: (bool,),
-    loads: Option<Vec<usize>>,
-    mut ctx: Context,
-) -> Result<usize, Report> {
-    let (optimized,) = parameters;
-    let mut last_good_scale = 0;
-
-    let mut aws = crate::launcher();
-    aws.set_mode(aws::LaunchMode::on_demand());
-
-    // try to ensure we do AWS cleanup
-    let result: Result<_, Report> = try {
-        tracing::info!("spinning up aws instances");
-
-        fn s_setup<'r>(
-            s: &'r tsunami::Machine<'_>,
-        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Report>> + Send + 'r>>
-        {
-            Box::pin(
-                async move {
-                    tracing::debug!("stop mysql (if running)");
-                    crate::output_on_success(s.ssh.shell("sudo systemctl stop mariadb"))
-                        .await
-                        .wrap_err("stop mariadb")?;
-
-                    tracing::debug!("mount mysql ramdisk");
-                    crate::output_on_success(
-                        s.ssh
-                            .shell("sudo mount -t tmpfs -o size=60G tmpfs /var/lib/mysql"),
-                    )
-                    .await
-                    .wrap_err("mount ramdisk")?;
-
-                    tracing::debug!("install mysql configuration");
-                    let mut config = String::from("[mysqld]\n");
-                    config.push_str(MYSQL_CONFIG);
-                    config.push_str("\n");
-                    config.push_str(&format!(
-                        "max-connections = {}\n",
-                        crate::invoke::lobsters::IN_FLIGHT
-                    ));
-                    config.push_str(&format!(
-                        "bind-address = {}\n",
-                        s.private_ip.as_ref().expect("no private ip address?")
-                    ));
-                    let mut cmd = s
-                        .ssh
-                        .shell("sudo tee /etc/mysql/mariadb.conf.d/99-noria.cnf")
-                        .stdout(std::process::Stdio::null())
-                        .stdin(std::process::Stdio::piped())
-                        .spawn()
-                        .wrap_err("tee .cnf")?;
-                    cmd.stdin()
-                        .take()
-                        .expect("set to piped above")
-                        .write_all(config.as_bytes())
-                        .await
-                        .wrap_err("write .cnf")?;
-
-                    let proc = cmd.wait_with_output().await.wrap_err("execute tee .cnf")?;
-                    if !proc.status.success() {
-                        Err(
-                            eyre::eyre!(String::from_utf8_lossy(&proc.stderr).to_string())
-                                .wrap_err("execute tee .cnf failed"),
-                        )?;
-                    }
-
-                    tracing::debug!("install mysql main dbs");
-                    crate::output_on_success(
-                        s.ssh
-                            .shell("sudo mysql_install_db --user=mysql --datadir=/var/lib/mysql"),
-                    )
-                    .await
-                    .wrap_err("mysql_install_db")?;
-
-                    tracing::debug!("start mysql");
-                    crate::output_on_success(s.ssh.shell("sudo systemctl start mariadb"))
-                        .await
-                        .wrap_err("start mariadb")?;
-
-                    tracing::debug!("make lobsters user");
-                    crate::output_on_success(
-                        s.ssh.shell("sudo mysql -e \"CREATE USER 'lobsters'\""),
-                    )
-                    .await
-                    .wrap_err("create user")?;
-                    tracing::trace!("grant all permissions");
-                    crate::output_on_success(s.ssh.shell(
-                        "sudo mysql -e \"GRANT ALL PRIVILEGES ON * . * TO 'lobsters'@'%';\"",
-                    ))
-                    .await
-                    .wrap_err("grant all")?;
-                    crate::output_on_success(s.ssh.shell("sudo mysql -e \"FLUSH PRIVILEGES\""))
-                        .await
-                        .wrap_err("flush privileges")?;
-
-                    tracing::trace!("testing mysql setup");
-                    crate::output_on_success(s.ssh.shell(&format!(
-                        "mysql --protocol=TCP --user=lobsters --host={} -e \"SELECT 1\"",
-                        s.private_ip.as_ref().unwrap()
-                    )))
-                    .await
-                    .wrap_err("test mysql connection")?;
-
-                    Ok(())
-                }
-                .in_current_span(),
-            )
-        }
+/// Describe what `main` would run.
+pub(crate) fn dry_run(ctx: &Context) -> Result<Vec<crate::dry_run::Experiment>, Report> {
+    Ok(experiments(ctx)?
+        .into_iter()
+        .map(|(parameters, plan)| {
+            let ctx = plan.context(ctx);
+            let search = plan.search_or(|| search(parameters));
+            crate::dry_run::Experiment::searching(&parameters, &search)
+                .repeated(&plan.repetitions)
+                .machine(
+                    "backend",
+                    crate::role::resolve(&ctx, "backend", Role::new(&ctx.server_type))
+                        .instance_type(),
+                    1,
+                )
+                .machine("client", &ctx.client_type, 1)
+        })
+        .collect())
+}
 
-        aws.spawn(
-            vec![
-                (
-                    String::from("server"),
-                    aws::Setup::default()
-                        .instance_type(&ctx.server_type)
-                        .ami(crate::AMI, "ubuntu")
-                        .availability_zone(ctx.az.clone())
-                        .setup(s_setup),
-                ),
-                (
-                    String::from("client"),
-                    aws::Setup::default()
-                        .instance_type(&ctx.client_type)
-                        .ami(crate::AMI, "ubuntu")
-                        .availability_zone(ctx.az.clone())
-                        .setup(crate::noria_setup("noria-applications", "lobsters-mysql")),
-                ),
-            ],
-            None,
-        )
-        .await
-        .wrap_err("failed to start instances")?;
-
-        tracing::debug!("connecting");
-        let vms = aws.connect_all().await?;
-        let server = vms.get("server").unwrap();
-        let client = vms.get("client").unwrap();
-        let c = &client.ssh;
-        tracing::debug!("connected");
-
-        let mut scales = if let Some(loads) = loads {
-            Box::new(cliff::LoadIterator::from(loads)) as Box<dyn cliff::CliffSearch + Send>
-        } else if !optimized {
-            Box::new(cliff::ExponentialCliffSearcher::until(1, 2))
-        } else {
-            Box::new(cliff::ExponentialCliffSearcher::until(32, 32))
-        };
-        let result: Result<(), Report> = try {
-            let mut successful_scale = None;
-            while let Some(scale) = scales.next() {
-                if let Some(scale) = successful_scale.take() {
-                    // last run succeeded at the given scale
-                    last_good_scale = scale;
-                }
-                successful_scale = Some(scale);
-
-                if *ctx.exit.borrow() {
-                    tracing::info!("exiting as instructed");
-                    break;
-                }
-
-                let scale_span = tracing::info_span!("scale", scale);
-                async {
-                    tracing::info!("start benchmark target");
-                    let mut backend = String::from("mysql");
-                    if !optimized {
-                        backend.push_str("_noopt");
-                    }
-                    let prefix = format!("lobsters-{}-{}-0m", backend, scale);
-
-                    // no need to start anything here -- we start MariaDB in setup
-                    // and then the priming takes care of dropping/creating the DB.
-                    crate::invoke::lobsters::run(
-                        &prefix,
-                        scale,
-                        || {
-                            scales.overloaded();
-                            successful_scale.take();
-                        },
-                        c,
-                        &server,
-                        crate::invoke::lobsters::Backend::Mysql { optimized },
-                        &mut ctx,
-                    )
-                    .await?;
-
-                    // no need to stop anything either, for the same reason
-
-                    Ok::<_, Report>(())
-                }
-                .instrument(scale_span)
-                .await?;
-            }
-        };
-
-        tracing::debug!("cleaning up");
-        tracing::trace!("cleaning up ssh connections");
-        for (name, host) in vms {
-            let host_span = tracing::trace_span!("ssh_close", name = &*name);
-            async {
-                tracing::trace!("closing connection");
-                if let Err(e) = host.ssh.close().await {
-                    tracing::warn!("ssh connection failed: {}", e);
-                }
+fn s_setup<'r>(
+    s: &'r tsunami::Machine<'_>,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Report>> + Send + 'r>> {
+    use crate::host::Command;
+
+    Box::pin(
+        async move {
+            tracing::debug!("stop mysql (if running)");
+            crate::output_on_success(Command::from(s.ssh.shell("sudo systemctl stop mariadb")))
+                .await
+                .wrap_err("stop mariadb")?;
+
+            tracing::debug!("mount mysql ramdisk");
+            crate::output_on_success(Command::from(
+                s.ssh
+                    .shell("sudo mount -t tmpfs -o size=60G tmpfs /var/lib/mysql"),
+            ))
+            .await
+            .wrap_err("mount ramdisk")?;
+
+            tracing::debug!("install mysql configuration");
+            let mut config = String::from("[mysqld]\n");
+            config.push_str(MYSQL_CONFIG);
+            config.push_str("\n");
+            config.push_str(&format!(
+                "max-connections = {}\n",
+                crate::invoke::lobsters::IN_FLIGHT
+            ));
+            config.push_str(&format!(
+                "bind-address = {}\n",
+                s.private_ip.as_ref().expect("no private ip address?")
+            ));
+            let mut cmd = s
+                .ssh
+                .shell("sudo tee /etc/mysql/mariadb.conf.d/99-noria.cnf")
+                .stdout(std::process::Stdio::null())
+                .stdin(std::process::Stdio::piped())
+                .spawn()
+                .wrap_err("tee .cnf")?;
+            cmd.stdin()
+                .take()
+                .expect("set to piped above")
+                .write_all(config.as_bytes())
+                .await
+                .wrap_err("write .cnf")?;
+
+            let proc = cmd.wait_with_output().await.wrap_err("execute tee .cnf")?;
+            if !proc.status.success() {
+                Err(
+                    eyre::eyre!(String::from_utf8_lossy(&proc.stderr).to_string())
+                        .wrap_err("execute tee .cnf failed"),
+                )?;
             }
-            .instrument(host_span)
+
+            tracing::debug!("install mysql main dbs");
+            crate::output_on_success(Command::from(
+                s.ssh
+                    .shell("sudo mysql_install_db --user=mysql --datadir=/var/lib/mysql"),
+            ))
+            .await
+            .wrap_err("mysql_install_db")?;
+
+            tracing::debug!("start mysql");
+            crate::output_on_success(Command::from(s.ssh.shell("sudo systemctl start mariadb")))
+                .await
+                .wrap_err("start mariadb")?;
+
+            tracing::debug!("make lobsters user");
+            crate::output_on_success(Command::from(
+                s.ssh.shell("sudo mysql -e \"CREATE USER 'lobsters'\""),
+            ))
+            .await
+            .wrap_err("create user")?;
+            tracing::trace!("grant all permissions");
+            crate::output_on_success(Command::from(
+                s.ssh
+                    .shell("sudo mysql -e \"GRANT ALL PRIVILEGES ON * . * TO 'lobsters'@'%';\""),
+            ))
+            .await
+            .wrap_err("grant all")?;
+            crate::output_on_success(Command::from(
+                s.ssh.shell("sudo mysql -e \"FLUSH PRIVILEGES\""),
+            ))
             .await
+            .wrap_err("flush privileges")?;
+
+            tracing::trace!("testing mysql setup");
+            crate::output_on_success(Command::from(s.ssh.shell(&format!(
+                "mysql --protocol=TCP --user=lobsters --host={} -e \"SELECT 1\"",
+                s.private_ip.as_ref().unwrap()
+            ))))
+            .await
+            .wrap_err("test mysql connection")?;
+
+            Ok(())
         }
+        .in_current_span(),
+    )
+}
+
+struct LobstersMysql((bool,));
+
+impl LobstersMysql {
+    fn backend(&self) -> crate::invoke::lobsters::Backend {
+        let (optimized,) = self.0;
+        crate::invoke::lobsters::Backend::Mysql { optimized }
+    }
+}
+
+impl Experiment for LobstersMysql {
+    const NAME: &'static str = "lobsters-mysql";
+
+    fn parameters(&self) -> &(dyn std::fmt::Debug + Sync) {
+        &self.0
+    }
+
+    fn search(&self) -> Search {
+        search(self.0)
+    }
 
-        result?
-    };
+    fn machines(&self, ctx: &Context) -> Vec<(String, crate::provider::Setup)> {
+        vec![
+            (
+                String::from("server"),
+                crate::role::setup(ctx, "backend", Role::new(&ctx.server_type)).setup(s_setup),
+            ),
+            (
+                String::from("client"),
+                crate::role::setup(ctx, "client", Role::new(&ctx.client_type))
+                    .setup(crate::noria_setup("noria-applications", "lobsters-mysql")),
+            ),
+        ]
+    }
 
-    tracing::trace!("cleaning up instances");
-    let cleanup = aws.terminate_all().await;
-    tracing::debug!("done");
-    let _ = result?;
-    let _ = cleanup.wrap_err("cleanup failed")?;
-    Ok(last_good_scale)
+    fn results(&self) -> &'static str {
+        "lobsters-mysql"
+    }
+
+    fn prefix(&self, scale: usize) -> String {
+        let (optimized,) = self.0;
+        ResultKey::LobstersMysql {
+            optimized,
+            scale,
+            memlimit: 0,
+        }
+        .to_string()
+    }
+
+    fn completed(&self, _: usize, prefix: &str) -> Option<bool> {
+        crate::invoke::lobsters::completed(prefix, self.backend())
+    }
+
+    fn start<'a>(
+        &'a self,
+        _: usize,
+        _: &'a Machines,
+    ) -> BoxFuture<'a, Result<Option<Child<'a>>, Report>> {
+        // no need to start anything here -- we start MariaDB in setup
+        // and then the priming takes care of dropping/creating the DB.
+        Box::pin(async { Ok(None) })
+    }
+
+    fn invoke<'a>(
+        &'a self,
+        scale: usize,
+        prefix: &'a str,
+        on_overloaded: &'a mut (dyn FnMut() + Send),
+        machines: &'a Machines,
+        ctx: &'a mut Context,
+    ) -> BoxFuture<'a, Result<(), Report>> {
+        let (optimized,) = self.0;
+        Box::pin(crate::invoke::lobsters::run(
+            prefix,
+            serde_json::json!({
+                "scale": scale,
+                "optimized": optimized,
+            }),
+            scale,
+            on_overloaded,
+            &machines.get("client").session,
+            machines.get("server"),
+            self.backend(),
+            ctx,
+        ))
+    }
+
+    fn services(&self) -> &'static [&'static str] {
+        &["mariadb"]
+    }
+
+    fn stop<'a>(
+        &'a self,
+        _: Option<Child<'a>>,
+        _: &'a Machines,
+    ) -> BoxFuture<'a, Result<(), Report>> {
+        // no need to stop anything either, for the same reason
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[instrument(err, skip(ctx))]
+pub(crate) async fn one(
+    parameters: (bool,),
+    plan: crate::spec::Plan,
+    ctx: Context,
+) -> Result<usize, Report> {
+    crate::experiment::run(LobstersMysql(parameters), plan, ctx).await
 }
@@ -1,228 +1,263 @@
+use crate::experiment::{Experiment, Machines};
+use crate::host::Child;
+use crate::role::Role;
+use crate::spec::Search;
 use crate::Context;
 use color_eyre::{eyre::WrapErr, Report};
+use futures_util::future::BoxFuture;
+use result_key::ResultKey;
 use tracing::instrument;
 use tracing_futures::Instrument;
-use tsunami::providers::aws;
-use tsunami::Tsunami;
+
+/// The parameters of a single lobsters-noria experiment, as given in a spec file.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Parameters {
+    shards: usize,
+    partial: bool,
+    memlimit: usize,
+    durable: bool,
+}
+
+impl Parameters {
+    fn into_tuple(self) -> (usize, bool, usize, bool) {
+        (self.shards, self.partial, self.memlimit, self.durable)
+    }
+}
+
+/// The experiments to run, from the spec if it has any.
+fn experiments(
+    ctx: &Context,
+) -> Result<Vec<((usize, bool, usize, bool), crate::spec::Plan)>, Report> {
+    if let Some(experiments) =
+        crate::spec::experiments(ctx, "lobsters-noria", Parameters::into_tuple)?
+    {
+        return Ok(experiments);
+    }
+
+    Ok(vec![
+        (0, false, 0, false),
+        // (0, true, 0, false),
+        (0, true, 128 * 1024 * 1024, false),
+        (0, true, 256 * 1024 * 1024, false),
+        (0, true, 384 * 1024 * 1024, false),
+        // (0, true, 0, true),
+        (0, true, 128 * 1024 * 1024, true),
+        (0, true, 256 * 1024 * 1024, true),
+        (0, false, 0, true),
+    ]
+    .into_iter()
+    .map(|parameters| (parameters, crate::spec::Plan::default()))
+    .collect())
+}
+
+/// The search to use for the given parameters, unless the spec says otherwise.
+fn search(parameters: (usize, bool, usize, bool)) -> Search {
+    let (_, partial, _, durable) = parameters;
+    if durable && partial {
+        // we don't normally run non-durable partial @ 6k scale, so run that too (6001)
+        Search::Loads(vec![2000, 6000, 6001])
+    } else if durable {
+        Search::Loads(vec![6000])
+    } else {
+        Search::Exponential {
+            start: 2000,
+            min_width: 250,
+            fill_left: false,
+        }
+    }
+}
 
 /// lobsters-noria; requires two machines: a client and a server
 #[instrument(name = "lobsters-noria", skip(ctx))]
 pub(crate) async fn main(ctx: Context) -> Result<(), Report> {
-    crate::explore!(
-        [
-            (0, false, 0, false),
-            // (0, true, 0, false),
-            (0, true, 128 * 1024 * 1024, false),
-            (0, true, 256 * 1024 * 1024, false),
-            (0, true, 384 * 1024 * 1024, false),
-            // (0, true, 0, true),
-            (0, true, 128 * 1024 * 1024, true),
-            (0, true, 256 * 1024 * 1024, true),
-            (0, false, 0, true),
-        ],
-        one,
-        ctx,
-        false
-    )
+    crate::explore!(@plans experiments(&ctx)?, one, ctx)
 }
 
-#[instrument(err, skip(ctx))]
-pub(crate) async fn one(
+/// Describe what `main` would run.
+pub(crate) fn dry_run(ctx: &Context) -> Result<Vec<crate::dry_run::Experiment>, Report> {
+    Ok(experiments(ctx)?
+        .into_iter()
+        .map(|(parameters, plan)| {
+            let ctx = plan.context(ctx);
+            let search = plan.search_or(|| search(parameters));
+            crate::dry_run::Experiment::searching(&parameters, &search)
+                .repeated(&plan.repetitions)
+                .machine("server", &ctx.server_type, 1)
+                .machine("client", &ctx.client_type, 1)
+        })
+        .collect())
+}
+
+struct LobstersNoria {
     parameters: (usize, bool, usize, bool),
-    loads: Option<Vec<usize>>,
-    mut ctx: Context,
-) -> Result<usize, Report> {
-    let (nshards, partial, memlimit, mut durable) = parameters;
-    let mut last_good_scale = 0;
-
-    let mut aws = crate::launcher();
-    aws.set_mode(aws::LaunchMode::on_demand());
-
-    // try to ensure we do AWS cleanup
-    let result: Result<_, Report> = try {
-        tracing::info!("spinning up aws instances");
-
-        aws.spawn(
-            vec![
-                (
-                    String::from("server"),
-                    aws::Setup::default()
-                        .instance_type(&ctx.server_type)
-                        .ami(crate::AMI, "ubuntu")
-                        .availability_zone(ctx.az.clone())
-                        .setup(crate::noria_setup("noria-server", "noria-server")),
-                ),
-                (
-                    String::from("client"),
-                    aws::Setup::default()
-                        .instance_type(&ctx.client_type)
-                        .ami(crate::AMI, "ubuntu")
-                        .availability_zone(ctx.az.clone())
-                        .setup(crate::noria_setup("noria-applications", "lobsters-noria")),
-                ),
-            ],
-            None,
-        )
-        .await
-        .wrap_err("failed to start instances")?;
-
-        tracing::debug!("connecting");
-        let vms = aws.connect_all().await?;
-        let server = vms.get("server").unwrap();
-        let client = vms.get("client").unwrap();
-        let s = &server.ssh;
-        let c = &client.ssh;
-        tracing::debug!("connected");
-
-        if durable {
-            tracing::debug!("mount ramdisk");
-            crate::output_on_success(s.shell("sudo mount -t tmpfs -o size=60G tmpfs /mnt"))
-                .await
-                .wrap_err("mount ramdisk")?;
+    /// Whether the points that follow are durable, which may change mid-search.
+    durable: bool,
+}
+
+impl Experiment for LobstersNoria {
+    const NAME: &'static str = "lobsters-noria";
+
+    fn parameters(&self) -> &(dyn std::fmt::Debug + Sync) {
+        &self.parameters
+    }
+
+    fn search(&self) -> Search {
+        search(self.parameters)
+    }
+
+    fn machines(&self, ctx: &Context) -> Vec<(String, crate::provider::Setup)> {
+        let mut server = Role::new(&ctx.server_type);
+        if self.durable {
+            server = server.ramdisk("/mnt", "60G");
         }
+        vec![
+            (
+                String::from("server"),
+                crate::role::setup(ctx, "server", server)
+                    .setup(crate::noria_setup("noria-server", "noria-server")),
+            ),
+            (
+                String::from("client"),
+                crate::role::setup(ctx, "client", Role::new(&ctx.client_type))
+                    .setup(crate::noria_setup("noria-applications", "lobsters-noria")),
+            ),
+        ]
+    }
 
-        let mut scales = if let Some(loads) = loads {
-            Box::new(cliff::LoadIterator::from(loads)) as Box<dyn cliff::CliffSearch + Send>
-        } else if durable && partial {
-            // we don't normally run non-durable partial @ 6k scale, so run that too (6001)
-            Box::new(cliff::LoadIterator::from(vec![2000, 6000, 6001]))
-                as Box<dyn cliff::CliffSearch + Send>
-        } else if durable {
-            Box::new(cliff::LoadIterator::from(vec![6000])) as Box<dyn cliff::CliffSearch + Send>
+    fn adjust(&mut self, scale: usize) -> usize {
+        if scale % 2 == 1 {
+            tracing::warn!(%scale, "switching to non-durable");
+            assert!(self.durable);
+            self.durable = false;
+            scale - 1
         } else {
-            Box::new(cliff::ExponentialCliffSearcher::until(2000, 250))
-        };
-        let result: Result<(), Report> = try {
-            let mut successful_scale = None;
-            while let Some(mut scale) = scales.next() {
-                if scale % 2 == 1 {
-                    tracing::warn!(%scale, "switching to non-durable");
-                    assert!(durable);
-                    scale -= 1;
-                    durable = false;
-                }
-
-                if let Some(scale) = successful_scale.take() {
-                    // last run succeeded at the given scale
-                    last_good_scale = scale;
-                }
-                successful_scale = Some(scale);
-
-                if !partial && !durable && nshards == 0 && scale >= 6_250 {
-                    // this runs out of memory
-                    scales.overloaded();
-                    tracing::warn!(%scale, "skipping full scale that runs out of memory");
-                    continue;
-                }
-                if partial && nshards == 0 && scale >= 11_000 {
-                    // this runs partial out of memory
-                    scales.overloaded();
-                    tracing::warn!(%scale, "skipping partial scale that runs out of memory");
-                    continue;
-                }
-
-                if *ctx.exit.borrow() {
-                    tracing::info!("exiting as instructed");
-                    break;
-                }
-
-                let scale_span = tracing::info_span!("scale", scale);
-                async {
-                    tracing::info!("start benchmark target");
-                    let mut backend = if nshards == 0 {
-                        "direct".to_string()
-                    } else {
-                        format!("direct_{}", nshards)
-                    };
-                    if !partial {
-                        backend.push_str("_full");
-                    }
-                    if durable {
-                        backend.push_str("_durable");
-                    }
-                    let prefix = format!("lobsters-{}-{}-{}m", backend, scale, memlimit);
-
-                    if durable {
-                        tracing::debug!("remount ramdisk");
-                        crate::output_on_success(s.shell("sudo umount /mnt"))
-                            .await
-                            .wrap_err("unmount ramdisk")?;
-                        crate::output_on_success(
-                            s.shell("sudo mount -t tmpfs -o size=60G tmpfs /mnt"),
-                        )
-                        .await
-                        .wrap_err("remount ramdisk")?;
-                    }
-
-                    tracing::trace!("starting noria server");
-                    let dir = if durable { Some("/mnt") } else { None };
-                    let mut noria_server = crate::server::build(s, server, dir);
-                    if !partial {
-                        noria_server.arg("--no-partial");
-                    }
-                    let durability = if durable {
-                        "--durability=persistent"
-                    } else {
-                        "--durability=memory"
-                    };
-                    let noria_server = noria_server
-                        .arg(durability)
-                        .arg("--no-reuse")
-                        .arg("--shards")
-                        .arg(nshards.to_string())
-                        .arg("-m")
-                        .arg(memlimit.to_string())
-                        .spawn()
-                        .wrap_err("failed to start noria-server")?;
-
-                    crate::invoke::lobsters::run(
-                        &prefix,
-                        scale,
-                        || {
-                            scales.overloaded();
-                            successful_scale.take();
-                        },
-                        c,
-                        &server,
-                        crate::invoke::lobsters::Backend::Noria,
-                        &mut ctx,
-                    )
-                    .await?;
-
-                    if !*ctx.exit.borrow() {
-                        tracing::debug!("stopping server");
-                        crate::server::stop(s, noria_server).await?;
-                        tracing::trace!("server stopped");
-                    }
-
-                    Ok::<_, Report>(())
-                }
-                .instrument(scale_span)
-                .await?;
+            scale
+        }
+    }
+
+    fn results(&self) -> &'static str {
+        "lobsters"
+    }
+
+    fn prefix(&self, scale: usize) -> String {
+        let (nshards, partial, memlimit, _) = self.parameters;
+        ResultKey::LobstersNoria {
+            shards: nshards,
+            partial,
+            durable: self.durable,
+            scale,
+            memlimit,
+        }
+        .to_string()
+    }
+
+    fn completed(&self, _: usize, prefix: &str) -> Option<bool> {
+        crate::invoke::lobsters::completed(prefix, crate::invoke::lobsters::Backend::Noria)
+    }
+
+    fn known_bad(&self, scale: usize) -> Option<&'static str> {
+        let (nshards, partial, _, _) = self.parameters;
+        if !partial && !self.durable && nshards == 0 && scale >= 6_250 {
+            Some("full runs out of memory")
+        } else if partial && nshards == 0 && scale >= 11_000 {
+            Some("partial runs out of memory")
+        } else {
+            None
+        }
+    }
+
+    fn start<'a>(
+        &'a self,
+        _: usize,
+        machines: &'a Machines,
+    ) -> BoxFuture<'a, Result<Option<Child<'a>>, Report>> {
+        let (nshards, partial, memlimit, _) = self.parameters;
+        let durable = self.durable;
+        Box::pin(async move {
+            let server = machines.get("server");
+            let s = &server.session;
+            if durable {
+                tracing::debug!("remount ramdisk");
+                crate::output_on_success(s.shell("sudo umount /mnt"))
+                    .await
+                    .wrap_err("unmount ramdisk")?;
+                crate::output_on_success(s.shell("sudo mount -t tmpfs -o size=60G tmpfs /mnt"))
+                    .await
+                    .wrap_err("remount ramdisk")?;
             }
-        };
-
-        tracing::debug!("cleaning up");
-        tracing::trace!("cleaning up ssh connections");
-        for (name, host) in vms {
-            let host_span = tracing::trace_span!("ssh_close", name = &*name);
-            async {
-                tracing::trace!("closing connection");
-                if let Err(e) = host.ssh.close().await {
-                    tracing::warn!("ssh connection failed: {}", e);
-                }
+
+            tracing::trace!("starting noria server");
+            let dir = if durable { Some("/mnt") } else { None };
+            let mut noria_server = crate::server::build(s, server, dir);
+            if !partial {
+                noria_server.arg("--no-partial");
             }
-            .instrument(host_span)
-            .await
-        }
+            let durability = if durable {
+                "--durability=persistent"
+            } else {
+                "--durability=memory"
+            };
+            let noria_server = noria_server
+                .arg(durability)
+                .arg("--no-reuse")
+                .arg("--shards")
+                .arg(nshards.to_string())
+                .arg("-m")
+                .arg(memlimit.to_string())
+                .spawn()
+                .wrap_err("failed to start noria-server")?;
+            Ok(Some(noria_server))
+        })
+    }
 
-        result?
-    };
+    fn invoke<'a>(
+        &'a self,
+        scale: usize,
+        prefix: &'a str,
+        on_overloaded: &'a mut (dyn FnMut() + Send),
+        machines: &'a Machines,
+        ctx: &'a mut Context,
+    ) -> BoxFuture<'a, Result<(), Report>> {
+        let (nshards, partial, memlimit, _) = self.parameters;
+        Box::pin(crate::invoke::lobsters::run(
+            prefix,
+            serde_json::json!({
+                "scale": scale,
+                "shards": nshards,
+                "partial": partial,
+                "memlimit": memlimit,
+                "durable": self.durable,
+            }),
+            scale,
+            on_overloaded,
+            &machines.get("client").session,
+            machines.get("server"),
+            crate::invoke::lobsters::Backend::Noria,
+            ctx,
+        ))
+    }
 
-    tracing::trace!("cleaning up instances");
-    let cleanup = aws.terminate_all().await;
-    tracing::debug!("done");
-    let _ = result?;
-    let _ = cleanup.wrap_err("cleanup failed")?;
-    Ok(last_good_scale)
+    fn stop<'a>(
+        &'a self,
+        server: Option<Child<'a>>,
+        machines: &'a Machines,
+    ) -> BoxFuture<'a, Result<(), Report>> {
+        let s = &machines.get("server").session;
+        Box::pin(crate::server::stop(s, server.expect("started in start")))
+    }
+}
+
+#[instrument(err, skip(ctx))]
+pub(crate) async fn one(
+    parameters: (usize, bool, usize, bool),
+    plan: crate::spec::Plan,
+    ctx: Context,
+) -> Result<usize, Report> {
+    let (_, _, _, durable) = parameters;
+    let experiment = LobstersNoria {
+        parameters,
+        durable,
+    };
+    crate::experiment::run(experiment, plan, ctx).await
 }
@@ -1,186 +1,206 @@
+use crate::experiment::{Experiment, Machines};
+use crate::host::Child;
+use crate::role::Role;
+use crate::spec::Search;
 use crate::Context;
 use color_eyre::{eyre::WrapErr, Report};
+use futures_util::future::BoxFuture;
+use result_key::ResultKey;
 use tracing::instrument;
 use tracing_futures::Instrument;
-use tsunami::providers::aws;
-use tsunami::Tsunami;
 
 const KB: usize = 1024;
 const MB: usize = 1024 * KB;
 const GB: usize = 1024 * MB;
 
+/// Memory limits only make sense with partial materialization.
+const PARTIAL: bool = true;
+
+/// The parameters of a single lobsters-noria-memory experiment, as given in a spec file.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Parameters {
+    scale: usize,
+    shards: usize,
+}
+
+impl Parameters {
+    fn into_tuple(self) -> (usize, usize) {
+        (self.scale, self.shards)
+    }
+}
+
+/// The experiments to run, from the spec if it has any.
+fn experiments(ctx: &Context) -> Result<Vec<((usize, usize), crate::spec::Plan)>, Report> {
+    if let Some(experiments) =
+        crate::spec::experiments(ctx, "lobsters-noria-memory", Parameters::into_tuple)?
+    {
+        return Ok(experiments);
+    }
+
+    Ok(vec![(2000, 0)]
+        .into_iter()
+        .map(|parameters| (parameters, crate::spec::Plan::default()))
+        .collect())
+}
+
+/// The search to use for the given parameters, unless the spec says otherwise.
+fn search(_parameters: (usize, usize)) -> Search {
+    Search::BinaryMin {
+        start: 1 * GB,
+        min_width: 32 * MB,
+    }
+}
+
 /// lobsters-noria; requires two machines: a client and a server
 #[instrument(name = "lobsters-noria-mem", skip(ctx))]
 pub(crate) async fn main(ctx: Context) -> Result<(), Report> {
-    crate::explore!([(2000, 0)], one, ctx, false)
+    crate::explore!(@plans experiments(&ctx)?, one, ctx)
+}
+
+/// Describe what `main` would run.
+pub(crate) fn dry_run(ctx: &Context) -> Result<Vec<crate::dry_run::Experiment>, Report> {
+    Ok(experiments(ctx)?
+        .into_iter()
+        .map(|(parameters, plan)| {
+            let ctx = plan.context(ctx);
+            let search = plan.search_or(|| search(parameters));
+            crate::dry_run::Experiment::searching(&parameters, &search)
+                .repeated(&plan.repetitions)
+                .machine("server", &ctx.server_type, 1)
+                .machine("client", &ctx.client_type, 1)
+        })
+        .collect())
+}
+
+struct LobstersNoriaMemory((usize, usize));
+
+impl Experiment for LobstersNoriaMemory {
+    const NAME: &'static str = "lobsters-noria-memory";
+
+    fn parameters(&self) -> &(dyn std::fmt::Debug + Sync) {
+        &self.0
+    }
+
+    fn search(&self) -> Search {
+        search(self.0)
+    }
+
+    fn machines(&self, ctx: &Context) -> Vec<(String, crate::provider::Setup)> {
+        vec![
+            (
+                String::from("server"),
+                crate::role::setup(ctx, "server", Role::new(&ctx.server_type))
+                    .setup(crate::noria_setup("noria-server", "noria-server")),
+            ),
+            (
+                String::from("client"),
+                crate::role::setup(ctx, "client", Role::new(&ctx.client_type))
+                    .setup(crate::noria_setup("noria-applications", "lobsters-noria")),
+            ),
+        ]
+    }
+
+    fn baseline(&self) -> Option<usize> {
+        // no memory limit at all
+        Some(0)
+    }
+
+    fn results(&self) -> &'static str {
+        "lobsters"
+    }
+
+    fn prefix(&self, limit: usize) -> String {
+        let (scale, nshards) = self.0;
+        ResultKey::LobstersNoria {
+            shards: nshards,
+            partial: PARTIAL,
+            durable: false,
+            scale,
+            memlimit: limit,
+        }
+        .to_string()
+    }
+
+    fn completed(&self, _: usize, prefix: &str) -> Option<bool> {
+        crate::invoke::lobsters::completed(prefix, crate::invoke::lobsters::Backend::Noria)
+    }
+
+    fn known_bad(&self, limit: usize) -> Option<&'static str> {
+        if limit == 64 * MB {
+            Some("this doesn't work")
+        } else {
+            None
+        }
+    }
+
+    fn start<'a>(
+        &'a self,
+        limit: usize,
+        machines: &'a Machines,
+    ) -> BoxFuture<'a, Result<Option<Child<'a>>, Report>> {
+        let (_, nshards) = self.0;
+        Box::pin(async move {
+            tracing::trace!("starting noria server");
+            let server = machines.get("server");
+            let mut noria_server = crate::server::build(&server.session, server, None);
+            if !PARTIAL {
+                noria_server.arg("--no-partial");
+            }
+            let noria_server = noria_server
+                .arg("--durability=memory")
+                .arg("--no-reuse")
+                .arg("--shards")
+                .arg(nshards.to_string())
+                .arg("-m")
+                .arg(limit.to_string())
+                .spawn()
+                .wrap_err("failed to start noria-server")?;
+            Ok(Some(noria_server))
+        })
+    }
+
+    fn invoke<'a>(
+        &'a self,
+        limit: usize,
+        prefix: &'a str,
+        on_overloaded: &'a mut (dyn FnMut() + Send),
+        machines: &'a Machines,
+        ctx: &'a mut Context,
+    ) -> BoxFuture<'a, Result<(), Report>> {
+        let (scale, nshards) = self.0;
+        Box::pin(crate::invoke::lobsters::run(
+            prefix,
+            serde_json::json!({
+                "scale": scale,
+                "shards": nshards,
+                "partial": PARTIAL,
+                "memlimit": limit,
+            }),
+            scale,
+            on_overloaded,
+            &machines.get("client").session,
+            machines.get("server"),
+            crate::invoke::lobsters::Backend::Noria,
+            ctx,
+        ))
+    }
+
+    fn stop<'a>(
+        &'a self,
+        server: Option<Child<'a>>,
+        machines: &'a Machines,
+    ) -> BoxFuture<'a, Result<(), Report>> {
+        let s = &machines.get("server").session;
+        Box::pin(crate::server::stop(s, server.expect("started in start")))
+    }
 }
 
 #[instrument(err, skip(ctx))]
 pub(crate) async fn one(
     parameters: (usize, usize),
-    limits: Option<Vec<usize>>,
-    mut ctx: Context,
+    plan: crate::spec::Plan,
+    ctx: Context,
 ) -> Result<usize, Report> {
-    let (scale, nshards) = parameters;
-    let partial = true;
-    let mut last_good_limit = 0;
-
-    let mut aws = crate::launcher();
-    aws.set_mode(aws::LaunchMode::on_demand());
-
-    // try to ensure we do AWS cleanup
-    let result: Result<_, Report> = try {
-        tracing::info!("spinning up aws instances");
-
-        aws.spawn(
-            vec![
-                (
-                    String::from("server"),
-                    aws::Setup::default()
-                        .instance_type(&ctx.server_type)
-                        .ami(crate::AMI, "ubuntu")
-                        .availability_zone(ctx.az.clone())
-                        .setup(crate::noria_setup("noria-server", "noria-server")),
-                ),
-                (
-                    String::from("client"),
-                    aws::Setup::default()
-                        .instance_type(&ctx.client_type)
-                        .ami(crate::AMI, "ubuntu")
-                        .availability_zone(ctx.az.clone())
-                        .setup(crate::noria_setup("noria-applications", "lobsters-noria")),
-                ),
-            ],
-            None,
-        )
-        .await
-        .wrap_err("failed to start instances")?;
-
-        tracing::debug!("connecting");
-        let vms = aws.connect_all().await?;
-        let server = vms.get("server").unwrap();
-        let client = vms.get("client").unwrap();
-        let s = &server.ssh;
-        let c = &client.ssh;
-        tracing::debug!("connected");
-
-        let mut limits = if let Some(limits) = limits {
-            Box::new(cliff::LoadIterator::from(limits)) as Box<dyn cliff::CliffSearch + Send>
-        } else {
-            Box::new(cliff::BinaryMinSearcher::until(1 * GB, 32 * MB))
-                as Box<dyn cliff::CliffSearch + Send>
-        };
-        let mut zero = Some(0);
-        let result: Result<(), Report> = try {
-            let mut successful_limit = None;
-            while let Some(limit) = zero.take().or_else(|| limits.next()) {
-                if let Some(limit) = successful_limit.take() {
-                    // last run succeeded at the given limit
-                    last_good_limit = limit;
-                }
-                successful_limit = Some(limit);
-
-                if limit == 64 * MB {
-                    // this doesn't work
-                    tracing::warn!(%limit, "skipping known-bad limit");
-                    limits.overloaded();
-                    continue;
-                }
-
-                if scale % 4000 == 0 && (scale / 4000).is_power_of_two() {
-                    if limit == 128 * MB || limit == 256 * MB || limit == 384 * MB {
-                        // we already have this
-                        tracing::info!(%scale, %limit, "skipping (good) memlimit we already have");
-                        continue;
-                    }
-                    if limit == 0 {
-                        // we already have this
-                        tracing::info!(%scale, "skipping (good) non-limited scale we already have");
-                        continue;
-                    }
-                }
-
-                if *ctx.exit.borrow() {
-                    tracing::info!("exiting as instructed");
-                    break;
-                }
-
-                let limit_span = tracing::info_span!("limit", limit);
-                async {
-                    tracing::info!("start benchmark target");
-                    let mut backend = if nshards == 0 {
-                        "direct".to_string()
-                    } else {
-                        format!("direct_{}", nshards)
-                    };
-                    if !partial {
-                        backend.push_str("_full");
-                    }
-                    let prefix = format!("lobsters-{}-{}-{}m", backend, scale, limit);
-
-                    tracing::trace!("starting noria server");
-                    let mut noria_server = crate::server::build(s, server, None);
-                    if !partial {
-                        noria_server.arg("--no-partial");
-                    }
-                    let noria_server = noria_server
-                        .arg("--durability=memory")
-                        .arg("--no-reuse")
-                        .arg("--shards")
-                        .arg(nshards.to_string())
-                        .arg("-m")
-                        .arg(limit.to_string())
-                        .spawn()
-                        .wrap_err("failed to start noria-server")?;
-
-                    crate::invoke::lobsters::run(
-                        &prefix,
-                        scale,
-                        || {
-                            limits.overloaded();
-                            successful_limit.take();
-                        },
-                        c,
-                        &server,
-                        crate::invoke::lobsters::Backend::Noria,
-                        &mut ctx,
-                    )
-                    .await?;
-
-                    if !*ctx.exit.borrow() {
-                        tracing::debug!("stopping server");
-                        crate::server::stop(s, noria_server).await?;
-                        tracing::trace!("server stopped");
-                    }
-
-                    Ok::<_, Report>(())
-                }
-                .instrument(limit_span)
-                .await?;
-            }
-        };
-
-        tracing::debug!("cleaning up");
-        tracing::trace!("cleaning up ssh connections");
-        for (name, host) in vms {
-            let host_span = tracing::trace_span!("ssh_close", name = &*name);
-            async {
-                tracing::trace!("closing connection");
-                if let Err(e) = host.ssh.close().await {
-                    tracing::warn!("ssh connection failed: {}", e);
-                }
-            }
-            .instrument(host_span)
-            .await
-        }
-
-        result?
-    };
-
-    tracing::trace!("cleaning up instances");
-    let cleanup = aws.terminate_all().await;
-    tracing::debug!("done");
-    let _ = result?;
-    let _ = cleanup.wrap_err("cleanup failed")?;
-    Ok(last_good_limit)
+    crate::experiment::run(LobstersNoriaMemory(parameters), plan, ctx).await
 }
@@ -1,8 +1,18 @@
 #![feature(try_blocks, label_break_value)]
 
-const AMI: &str = "ami-0a8091371c4d91a3e";
-
-use clap::{App, Arg};
+/// The availability zones to try, in order, unless told otherwise.
+///
+/// us-east-1e goes last since it doesn't have most of the newer instance types.
+const ZONES: &[&str] = &[
+    "us-east-1a",
+    "us-east-1b",
+    "us-east-1c",
+    "us-east-1d",
+    "us-east-1f",
+    "us-east-1e",
+];
+
+use clap::{App, Arg, SubCommand};
 use color_eyre::{eyre, eyre::WrapErr, Report};
 use std::future::Future;
 use std::pin::Pin;
@@ -16,6 +26,28 @@ struct Context {
     client_type: String,
     exit: tokio::sync::watch::Receiver<bool>,
     az: aws::AvailabilityZoneSpec,
+    /// The availability zones to try when spawning an experiment's machines.
+    zones: Vec<String>,
+    provider: provider::Provider,
+    spec: Option<std::sync::Arc<spec::Spec>>,
+    resume: bool,
+    schedule: std::sync::Arc<schedule::Schedule>,
+    spot: bool,
+    /// The AMI for roles that do not name their own.
+    ami: String,
+    /// What the spec and command line override about each role.
+    roles: std::sync::Arc<std::collections::BTreeMap<String, role::Role>>,
+    ledger: std::sync::Arc<cost::Ledger>,
+    /// Whether to sample the resource use of the clients, and not just of the server.
+    sample_clients: bool,
+    /// Which part of the benchmark to profile the server for, if any.
+    profile: Option<profile::Window>,
+    /// What the experiment overrides about when targets count as overloaded.
+    overload: overload::OverloadPolicy,
+    /// How to decide how long to measure each target for, if not for a fixed time.
+    steady_state: Option<steady::SteadyState>,
+    /// Where the results of this run go.
+    results: results::Layout,
 }
 
 #[macro_export]
@@ -24,24 +56,33 @@ macro_rules! explore {
         crate::explore!([$($arg),*], $one, $ctx, $min)
     }};
     ([$($arg:expr),*], $one:ident, $ctx:ident, $min:expr) => {{
+        crate::explore!(@plans vec![$(($arg, crate::spec::Plan::default())),*], $one, $ctx)
+    }};
+    (@plans $plans:expr, $one:ident, $ctx:ident) => {{
         use tokio::stream::StreamExt;
 
-        let targets = vec![$($arg),*];
+        let targets: Vec<(_, crate::spec::Plan)> = $plans;
         let mut futs = futures_util::stream::futures_unordered::FuturesUnordered::new();
         let mut results = Vec::new();
-        for (i, target) in targets.iter().enumerate() {
+        for (i, (target, plan)) in targets.iter().enumerate() {
             results.push(Ok(0));
 
-            if futs.len() >= 3 {
-                // don't overwhelm ec2
-                let (i, r) = futs.next().await.expect(".len() > 0");
-                if let Err(ref e) = r {
-                    eprintln!("{:?}", e);
+            // wait for a slot, and keep track of experiments that finish in the meantime
+            let start = $ctx.schedule.start();
+            tokio::pin!(start);
+            let slot = loop {
+                tokio::select! {
+                    slot = &mut start => break slot,
+                    Some((i, r)) = futs.next(), if !futs.is_empty() => {
+                        if let Err(ref e) = r {
+                            eprintln!("{:?}", e);
+                        }
+                        results[i] = r;
+                    }
                 }
-                results[i] = r;
-            }
+            };
 
-            let mut ctx = $ctx.clone();
+            let mut ctx = plan.context(&$ctx);
             // we need to await exit so that it only yields again when we should exit
             // we need to do this for _every_ clone of exit
             if let Some(false) = ctx.exit.recv().await {
@@ -50,13 +91,15 @@ macro_rules! explore {
                 break;
             }
 
-            let fut = tokio::spawn($one(target.clone(), None, ctx).in_current_span());
+            let one = $one(target.clone(), plan.clone(), ctx);
+            let fut = tokio::spawn(async move {
+                // give up the slot only once the experiment is done
+                let _slot = slot;
+                one.await
+            }.in_current_span());
             futs.push(async move {
                 (i, fut.await.expect("runtime went away?"))
             });
-
-            // don't overwhelm ec2 by issuing all the requests at once
-            tokio::time::delay_for(std::time::Duration::from_secs(10)).await;
         }
 
         tracing::debug!("waiting for experiments to finish");
@@ -95,7 +138,29 @@ mod vote_redis;
 
 mod invoke;
 
+pub(crate) mod cost;
+pub(crate) mod dry_run;
+pub(crate) mod experiment;
+pub(crate) mod host;
+pub(crate) mod manifest;
+pub(crate) mod network;
+pub(crate) mod overload;
+pub(crate) mod profile;
+pub(crate) mod provider;
+pub(crate) mod repeat;
+pub(crate) mod results;
+pub(crate) mod resume;
+pub(crate) mod retry;
+pub(crate) mod role;
+pub(crate) mod sampler;
+pub(crate) mod schedule;
+pub(crate) mod search;
 pub(crate) mod server;
+pub(crate) mod spec;
+pub(crate) mod spot;
+pub(crate) mod steady;
+pub(crate) mod validate;
+pub(crate) mod watchdog;
 
 #[tokio::main]
 async fn main() {
@@ -130,15 +195,13 @@ async fn main() {
             Arg::with_name("az")
                 .long("az")
                 .takes_value(true)
-                .possible_values(&[
-                    "us-east-1a",
-                    "us-east-1b",
-                    "us-east-1c",
-                    "us-east-1d",
-                    "us-east-1e",
-                    "us-east-1f",
-                ])
-                .help("Spin up instances in the given availability zone"),
+                .multiple(true)
+                .require_delimiter(true)
+                .possible_values(ZONES)
+                .help(
+                    "Spin up instances in the first of these availability zones that has \
+                     capacity [default: all]",
+                ),
         )
         .arg(
             Arg::with_name("client")
@@ -146,22 +209,285 @@ async fn main() {
                 .default_value("m5n.4xlarge")
                 .help("Run the benchmark clients on instances of this type"),
         )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend-instance")
+                .takes_value(true)
+                .help("Run the databases noria is compared against on instances of this type"),
+        )
+        .arg(
+            Arg::with_name("ami")
+                .long("ami")
+                .takes_value(true)
+                .help("Boot machines from this AMI unless their role says otherwise"),
+        )
+        .arg(
+            Arg::with_name("spot")
+                .long("spot")
+                .conflicts_with_all(&["inventory", "local"])
+                .help("Run on spot instances, and start over on new ones if they are reclaimed"),
+        )
+        .arg(
+            Arg::with_name("inventory")
+                .long("inventory")
+                .takes_value(true)
+                .conflicts_with("az")
+                .help("Run on the existing hosts listed in this file instead of on EC2"),
+        )
+        .arg(
+            Arg::with_name("local")
+                .long("local")
+                .takes_value(true)
+                .value_name("DIR")
+                .conflicts_with_all(&["az", "inventory"])
+                .help("Run all machines as local processes in DIR instead of on EC2"),
+        )
+        .arg(
+            Arg::with_name("spec")
+                .long("spec")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Run the experiments listed in this file instead of the built-in ones"),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .help("Pick up interrupted searches where they left off"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .value_name("DIR")
+                .default_value(results::DEFAULT_OUTPUT)
+                .help("Write the results of each run to a directory of its own in DIR"),
+        )
+        .arg(
+            Arg::with_name("run")
+                .long("run")
+                .takes_value(true)
+                .value_name("NAME")
+                .help(
+                    "Name this run, or the run to resume [default: when it started, or the latest]",
+                ),
+        )
+        .arg(
+            Arg::with_name("archived")
+                .long("archived")
+                .takes_value(true)
+                .value_name("RESULTS")
+                .help("Do not run targets again that are already in the results tree RESULTS"),
+        )
+        .arg(
+            Arg::with_name("concurrency")
+                .long("concurrency")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|n| match n.parse::<usize>() {
+                    Ok(0) => Err(String::from("must be at least 1")),
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e.to_string()),
+                })
+                .help("Run at most this many experiments at a time [default: 3]"),
+        )
+        .arg(
+            Arg::with_name("stagger")
+                .long("stagger")
+                .takes_value(true)
+                .value_name("SECS")
+                .validator(|s| s.parse::<u64>().map(drop).map_err(|e| e.to_string()))
+                .help("Start experiments at least this many seconds apart [default: 10]"),
+        )
+        .arg(
+            Arg::with_name("parallel-benchmarks")
+                .long("parallel-benchmarks")
+                .help("Run the experiments of different benchmarks at the same time"),
+        )
+        .arg(
+            Arg::with_name("sample-clients")
+                .long("sample-clients")
+                .help("Record the resource use of the clients too, not just that of the server"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .number_of_values(2)
+                .value_names(&["AFTER", "SECONDS"])
+                .validator(|s| s.parse::<u64>().map(drop).map_err(|e| e.to_string()))
+                .help("Profile the server for SECONDS, starting AFTER seconds into the benchmark"),
+        )
+        .arg(
+            Arg::with_name("steady-state").long("steady-state").help(
+                "Warm up until each target is steady, and only measure for as long as needed",
+            ),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Print what would be run and what it would cost, and then exit"),
+        )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("Check a directory of results for runs that need to be run again")
+                .arg(
+                    Arg::with_name("DIR")
+                        .required(true)
+                        .help("The directory the results are in"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("archive")
+                .about("Merge the results of a run into the results tree")
+                .arg(
+                    Arg::with_name("RUN")
+                        .required(true)
+                        .help("The directory of the run"),
+                )
+                .arg(
+                    Arg::with_name("RESULTS")
+                        .default_value("../results")
+                        .help("The results tree to merge into"),
+                ),
+        )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("validate") {
+        let dir = matches.value_of("DIR").expect("required by clap");
+        match validate::validate(dir) {
+            Ok(findings) => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&findings).expect("findings serialize")
+                );
+                if !findings.problems.is_empty() {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("{:?}", e);
+                std::process::exit(2);
+            }
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("archive") {
+        let run = matches.value_of("RUN").expect("required by clap");
+        let results = matches.value_of("RESULTS").expect("has default value");
+        match results::archive(run.as_ref(), results.as_ref()) {
+            Ok(tallies) => {
+                for (dir, tally) in tallies {
+                    println!(
+                        "{}: {} added, {} replaced, {} unchanged",
+                        dir, tally.added, tally.replaced, tally.unchanged
+                    );
+                }
+            }
+            Err(e) => {
+                eprintln!("{:?}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let spec = if let Some(spec) = matches.value_of("spec") {
+        match spec::Spec::load(spec).and_then(|spec| spec::validate(&spec).map(|_| spec)) {
+            Ok(spec) => Some(std::sync::Arc::new(spec)),
+            Err(e) => {
+                eprintln!("{:?}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
     // only run specified benchmarks
     if let Some(vs) = matches.values_of("benchmarks") {
         benchmarks.clear();
         benchmarks.extend(vs);
+    } else if let Some(ref spec) = spec {
+        // or the ones the spec has experiments for
+        benchmarks.retain(|b| spec.benchmarks().any(|sb| sb == *b));
     }
 
-    let server_type = matches
+    // instance types given on the command line take precedence over those in the spec
+    let mut server_type = matches
         .value_of("server")
         .expect("has default value")
         .to_string();
-    let client_type = matches
+    let mut client_type = matches
         .value_of("client")
         .expect("has default value")
         .to_string();
+    if let Some(ref spec) = spec {
+        if matches.occurrences_of("server") == 0 {
+            if let Some(ref server) = spec.server_instance {
+                server_type = server.clone();
+            }
+        }
+        if matches.occurrences_of("client") == 0 {
+            if let Some(ref client) = spec.client_instance {
+                client_type = client.clone();
+            }
+        }
+    }
+
+    // the same goes for the rest of the role configuration
+    let mut roles = spec
+        .as_ref()
+        .map(|spec| spec.roles.clone())
+        .unwrap_or_default();
+    if let Some(backend) = matches.value_of("backend") {
+        roles.entry(String::from("backend")).or_default().instance = Some(backend.to_string());
+    }
+    let ami = matches
+        .value_of("ami")
+        .map(String::from)
+        .or_else(|| spec.as_ref().and_then(|spec| spec.ami.clone()))
+        .unwrap_or_else(|| String::from(role::DEFAULT_AMI));
+
+    // as are the limits on how many experiments run at once
+    let concurrency = matches
+        .value_of("concurrency")
+        .map(|c| c.parse().expect("validated by clap"))
+        .or_else(|| spec.as_ref().and_then(|spec| spec.concurrency))
+        .unwrap_or(schedule::DEFAULT_CONCURRENCY);
+    let stagger = matches
+        .value_of("stagger")
+        .map(|s| std::time::Duration::from_secs(s.parse().expect("validated by clap")))
+        .or_else(|| {
+            spec.as_ref()
+                .and_then(|spec| spec.stagger)
+                .map(std::time::Duration::from_secs)
+        })
+        .unwrap_or(schedule::DEFAULT_STAGGER);
+    let spot =
+        matches.is_present("spot") || spec.as_ref().and_then(|spec| spec.spot).unwrap_or(false);
+    let sample_clients = matches.is_present("sample-clients")
+        || spec
+            .as_ref()
+            .and_then(|spec| spec.sample_clients)
+            .unwrap_or(false);
+    let profile = matches.values_of("profile").map(|mut window| {
+        let mut next = || {
+            window
+                .next()
+                .expect("clap requires two values")
+                .parse()
+                .expect("validated by clap")
+        };
+        profile::Window {
+            after: next(),
+            seconds: next(),
+        }
+    });
+    let parallel = matches.is_present("parallel-benchmarks")
+        || spec
+            .as_ref()
+            .and_then(|spec| spec.parallel_benchmarks)
+            .unwrap_or(false);
 
     // set up tracing
     use tracing_error::ErrorLayer;
@@ -186,8 +512,47 @@ async fn main() {
     });
 
     // wrap all the contextual benchmark info in a Context
-    let az = if let Some(az) = matches.value_of("az") {
-        aws::AvailabilityZoneSpec::Specify(String::from(az))
+    let provider = if let Some(inventory) = matches.value_of("inventory") {
+        match provider::Inventory::load(inventory) {
+            Ok(inventory) => provider::Provider::Inventory(std::sync::Arc::new(inventory)),
+            Err(e) => {
+                eprintln!("{:?}", e);
+                std::process::exit(1);
+            }
+        }
+    } else if let Some(dir) = matches.value_of("local") {
+        provider::Provider::Local(std::path::PathBuf::from(dir))
+    } else {
+        provider::Provider::Aws
+    };
+    // keep all the machines of an experiment in one zone, so latencies are comparable
+    let zones: Vec<_> = if let provider::Provider::Aws = provider {
+        if let Some(zones) = matches.values_of("az") {
+            zones.map(String::from).collect()
+        } else {
+            ZONES.iter().map(|&zone| String::from(zone)).collect()
+        }
+    } else {
+        Vec::new()
+    };
+    let layout = match results::Layout::new(
+        matches
+            .value_of("output")
+            .expect("has default value")
+            .as_ref(),
+        matches.value_of("run"),
+        matches.is_present("resume"),
+        matches.value_of("archived").map(std::path::Path::new),
+    ) {
+        Ok(layout) => layout,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+    };
+    let ledger = cost::Ledger::new(matches!(provider, provider::Provider::Aws));
+    let az = if let Some(zone) = zones.first() {
+        aws::AvailabilityZoneSpec::Specify(zone.clone())
     } else {
         aws::AvailabilityZoneSpec::Any
     };
@@ -196,49 +561,139 @@ async fn main() {
         client_type,
         exit: rx,
         az,
+        zones,
+        provider,
+        spec,
+        resume: matches.is_present("resume"),
+        schedule: std::sync::Arc::new(schedule::Schedule::new(concurrency, stagger)),
+        spot,
+        ami,
+        roles: std::sync::Arc::new(roles),
+        ledger: std::sync::Arc::new(ledger),
+        sample_clients,
+        profile,
+        overload: Default::default(),
+        steady_state: if matches.is_present("steady-state") {
+            Some(Default::default())
+        } else {
+            None
+        },
+        results: layout,
     };
 
-    tracing::info!("running benchmarks");
-    for benchmark in benchmarks {
-        if *ctx.exit.borrow() {
-            tracing::warn!("exiting early as instructed");
+    if matches.is_present("dry-run") {
+        let mut hours = 0.0;
+        let mut cost = 0.0;
+        for benchmark in benchmarks {
+            let experiments = match benchmark {
+                "vote-migration" => vote_migration::dry_run(&ctx),
+                "vote" => vote::dry_run(&ctx),
+                "vote-memory" => vote_mem::dry_run(&ctx),
+                "vote-redis" => vote_redis::dry_run(&ctx),
+                "vote-hybrid" => vote_hybrid::dry_run(&ctx),
+                "lobsters-mysql" => lobsters_mysql::dry_run(&ctx),
+                "lobsters-noria" => lobsters_noria::dry_run(&ctx),
+                "lobsters-noria-memory" => lobsters_noria_mem::dry_run(&ctx),
+                _ => unreachable!("{}", benchmark),
+            };
+            match experiments {
+                Ok(experiments) => {
+                    let (h, c) = dry_run::print(benchmark, &experiments);
+                    hours += h;
+                    cost += c;
+                }
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    std::process::exit(1);
+                }
+            }
         }
+        println!(
+            "total: {:.1} instance-hours, ${:.2} (at most {} experiments run at a time)",
+            hours, cost, concurrency
+        );
+        return;
+    }
 
-        let result = match benchmark {
-            "vote-migration" => vote_migration::main(ctx.clone()).await,
-            "vote" => vote::main(ctx.clone()).await,
-            "vote-memory" => vote_mem::main(ctx.clone()).await,
-            "vote-redis" => vote_redis::main(ctx.clone()).await,
-            "vote-hybrid" => vote_hybrid::main(ctx.clone()).await,
-            "lobsters-mysql" => lobsters_mysql::main(ctx.clone()).await,
-            "lobsters-noria" => lobsters_noria::main(ctx.clone()).await,
-            "lobsters-noria-memory" => lobsters_noria_mem::main(ctx.clone()).await,
-            _ => unreachable!("{}", benchmark),
-        };
+    if let Err(e) = ctx.results.start() {
+        eprintln!("{:?}", e);
+        std::process::exit(1);
+    }
 
-        if let Err(ref e) = result {
-            tracing::error!(%benchmark, "benchmark failed: {}", e);
-            eprintln!("{:?}", e);
-        } else {
-            tracing::debug!(%benchmark, "benchmark completed");
+    tracing::info!("running benchmarks");
+    if parallel {
+        futures_util::future::join_all(
+            benchmarks
+                .into_iter()
+                .map(|benchmark| run(benchmark, ctx.clone())),
+        )
+        .await;
+    } else {
+        for benchmark in benchmarks {
+            if *ctx.exit.borrow() {
+                tracing::warn!("exiting early as instructed");
+                break;
+            }
+
+            run(benchmark, ctx.clone()).await;
         }
     }
     tracing::info!("all benchmarks completed");
+
+    let run = ctx.results.run();
+    let written = ctx
+        .ledger
+        .write(run.join("usage.tsv"), run.join("cost.txt"));
+    if let Err(e) = written {
+        eprintln!("{:?}", e);
+    }
 }
 
-fn launcher() -> aws::Launcher<rusoto_sts::StsAssumeRoleSessionCredentialsProvider> {
-    aws::Launcher::default().with_credentials(|| {
-        let sts = rusoto_sts::StsClient::new(rusoto_core::Region::UsEast1);
-        Ok(rusoto_sts::StsAssumeRoleSessionCredentialsProvider::new(
-            sts,
-            "arn:aws:sts::125163634912:role/soup".to_owned(),
-            "jon-thesis".to_owned(),
-            None,
-            None,
-            None,
-            None,
-        ))
-    })
+/// Run all the experiments of a single benchmark.
+async fn run(benchmark: &str, ctx: Context) {
+    let result = match benchmark {
+        "vote-migration" => vote_migration::main(ctx).await,
+        "vote" => vote::main(ctx).await,
+        "vote-memory" => vote_mem::main(ctx).await,
+        "vote-redis" => vote_redis::main(ctx).await,
+        "vote-hybrid" => vote_hybrid::main(ctx).await,
+        "lobsters-mysql" => lobsters_mysql::main(ctx).await,
+        "lobsters-noria" => lobsters_noria::main(ctx).await,
+        "lobsters-noria-memory" => lobsters_noria_mem::main(ctx).await,
+        _ => unreachable!("{}", benchmark),
+    };
+
+    if let Err(ref e) = result {
+        tracing::error!(%benchmark, "benchmark failed: {}", e);
+        eprintln!("{:?}", e);
+    } else {
+        tracing::debug!(%benchmark, "benchmark completed");
+    }
+}
+
+fn launcher(provider: &provider::Provider) -> provider::Launcher {
+    match *provider {
+        provider::Provider::Aws => {
+            provider::Launcher::Aws(aws::Launcher::default().with_credentials(|| {
+                let sts = rusoto_sts::StsClient::new(rusoto_core::Region::UsEast1);
+                Ok(rusoto_sts::StsAssumeRoleSessionCredentialsProvider::new(
+                    sts,
+                    "arn:aws:sts::125163634912:role/soup".to_owned(),
+                    "jon-thesis".to_owned(),
+                    None,
+                    None,
+                    None,
+                    None,
+                ))
+            }))
+        }
+        provider::Provider::Inventory(ref inventory) => provider::Launcher::Inventory(
+            provider::InventoryLauncher::new(std::sync::Arc::clone(inventory)),
+        ),
+        provider::Provider::Local(ref dir) => {
+            provider::Launcher::Local(provider::LocalLauncher::new(dir.clone()))
+        }
+    }
 }
 
 /// Prepare a box to run a particular experiment.
@@ -312,7 +767,7 @@ fn noria_setup(
     })
 }
 
-fn noria_bin<'s>(ssh: &'s openssh::Session, binary: &'static str) -> openssh::Command<'s> {
+fn noria_bin<'s>(ssh: &'s host::Session, binary: &'static str) -> host::Command<'s> {
     let mut cmd = ssh.command("env");
     cmd.arg("RUST_BACKTRACE=1")
         .arg(format!("target/release/{}", binary));
@@ -320,7 +775,7 @@ fn noria_bin<'s>(ssh: &'s openssh::Session, binary: &'static str) -> openssh::Co
 }
 
 #[instrument(level = "debug", skip(cmd))]
-async fn output_on_success<'a, C: std::borrow::BorrowMut<openssh::Command<'a>>>(
+async fn output_on_success<'a, C: std::borrow::BorrowMut<host::Command<'a>>>(
     mut cmd: C,
 ) -> Result<(Vec<u8>, Vec<u8>), Report> {
     let proc = cmd
@@ -347,7 +802,7 @@ async fn output_on_success<'a, C: std::borrow::BorrowMut<openssh::Command<'a>>>(
 }
 
 #[instrument(level = "debug", skip(ssh))]
-pub(crate) async fn noria_commit(ssh: &openssh::Session) -> Result<String, Report> {
+pub(crate) async fn noria_commit(ssh: &host::Session) -> Result<String, Report> {
     let commit = crate::output_on_success(
         ssh.command("git")
             .arg("-C")
@@ -362,7 +817,7 @@ pub(crate) async fn noria_commit(ssh: &openssh::Session) -> Result<String, Repor
 }
 
 #[instrument(level = "debug", skip(ssh))]
-pub(crate) async fn load(ssh: &openssh::Session) -> Result<(f64, f64), Report> {
+pub(crate) async fn load(ssh: &host::Session) -> Result<(f64, f64), Report> {
     let load = crate::output_on_success(
         ssh.command("awk")
             .arg("{print $1\" \"$2}")
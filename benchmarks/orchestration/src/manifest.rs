@@ -0,0 +1,110 @@
+//! Machine-readable metadata for a single benchmark target.
+//!
+//! The `.log` for each prefix carries the same information as `#` comment lines, but a
+//! `<prefix>.manifest.json` saves the analysis scripts from having to parse it back out.
+
+use crate::Context;
+use color_eyre::{eyre::WrapErr, Report};
+use serde::Serialize;
+use std::time::SystemTime;
+use tsunami::providers::aws;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Manifest {
+    pub(crate) prefix: String,
+    /// Everything the benchmark was configured with, including the load.
+    pub(crate) parameters: serde_json::Value,
+    pub(crate) server_type: String,
+    pub(crate) client_type: String,
+    /// The availability zone all the machines ran in, if we know it.
+    pub(crate) az: Option<String>,
+    pub(crate) commit: Option<String>,
+    /// Seconds spent priming, if the benchmark reports it.
+    pub(crate) prime_time: Option<f64>,
+    /// How warming up went, in adaptive mode.
+    pub(crate) warmup: Option<crate::steady::Warmup>,
+    /// How long the clients measured for, in adaptive mode.
+    pub(crate) measured: Option<crate::steady::Measured>,
+    /// 1- and 5-minute load averages.
+    pub(crate) server_load: Option<(f64, f64)>,
+    pub(crate) client_load: Option<(f64, f64)>,
+    pub(crate) server_memory_kb: Option<usize>,
+    /// The most memory the server used at any one sample, from `<prefix>-resources.tsv`.
+    pub(crate) server_peak_memory_kb: Option<usize>,
+    /// Memory used by a backing store that runs next to the server, if any.
+    pub(crate) backend_memory_kb: Option<usize>,
+    pub(crate) overloaded: bool,
+    /// Why the server died in the middle of the benchmark, if it did.
+    pub(crate) crashed: Option<String>,
+    /// Why we considered the target overloaded.
+    pub(crate) overload_reasons: Vec<String>,
+    /// The rules the target was judged by.
+    pub(crate) overload_policy: Option<crate::overload::OverloadPolicy>,
+    /// The rules that fired, and the values that made them.
+    pub(crate) violations: Vec<crate::overload::Violation>,
+    /// Seconds since the epoch.
+    pub(crate) started: u64,
+    pub(crate) finished: Option<u64>,
+    /// The result files written for this prefix, not including the manifest itself.
+    pub(crate) files: Vec<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl Manifest {
+    pub(crate) fn new(prefix: &str, parameters: serde_json::Value, ctx: &Context) -> Self {
+        Manifest {
+            prefix: prefix.to_string(),
+            parameters,
+            server_type: ctx.server_type.clone(),
+            client_type: ctx.client_type.clone(),
+            az: match ctx.az {
+                aws::AvailabilityZoneSpec::Specify(ref az) => Some(az.clone()),
+                _ => None,
+            },
+            commit: None,
+            prime_time: None,
+            warmup: None,
+            measured: None,
+            server_load: None,
+            client_load: None,
+            server_memory_kb: None,
+            server_peak_memory_kb: None,
+            backend_memory_kb: None,
+            overloaded: false,
+            crashed: None,
+            overload_reasons: Vec::new(),
+            overload_policy: None,
+            violations: Vec::new(),
+            started: now(),
+            finished: None,
+            files: Vec::new(),
+        }
+    }
+
+    /// Record the verdict and write the manifest to `<prefix>.manifest.json`.
+    pub(crate) async fn finish(
+        mut self,
+        overload_reasons: Vec<String>,
+        overload: crate::overload::Detector,
+    ) -> Result<(), Report> {
+        self.overloaded = !overload_reasons.is_empty();
+        self.overload_reasons = overload_reasons;
+        let (policy, violations) = overload.finish();
+        self.overload_policy = Some(policy);
+        self.violations = violations;
+        self.finished = Some(now());
+
+        let manifest = serde_json::to_vec_pretty(&self).wrap_err("failed to serialize manifest")?;
+        tokio::fs::write(format!("{}.manifest.json", self.prefix), manifest)
+            .await
+            .wrap_err("failed to write manifest")?;
+        Ok(())
+    }
+}
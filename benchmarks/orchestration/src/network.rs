@@ -0,0 +1,161 @@
+//! How much traffic the machines of a benchmark sent and received.
+//!
+//! A throughput cliff may just mean that a network link is saturated, not that the server is. To
+//! tell the two apart, the `/proc/net/dev` counters of every machine are read right before and
+//! right after the benchmark, and the difference for each interface is written to
+//! `<prefix>-network.tsv`.
+
+use crate::host::Session;
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use std::collections::BTreeMap;
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
+
+/// The counters of a single interface that we care about.
+#[derive(Debug, Clone, Copy, Default)]
+struct Counters {
+    rx_bytes: u64,
+    rx_packets: u64,
+    rx_drop: u64,
+    tx_bytes: u64,
+    tx_packets: u64,
+    tx_drop: u64,
+}
+
+impl Counters {
+    /// What changed since `before`.
+    ///
+    /// Counters may wrap around, and interfaces may be reset, so this saturates at zero.
+    fn since(&self, before: &Counters) -> Counters {
+        Counters {
+            rx_bytes: self.rx_bytes.saturating_sub(before.rx_bytes),
+            rx_packets: self.rx_packets.saturating_sub(before.rx_packets),
+            rx_drop: self.rx_drop.saturating_sub(before.rx_drop),
+            tx_bytes: self.tx_bytes.saturating_sub(before.tx_bytes),
+            tx_packets: self.tx_packets.saturating_sub(before.tx_packets),
+            tx_drop: self.tx_drop.saturating_sub(before.tx_drop),
+        }
+    }
+}
+
+/// Read the counters of every interface but loopback.
+async fn snapshot(session: &Session) -> Result<BTreeMap<String, Counters>, Report> {
+    let (dev, _) = crate::output_on_success(session.command("cat").arg("/proc/net/dev"))
+        .await
+        .wrap_err("cat /proc/net/dev")?;
+    let dev = String::from_utf8_lossy(&dev);
+
+    let mut interfaces = BTreeMap::new();
+    // the first two lines are headers
+    for line in dev.lines().skip(2) {
+        let mut parts = line.splitn(2, ':');
+        let (name, counters) = match (parts.next(), parts.next()) {
+            (Some(name), Some(counters)) => (name.trim(), counters),
+            _ => eyre::bail!("bad /proc/net/dev line: {}", line),
+        };
+        if name == "lo" {
+            continue;
+        }
+
+        let fields = counters
+            .split_whitespace()
+            .map(|f| f.parse())
+            .collect::<Result<Vec<u64>, _>>()
+            .wrap_err_with(|| format!("bad /proc/net/dev line: {}", line))?;
+        // bytes packets errs drop fifo frame compressed multicast, for receive and then transmit
+        if fields.len() < 12 {
+            eyre::bail!("bad /proc/net/dev line: {}", line);
+        }
+        interfaces.insert(
+            name.to_string(),
+            Counters {
+                rx_bytes: fields[0],
+                rx_packets: fields[1],
+                rx_drop: fields[3],
+                tx_bytes: fields[8],
+                tx_packets: fields[9],
+                tx_drop: fields[11],
+            },
+        );
+    }
+    Ok(interfaces)
+}
+
+struct Watched<'a> {
+    host: String,
+    session: &'a Session,
+    at: Instant,
+    before: BTreeMap<String, Counters>,
+}
+
+/// Network counters of any number of machines, as of when they started being watched.
+pub(crate) struct Network<'a> {
+    prefix: String,
+    watched: Vec<Watched<'a>>,
+}
+
+impl<'a> Network<'a> {
+    pub(crate) fn new(prefix: &str) -> Self {
+        Network {
+            prefix: prefix.to_string(),
+            watched: Vec::new(),
+        }
+    }
+
+    /// Remember the current counters of the given host.
+    pub(crate) async fn watch(&mut self, host: &str, session: &'a Session) -> Result<(), Report> {
+        tracing::trace!(%host, "snapshot network counters");
+        let before = snapshot(session).await?;
+        self.watched.push(Watched {
+            host: host.to_string(),
+            session,
+            at: Instant::now(),
+            before,
+        });
+        Ok(())
+    }
+
+    /// Write out what every host has sent and received since it started being watched.
+    ///
+    /// Returns the name of the file that was written.
+    pub(crate) async fn finish(self) -> Result<String, Report> {
+        let file = format!("{}-network.tsv", self.prefix);
+        let mut results = tokio::fs::File::create(&file)
+            .await
+            .wrap_err("failed to create local network file")?;
+        results
+            .write_all(
+                b"#host\tinterface\tseconds\trx_bytes\trx_packets\trx_drop\ttx_bytes\ttx_packets\ttx_drop\n",
+            )
+            .await?;
+
+        for w in self.watched {
+            let after = snapshot(w.session)
+                .await
+                .wrap_err_with(|| format!("failed to snapshot network of {}", w.host))?;
+            let seconds = w.at.elapsed().as_secs_f64();
+            for (interface, now) in after {
+                let d = now.since(&w.before.get(&interface).copied().unwrap_or_default());
+                results
+                    .write_all(
+                        format!(
+                            "{}\t{}\t{:.1}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                            w.host,
+                            interface,
+                            seconds,
+                            d.rx_bytes,
+                            d.rx_packets,
+                            d.rx_drop,
+                            d.tx_bytes,
+                            d.tx_packets,
+                            d.tx_drop
+                        )
+                        .as_bytes(),
+                    )
+                    .await?;
+            }
+        }
+        results.flush().await?;
+        Ok(file)
+    }
+}
@@ -0,0 +1,168 @@
+//! Deciding when a target is overloaded.
+//!
+//! Each benchmark has its own idea of what overloaded means, given by [`OverloadPolicy::vote`]
+//! and [`OverloadPolicy::lobsters`]. An experiment in a spec can override any of the rules:
+//!
+//! ```toml
+//! [[vote]]
+//! # ...
+//! overload = { min-throughput = 0.9, max-server-load = 31.5 }
+//!
+//! [[lobsters-noria]]
+//! # ...
+//! overload = { sojourn = [{ percentile = 95, max-us = 500000 }] }
+//! ```
+//!
+//! A `sojourn` list replaces the benchmark's latency limits entirely. A limit without an
+//! `endpoint` applies to every endpoint. Whichever rules fire end up in the target's manifest,
+//! along with the value that broke them, and the policy itself is recorded next to them.
+
+use crate::Context;
+use serde::{Deserialize, Serialize};
+
+/// The rules that make a target count as overloaded.
+///
+/// A rule that is not set never fires.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct OverloadPolicy {
+    pub(crate) sojourn: Option<Vec<SojournLimit>>,
+    /// The fraction of the target throughput that the clients must at least achieve.
+    pub(crate) min_throughput: Option<f64>,
+    /// The highest 1-minute load average the server may have once the benchmark is done.
+    pub(crate) max_server_load: Option<f64>,
+}
+
+/// The highest sojourn latency that a percentile of requests may see.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct SojournLimit {
+    /// The endpoint this applies to, or all of them if not set.
+    pub(crate) endpoint: Option<String>,
+    pub(crate) percentile: u32,
+    /// In µs.
+    pub(crate) max_us: f64,
+}
+
+/// A rule that fired.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Violation {
+    pub(crate) rule: String,
+    pub(crate) value: f64,
+    pub(crate) limit: f64,
+}
+
+impl OverloadPolicy {
+    pub(crate) fn vote() -> Self {
+        OverloadPolicy {
+            sojourn: Some(vec![
+                SojournLimit {
+                    endpoint: Some("read".to_string()),
+                    percentile: 95,
+                    max_us: 20_000.0,
+                },
+                SojournLimit {
+                    endpoint: Some("write".to_string()),
+                    percentile: 50,
+                    max_us: 20_000.0,
+                },
+            ]),
+            min_throughput: Some(0.95),
+            max_server_load: Some(15.5),
+        }
+    }
+
+    pub(crate) fn lobsters() -> Self {
+        OverloadPolicy {
+            sojourn: Some(vec![SojournLimit {
+                endpoint: None,
+                percentile: 50,
+                max_us: 200_000.0,
+            }]),
+            min_throughput: Some(0.8),
+            max_server_load: Some(15.5),
+        }
+    }
+
+    /// Replace whatever `overrides` sets.
+    fn or(self, overrides: &OverloadPolicy) -> Self {
+        OverloadPolicy {
+            sojourn: overrides.sojourn.clone().or(self.sojourn),
+            min_throughput: overrides.min_throughput.or(self.min_throughput),
+            max_server_load: overrides.max_server_load.or(self.max_server_load),
+        }
+    }
+}
+
+/// Applies an [`OverloadPolicy`] to a single target, and remembers which rules fired.
+#[derive(Debug)]
+pub(crate) struct Detector {
+    policy: OverloadPolicy,
+    violations: Vec<Violation>,
+}
+
+impl Detector {
+    /// Start judging a target by the benchmark's `defaults`, as overridden for this experiment.
+    pub(crate) fn new(ctx: &Context, defaults: OverloadPolicy) -> Self {
+        Self::with_policy(defaults.or(&ctx.overload))
+    }
+
+    /// Judge by exactly the given policy.
+    pub(crate) fn with_policy(policy: OverloadPolicy) -> Self {
+        Detector {
+            policy,
+            violations: Vec::new(),
+        }
+    }
+
+    fn fire(&mut self, rule: String, value: f64, limit: f64) {
+        self.violations.push(Violation { rule, value, limit });
+    }
+
+    /// Check a sojourn latency percentile, and say why it is too high if it is.
+    pub(crate) fn sojourn(&mut self, endpoint: &str, percentile: u32, us: f64) -> Option<String> {
+        let limit = self
+            .policy
+            .sojourn
+            .iter()
+            .flatten()
+            .filter(|l| l.percentile == percentile)
+            .filter(|l| l.endpoint.as_deref().map(|e| e == endpoint).unwrap_or(true))
+            .find(|l| us > l.max_us)?
+            .max_us;
+        self.fire(format!("sojourn {} p{}", endpoint, percentile), us, limit);
+        Some(format!(
+            "high {} sojourn latency at p{}: {}us",
+            endpoint, percentile, us
+        ))
+    }
+
+    /// Check the throughput the clients achieved, and say why it is too low if it is.
+    pub(crate) fn throughput(&mut self, actual: f64, target: f64) -> Option<String> {
+        let limit = target * self.policy.min_throughput?;
+        if actual >= limit {
+            return None;
+        }
+        self.fire("min-throughput".to_string(), actual, limit);
+        Some(format!(
+            "low throughput: {} ops/s, expected {}",
+            actual, target
+        ))
+    }
+
+    /// Check the load of the server, and say why it is too high if it is.
+    pub(crate) fn server_load(&mut self, load1: f64) -> Option<String> {
+        let limit = self.policy.max_server_load?;
+        if load1 <= limit {
+            return None;
+        }
+        self.fire("max-server-load".to_string(), load1, limit);
+        Some(format!("high server load: {}", load1))
+    }
+
+    /// The policy that was applied, and the rules that fired.
+    pub(crate) fn finish(self) -> (OverloadPolicy, Vec<Violation>) {
+        (self.policy, self.violations)
+    }
+}
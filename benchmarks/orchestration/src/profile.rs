@@ -0,0 +1,152 @@
+//! Profiling the server while the benchmark runs.
+//!
+//! When an experiment asks for it, `perf record` samples the stacks of the server process for a
+//! window of the benchmark. The samples are saved as `<prefix>-perf.script`, the output of `perf
+//! script`, and folded into `<prefix>-perf.folded`, which `flamegraph.pl` and friends accept.
+
+use crate::host::{Child, Session};
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// How often `perf` samples each cpu, in Hz.
+const FREQUENCY: usize = 99;
+
+/// Which part of the benchmark to profile.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Window {
+    /// Seconds into the benchmark to start profiling.
+    pub(crate) after: u64,
+    /// How many seconds to profile for.
+    pub(crate) seconds: u64,
+}
+
+/// A `perf record` that is running (or waiting to run) on the server.
+pub(crate) struct Profiler<'a> {
+    session: &'a Session,
+    perf: Child<'a>,
+}
+
+impl<'a> Profiler<'a> {
+    /// Start profiling the oldest process with the given name once the window opens.
+    ///
+    /// This should be called right as the benchmark starts.
+    pub(crate) async fn start(
+        session: &'a Session,
+        process: &str,
+        window: Window,
+    ) -> Result<Profiler<'a>, Report> {
+        let pid = crate::server::pid_of(session, process).await?;
+        tracing::debug!(pid, ?window, "profiling server");
+        let perf = session
+            .shell(format!(
+                "sleep {} && sudo perf record -F {} -g -p {} -o perf.data -- sleep {}",
+                window.after, FREQUENCY, pid, window.seconds
+            ))
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .wrap_err("perf record")?;
+        Ok(Profiler { session, perf })
+    }
+
+    /// Wait for the profile to be recorded, and save it under the given prefix.
+    ///
+    /// Returns the files that were written.
+    pub(crate) async fn finish(self, prefix: &str) -> Result<Vec<String>, Report> {
+        let recorded = self.perf.wait_with_output().await.wrap_err("perf record")?;
+        if !recorded.status.success() {
+            return Err(eyre::eyre!(
+                String::from_utf8_lossy(&recorded.stderr).to_string()
+            ))
+            .wrap_err("perf record failed");
+        }
+
+        tracing::trace!("saving profile");
+        let (script, _) = crate::output_on_success(
+            self.session
+                .shell("sudo perf script -i perf.data && sudo rm perf.data"),
+        )
+        .await
+        .wrap_err("perf script")?;
+
+        let raw = format!("{}-perf.script", prefix);
+        tokio::fs::write(&raw, &script)
+            .await
+            .wrap_err("failed to save profile")?;
+        let folded = format!("{}-perf.folded", prefix);
+        tokio::fs::write(&folded, fold(&String::from_utf8_lossy(&script)))
+            .await
+            .wrap_err("failed to save folded stacks")?;
+        Ok(vec![raw, folded])
+    }
+}
+
+/// Start profiling the server, if we can.
+///
+/// A profile is nice to have, so failing to get one should not fail the benchmark.
+pub(crate) async fn start<'a>(
+    session: &'a Session,
+    process: &str,
+    window: Window,
+) -> Option<Profiler<'a>> {
+    match Profiler::start(session, process, window).await {
+        Ok(profiler) => Some(profiler),
+        Err(e) => {
+            tracing::warn!("not profiling server: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Save the profile of the server, if we can, and return the files it was saved to.
+pub(crate) async fn finish(profiler: Profiler<'_>, prefix: &str) -> Vec<String> {
+    tracing::trace!("saving server profile");
+    match profiler.finish(prefix).await {
+        Ok(files) => files,
+        Err(e) => {
+            tracing::warn!("failed to profile server: {:?}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Fold the stacks in the output of `perf script`, one line per distinct stack.
+///
+/// Each line is the name of the thread and then the frames, outermost first, all separated by
+/// `;`, followed by the number of samples of that stack.
+fn fold(script: &str) -> String {
+    let mut stacks = BTreeMap::new();
+    let mut thread = None;
+    let mut frames: Vec<String> = Vec::new();
+    // a blank line ends each sample
+    for line in script.lines().chain(std::iter::once("")) {
+        if line.trim().is_empty() {
+            if let Some(thread) = thread.take() {
+                let mut stack = String::from(thread);
+                for frame in frames.drain(..).rev() {
+                    stack.push(';');
+                    stack.push_str(&frame);
+                }
+                *stacks.entry(stack).or_insert(0) += 1;
+            }
+        } else if !line.starts_with(char::is_whitespace) {
+            // <thread> <pid> <time>: <period> <event>:
+            thread = line.split_whitespace().next();
+        } else {
+            // <address> <symbol>+<offset> (<object>)
+            let frame = line.trim();
+            let frame = frame.splitn(2, ' ').nth(1).unwrap_or("[unknown]");
+            let frame = frame.rsplitn(2, " (").last().unwrap_or(frame);
+            let frame = frame.rsplitn(2, "+0x").last().unwrap_or(frame);
+            // the separator may show up in symbols like `[u8; 32]`
+            frames.push(frame.replace(';', ":"));
+        }
+    }
+
+    stacks
+        .into_iter()
+        .map(|(stack, n)| format!("{} {}\n", stack, n))
+        .collect()
+}
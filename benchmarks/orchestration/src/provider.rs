@@ -0,0 +1,574 @@
+//! Where experiment machines come from.
+//!
+//! By default, every experiment spins up fresh EC2 instances. Alternatively, an inventory file can
+//! name hosts that already exist (lab machines, localhost, ...), which are then handed out to the
+//! experiments over ssh instead. Finally, in local mode, every machine is just a working directory
+//! on this machine, and no ssh is involved at all.
+
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use tracing::instrument;
+use tracing_futures::Instrument;
+use tsunami::providers::{aws, baremetal, LaunchDescriptor, MachineSetup};
+
+use crate::host::Host;
+use crate::role::{CpuOptions, Ramdisk};
+
+pub(crate) type SetupFn = Arc<
+    dyn for<'r> Fn(
+            &'r tsunami::Machine<'_>,
+        ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+        + Send
+        + Sync
+        + 'static,
+>;
+
+/// Make the compiler see `f` as a [`SetupFn`].
+fn setup_fn(
+    f: impl for<'r> Fn(
+            &'r tsunami::Machine<'_>,
+        ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+        + Send
+        + Sync
+        + 'static,
+) -> SetupFn {
+    Arc::new(f)
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Provider {
+    Aws,
+    Inventory(Arc<Inventory>),
+    Local(PathBuf),
+}
+
+/// A provider-agnostic descriptor for a single machine.
+///
+/// The builder methods mirror those of `aws::Setup`, and are simply ignored by providers that
+/// have no use for them (like the instance type of a pre-provisioned host).
+#[derive(Clone)]
+pub(crate) struct Setup {
+    aws: aws::Setup,
+    role: Option<String>,
+    instance_type: Option<String>,
+    ramdisk: Option<Ramdisk>,
+    cpu_options: Option<CpuOptions>,
+    setup_fn: Option<SetupFn>,
+}
+
+impl std::fmt::Debug for Setup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.aws.fmt(f)
+    }
+}
+
+impl Default for Setup {
+    fn default() -> Self {
+        Setup {
+            aws: aws::Setup::default(),
+            role: None,
+            instance_type: None,
+            ramdisk: None,
+            cpu_options: None,
+            setup_fn: None,
+        }
+    }
+}
+
+impl MachineSetup for Setup {
+    type Region = String;
+
+    fn region(&self) -> Self::Region {
+        MachineSetup::region(&self.aws)
+    }
+}
+
+impl Setup {
+    pub(crate) fn instance_type(self, typ: impl ToString) -> Self {
+        Self {
+            aws: self.aws.instance_type(typ.to_string()),
+            instance_type: Some(typ.to_string()),
+            ..self
+        }
+    }
+
+    pub(crate) fn ami(self, ami: impl ToString, username: impl ToString) -> Self {
+        Self {
+            aws: self.aws.ami(ami, username),
+            ..self
+        }
+    }
+
+    pub(crate) fn availability_zone(self, az: aws::AvailabilityZoneSpec) -> Self {
+        Self {
+            aws: self.aws.availability_zone(az),
+            ..self
+        }
+    }
+
+    /// Note which role this machine plays in its experiment.
+    pub(crate) fn role(self, role: impl ToString) -> Self {
+        Self {
+            role: Some(role.to_string()),
+            ..self
+        }
+    }
+
+    /// Mount a ramdisk before running the setup procedure.
+    pub(crate) fn ramdisk(self, ramdisk: Ramdisk) -> Self {
+        Self {
+            ramdisk: Some(ramdisk),
+            ..self
+        }
+    }
+
+    /// Take some of the machine's cpus offline before running the setup procedure.
+    ///
+    /// This is only done on EC2 instances; the hosts of an inventory are not ours to change.
+    pub(crate) fn cpu_options(self, cpu_options: CpuOptions) -> Self {
+        Self {
+            cpu_options: Some(cpu_options),
+            ..self
+        }
+    }
+
+    pub(crate) fn setup(
+        self,
+        setup: impl for<'r> Fn(
+                &'r tsunami::Machine<'_>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        Self {
+            setup_fn: Some(Arc::new(setup)),
+            ..self
+        }
+    }
+
+    /// The role this machine was given, if any.
+    pub(crate) fn role_name(&self) -> Option<&str> {
+        self.role.as_deref()
+    }
+
+    /// The instance type this machine was given, if any.
+    pub(crate) fn instance(&self) -> Option<&str> {
+        self.instance_type.as_deref()
+    }
+
+    /// Everything that needs to happen on a fresh machine, if anything does.
+    fn procedure(&self, limit_cpus: bool) -> Option<SetupFn> {
+        let ramdisk = self.ramdisk.clone();
+        let cpu_options = self.cpu_options.filter(|_| limit_cpus);
+        let then = self.setup_fn.clone();
+        if ramdisk.is_none() && cpu_options.is_none() && then.is_none() {
+            return None;
+        }
+
+        Some(setup_fn(move |vm| {
+            let ramdisk = ramdisk.clone();
+            let then = then.clone();
+            Box::pin(async move {
+                if let Some(cpu_options) = cpu_options {
+                    cpu_options.apply(&vm.ssh).await?;
+                }
+                if let Some(ramdisk) = ramdisk {
+                    ramdisk.mount(&vm.ssh).await?;
+                }
+                if let Some(f) = then {
+                    f(vm).await?;
+                }
+                Ok::<_, Report>(())
+            })
+        }))
+    }
+}
+
+/// A launcher for whichever [`Provider`] was chosen on the command line.
+pub(crate) enum Launcher {
+    Aws(aws::Launcher<rusoto_sts::StsAssumeRoleSessionCredentialsProvider>),
+    Inventory(InventoryLauncher),
+    Local(LocalLauncher),
+}
+
+impl Launcher {
+    pub(crate) fn set_mode(&mut self, mode: aws::LaunchMode) -> &mut Self {
+        if let Launcher::Aws(ref mut aws) = *self {
+            aws.set_mode(mode);
+        }
+        self
+    }
+
+    /// Connect to all the spawned machines.
+    ///
+    /// Prefer this over `connect_all`, which only works for providers that have ssh connections
+    /// to hand out.
+    pub(crate) async fn hosts(&self) -> Result<HashMap<String, Host>, Report> {
+        use tsunami::providers::Launcher;
+
+        if let crate::provider::Launcher::Local(ref local) = *self {
+            return Ok(local
+                .names
+                .iter()
+                .map(|name| (name.clone(), Host::local(name.clone(), local.dir.clone())))
+                .collect());
+        }
+
+        Ok(self
+            .connect_all()
+            .await?
+            .into_iter()
+            .map(|(name, vm)| (name, Host::from(vm)))
+            .collect())
+    }
+}
+
+impl tsunami::providers::Launcher for Launcher {
+    type MachineDescriptor = Setup;
+
+    fn launch<'l>(
+        &'l mut self,
+        l: LaunchDescriptor<Self::MachineDescriptor>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        match *self {
+            Launcher::Aws(ref mut aws) => aws.launch(LaunchDescriptor {
+                region: l.region,
+                max_wait: l.max_wait,
+                machines: l
+                    .machines
+                    .into_iter()
+                    .map(|(name, setup)| {
+                        if let Some(f) = setup.procedure(true) {
+                            (name, setup.aws.setup(move |vm| f(vm)))
+                        } else {
+                            (name, setup.aws)
+                        }
+                    })
+                    .collect(),
+            }),
+            Launcher::Inventory(ref mut inv) => Box::pin(inv.launch(l)),
+            Launcher::Local(ref mut local) => {
+                for (name, setup) in l.machines {
+                    if setup.procedure(false).is_some() {
+                        tracing::debug!(%name, "skipping setup of local host");
+                    }
+                    local.names.push(name);
+                }
+                Box::pin(async { Ok(()) })
+            }
+        }
+    }
+
+    fn connect_all<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, tsunami::Machine<'l>>, Report>> + Send + 'l>,
+    > {
+        match *self {
+            Launcher::Aws(ref aws) => aws.connect_all(),
+            Launcher::Inventory(ref inv) => Box::pin(inv.connect_all()),
+            Launcher::Local(_) => Box::pin(async {
+                Err(eyre::eyre!(
+                    "local hosts have no ssh connection; use hosts()"
+                ))
+            }),
+        }
+    }
+
+    fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
+        match self {
+            Launcher::Aws(aws) => aws.terminate_all(),
+            Launcher::Inventory(_) | Launcher::Local(_) => {
+                // we didn't start these machines, so we certainly shouldn't stop them
+                Box::pin(async { Ok(()) })
+            }
+        }
+    }
+}
+
+/// A single pre-provisioned host from an inventory file.
+#[derive(Debug, Clone)]
+pub(crate) struct InventoryHost {
+    role: String,
+    username: Option<String>,
+    addr: String,
+    private_ip: Option<String>,
+}
+
+/// A list of pre-provisioned hosts.
+///
+/// The inventory file has one host per line, on the form
+///
+/// ```text
+/// # role   [user@]host[:port]   [private ip]
+/// server   ubuntu@10.0.0.1
+/// client   ubuntu@lab-2.local   10.0.0.2
+/// *        127.0.0.1
+/// ```
+///
+/// Each machine an experiment asks for is matched against the roles by its exact nickname (e.g.,
+/// `client0`), then by its nickname without any trailing index (`client`), and finally against
+/// the wildcard role `*`. If an experiment asks for more machines with a given role than there
+/// are hosts listed for it, the hosts are re-used in a round-robin fashion. A single `*` line
+/// thus runs everything on one host.
+///
+/// The private ip is what clients will use to talk to the server. It defaults to the address ssh
+/// connects to.
+#[derive(Debug, Clone)]
+pub(crate) struct Inventory {
+    hosts: Vec<InventoryHost>,
+}
+
+impl Inventory {
+    #[instrument(level = "debug")]
+    pub(crate) fn load(path: &str) -> Result<Self, Report> {
+        let inventory = std::fs::read_to_string(path).wrap_err("failed to read inventory")?;
+        let mut hosts = Vec::new();
+        for (lineno, line) in inventory.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let role = fields.next().expect("line is not empty");
+            let dest = if let Some(dest) = fields.next() {
+                dest
+            } else {
+                eyre::bail!("{}:{}: missing host address", path, lineno + 1);
+            };
+            let private_ip = fields.next().map(String::from);
+            if fields.next().is_some() {
+                eyre::bail!("{}:{}: trailing fields", path, lineno + 1);
+            }
+
+            let (username, addr) = if let Some(at) = dest.find('@') {
+                (Some(dest[..at].to_string()), &dest[(at + 1)..])
+            } else {
+                (None, dest)
+            };
+            let addr = if addr.contains(':') {
+                addr.to_string()
+            } else {
+                format!("{}:22", addr)
+            };
+
+            hosts.push(InventoryHost {
+                role: role.to_string(),
+                username,
+                addr,
+                private_ip,
+            });
+        }
+
+        if hosts.is_empty() {
+            eyre::bail!("inventory {} lists no hosts", path);
+        }
+
+        Ok(Inventory { hosts })
+    }
+
+    /// Pick the host to use for the machine with the given nickname.
+    ///
+    /// `assigned` keeps track of how many hosts have been handed out for each role so far.
+    fn assign(
+        &self,
+        name: &str,
+        assigned: &mut HashMap<String, usize>,
+    ) -> Result<&InventoryHost, Report> {
+        let candidates = [
+            name,
+            name.trim_end_matches(|c: char| c.is_ascii_digit()),
+            "*",
+        ];
+        for &role in &candidates {
+            let hosts: Vec<_> = self.hosts.iter().filter(|h| h.role == role).collect();
+            if hosts.is_empty() {
+                continue;
+            }
+
+            let next = assigned.entry(role.to_string()).or_insert(0);
+            let host = hosts[*next % hosts.len()];
+            *next += 1;
+            return Ok(host);
+        }
+
+        Err(eyre::eyre!("no host in inventory for {}", name))
+    }
+}
+
+/// Hands out hosts from an [`Inventory`] rather than starting new ones.
+pub(crate) struct InventoryLauncher {
+    inventory: Arc<Inventory>,
+    assigned: HashMap<String, usize>,
+    machines: Vec<(Option<String>, baremetal::Machine)>,
+}
+
+impl InventoryLauncher {
+    pub(crate) fn new(inventory: Arc<Inventory>) -> Self {
+        InventoryLauncher {
+            inventory,
+            assigned: Default::default(),
+            machines: Vec::new(),
+        }
+    }
+
+    async fn launch(&mut self, l: LaunchDescriptor<Setup>) -> Result<(), Report> {
+        use tsunami::providers::Launcher;
+
+        let max_wait = l.max_wait;
+        for (name, setup) in l.machines {
+            let host = self.inventory.assign(&name, &mut self.assigned)?.clone();
+            let host_span = tracing::debug_span!("host", name = &*name, addr = &*host.addr);
+            async {
+                tracing::trace!("connecting to existing host");
+                let bare = baremetal::Setup::new(&*host.addr, host.username.clone())
+                    .wrap_err("bad inventory address")?;
+                let mut machine = baremetal::Machine::default();
+                machine
+                    .launch(LaunchDescriptor {
+                        region: bare.region(),
+                        max_wait,
+                        machines: vec![(name.clone(), bare)],
+                    })
+                    .await
+                    .wrap_err("failed to reach host")?;
+
+                // baremetal machines do not know their private ip, so we cannot just hand the
+                // setup function to tsunami -- it needs to see the fixed-up machine.
+                if setup.cpu_options.is_some() {
+                    tracing::warn!("not limiting the cpus of an inventory host");
+                }
+                if let Some(f) = setup.procedure(false) {
+                    let mut vms = machine.connect_all().await?;
+                    let mut vm = vms.remove(&name).expect("connected to the one machine");
+                    vm.private_ip = Some(host.private_ip.clone().unwrap_or(vm.public_ip.clone()));
+                    f(&vm).await.wrap_err("setup procedure failed")?;
+                    if let Err(e) = vm.ssh.close().await {
+                        tracing::warn!("ssh connection failed: {:?}", e);
+                    }
+                }
+
+                self.machines.push((host.private_ip, machine));
+                Ok::<_, Report>(())
+            }
+            .instrument(host_span)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn connect_all(&self) -> Result<HashMap<String, tsunami::Machine<'_>>, Report> {
+        use tsunami::providers::Launcher;
+
+        let mut vms = HashMap::new();
+        for (private_ip, machine) in &self.machines {
+            for (name, mut vm) in machine.connect_all().await? {
+                vm.private_ip = Some(private_ip.clone().unwrap_or(vm.public_ip.clone()));
+                vms.insert(name, vm);
+            }
+        }
+        Ok(vms)
+    }
+}
+
+/// Runs every machine as a set of local processes in a single working directory.
+///
+/// That directory stands in for the home directory of a remote host, so it should contain the
+/// `noria` checkout and the compiled binaries in `target/release/`. Setup procedures are _not_
+/// run, so the binaries need to be built (and ZooKeeper and friends started) ahead of time.
+pub(crate) struct LocalLauncher {
+    dir: PathBuf,
+    names: Vec<String>,
+}
+
+impl LocalLauncher {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        LocalLauncher {
+            dir,
+            names: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Load an inventory file with the given contents.
+    fn load(name: &str, contents: &str) -> Result<Inventory, Report> {
+        let path = std::env::temp_dir().join(format!(
+            "orchestration-{}-{}.inventory",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        let inventory = Inventory::load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        inventory
+    }
+
+    #[test]
+    fn inventory() {
+        let inventory = load(
+            "good",
+            "\
+# role   [user@]host[:port]   [private ip]
+server   ubuntu@10.0.0.1
+
+client   lab-2.local:2222   10.0.0.2   # the fast one
+*        127.0.0.1
+",
+        )
+        .unwrap();
+        let hosts = &inventory.hosts;
+        assert_eq!(hosts.len(), 3);
+
+        assert_eq!(hosts[0].role, "server");
+        assert_eq!(hosts[0].username.as_deref(), Some("ubuntu"));
+        assert_eq!(hosts[0].addr, "10.0.0.1:22");
+        assert_eq!(hosts[0].private_ip, None);
+
+        assert_eq!(hosts[1].role, "client");
+        assert_eq!(hosts[1].username, None);
+        assert_eq!(hosts[1].addr, "lab-2.local:2222");
+        assert_eq!(hosts[1].private_ip.as_deref(), Some("10.0.0.2"));
+
+        assert_eq!(hosts[2].role, "*");
+        assert_eq!(hosts[2].addr, "127.0.0.1:22");
+    }
+
+    #[test]
+    fn bad_inventory() {
+        assert!(load("empty", "# nothing here\n\n").is_err());
+        assert!(load("missing", "server\n").is_err());
+        assert!(load("trailing", "server 10.0.0.1 10.0.0.2 extra\n").is_err());
+    }
+
+    #[test]
+    fn assign() {
+        let inventory = load(
+            "assign",
+            "server s\nclient c1\nclient c2\nclient0 pinned\n* any\n",
+        )
+        .unwrap();
+        let mut assigned = HashMap::new();
+        let mut assign = |name| inventory.assign(name, &mut assigned).unwrap().addr.clone();
+        assert_eq!(assign("server"), "s:22");
+        assert_eq!(assign("client0"), "pinned:22");
+        // clients beyond those listed go round-robin
+        assert_eq!(assign("client1"), "c1:22");
+        assert_eq!(assign("client2"), "c2:22");
+        assert_eq!(assign("client3"), "c1:22");
+        assert_eq!(assign("zookeeper"), "any:22");
+
+        let inventory = load("unassigned", "server s\n").unwrap();
+        assert!(inventory.assign("client0", &mut HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Running each load point more than once.
+//!
+//! A single noisy run on EC2 is enough to move the cliff. An experiment in a spec can instead ask
+//! for every point to be run several times:
+//!
+//! ```toml
+//! [[vote]]
+//! # ...
+//! repetitions = { runs = 3, quorum = 2, fresh-instances = true }
+//! ```
+//!
+//! A point then only counts as good if at least `quorum` of its runs were not overloaded, or a
+//! majority of them if no quorum is given. Each run writes its results under `<prefix>.run<k>`,
+//! and `<prefix>.summary.tsv` gives the mean and the 95% confidence interval of the throughput
+//! and of each sojourn latency percentile across the runs. With `fresh-instances`, every run after
+//! the first gets machines of its own, so that one bad instance cannot sway all of them.
+
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// The two-sided 95% quantiles of Student's t-distribution, by degrees of freedom.
+const T95: &[f64] = &[
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Repetitions {
+    pub(crate) runs: usize,
+    /// How many runs must not be overloaded for the point to count as good.
+    pub(crate) quorum: Option<usize>,
+    /// Whether to run each repetition on new machines.
+    #[serde(default)]
+    pub(crate) fresh_instances: bool,
+}
+
+impl Default for Repetitions {
+    fn default() -> Self {
+        Repetitions {
+            runs: 1,
+            quorum: None,
+            fresh_instances: false,
+        }
+    }
+}
+
+impl Repetitions {
+    pub(crate) fn quorum(&self) -> usize {
+        self.quorum.unwrap_or(self.runs / 2 + 1)
+    }
+
+    /// The prefixes of the runs of the point with the given prefix.
+    ///
+    /// A point that is only run once keeps its prefix as-is.
+    pub(crate) fn prefixes(&self, prefix: &str) -> Vec<String> {
+        if self.runs == 1 {
+            return vec![prefix.to_string()];
+        }
+        (1..=self.runs)
+            .map(|run| format!("{}.run{}", prefix, run))
+            .collect()
+    }
+
+    /// Whether a point passed, given whether each of its runs was good.
+    pub(crate) fn passed(&self, good: &[bool]) -> bool {
+        good.iter().filter(|&&good| good).count() >= self.quorum()
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Report> {
+        if self.runs == 0 {
+            eyre::bail!("repetitions must have at least one run");
+        }
+        if let Some(quorum) = self.quorum {
+            if quorum == 0 || quorum > self.runs {
+                eyre::bail!("quorum must be between 1 and the number of runs");
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the mean and confidence interval of every metric across the runs of a point.
+    ///
+    /// `good` says whether each of the runs at `prefixes` was good. Returns the name of the
+    /// summary file.
+    pub(crate) fn summarize(
+        &self,
+        prefix: &str,
+        prefixes: &[String],
+        good: &[bool],
+    ) -> Result<String, Report> {
+        // the values of each metric, one per run that reported it
+        let mut values: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+        for run in prefixes {
+            let log = format!("{}.log", run);
+            let log = match std::fs::read_to_string(&log) {
+                Ok(log) => log,
+                // a run that failed to prime has no log, and nothing to summarize
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).wrap_err_with(|| format!("failed to read {}", log)),
+            };
+            for (metric, value) in metrics(&log) {
+                values.entry(metric).or_default().push(value);
+            }
+        }
+
+        let file = format!("{}.summary.tsv", prefix);
+        let mut f =
+            std::fs::File::create(&file).wrap_err_with(|| format!("failed to create {}", file))?;
+        let r: Result<(), std::io::Error> = try {
+            writeln!(
+                f,
+                "# runs: {}, good: {}, quorum: {}, passed: {}",
+                good.len(),
+                good.iter().filter(|&&good| good).count(),
+                self.quorum(),
+                self.passed(good)
+            )?;
+            writeln!(f, "#metric\truns\tmean\tstddev\tci95_low\tci95_high")?;
+            for (metric, values) in &values {
+                let (mean, stddev, ci) = statistics(values);
+                let (low, high) = match ci {
+                    Some(ci) => (format!("{:.1}", mean - ci), format!("{:.1}", mean + ci)),
+                    None => (String::new(), String::new()),
+                };
+                writeln!(
+                    f,
+                    "{}\t{}\t{:.1}\t{:.1}\t{}\t{}",
+                    metric,
+                    values.len(),
+                    mean,
+                    stddev,
+                    low,
+                    high
+                )?;
+            }
+        };
+        r.wrap_err_with(|| format!("failed to write {}", file))?;
+        Ok(file)
+    }
+}
+
+/// The metrics of a single run, from its `.log`.
+///
+/// Throughput is summed across clients, in ops/s. For each sojourn latency percentile, in µs,
+/// the worst client counts.
+pub(crate) fn metrics(log: &str) -> BTreeMap<String, f64> {
+    let mut actual = None;
+    let mut generated = None;
+    let mut metrics = BTreeMap::new();
+    for line in log.lines() {
+        let rate = || line.split_whitespace().next_back()?.parse::<f64>().ok();
+        if line.starts_with("# actual ops/s") {
+            *actual.get_or_insert(0.0) += rate().unwrap_or(0.0);
+            continue;
+        } else if line.starts_with("# generated ops/s") {
+            *generated.get_or_insert(0.0) += rate().unwrap_or(0.0);
+            continue;
+        } else if line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<_> = line.split_whitespace().collect();
+        let (op, pct, us) = match fields[..] {
+            // vote: <op> <pct> <sojourn> ...
+            [op, pct, us, ..] if pct.parse::<u32>().is_ok() => (op, pct, us),
+            // lobsters: <op> sojourn <pct> <sojourn>
+            [op, "sojourn", pct, us, ..] => (op, pct, us),
+            _ => continue,
+        };
+        if let (Ok(pct), Ok(us)) = (pct.parse::<u32>(), us.parse::<f64>()) {
+            let worst = metrics
+                .entry(format!("sojourn {} p{}", op, pct))
+                .or_insert(us);
+            if us > *worst {
+                *worst = us;
+            }
+        }
+    }
+
+    if let Some(throughput) = actual.or(generated) {
+        metrics.insert("throughput".to_string(), throughput);
+    }
+    metrics
+}
+
+/// The mean and standard deviation of the given values, and the half-width of the 95%
+/// confidence interval of the mean, if there is more than one value.
+fn statistics(values: &[f64]) -> (f64, f64, Option<f64>) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    if values.len() < 2 {
+        return (mean, 0.0, None);
+    }
+
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    let stddev = variance.sqrt();
+    let t = T95.get(values.len() - 2).copied().unwrap_or(1.960);
+    (mean, stddev, Some(t * stddev / n.sqrt()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repetitions(runs: usize, quorum: Option<usize>) -> Repetitions {
+        Repetitions {
+            runs,
+            quorum,
+            fresh_instances: false,
+        }
+    }
+
+    #[test]
+    fn quorum() {
+        // a majority, unless told otherwise
+        assert_eq!(repetitions(1, None).quorum(), 1);
+        assert_eq!(repetitions(3, None).quorum(), 2);
+        assert_eq!(repetitions(4, None).quorum(), 3);
+        assert_eq!(repetitions(4, Some(2)).quorum(), 2);
+
+        let r = repetitions(3, None);
+        assert!(r.passed(&[true, false, true]));
+        assert!(!r.passed(&[true, false, false]));
+        // runs that never finished count as bad
+        assert!(!r.passed(&[true]));
+        assert!(repetitions(3, Some(1)).passed(&[false, false, true]));
+
+        assert!(repetitions(3, Some(3)).validate().is_ok());
+        assert!(repetitions(3, Some(0)).validate().is_err());
+        assert!(repetitions(3, Some(4)).validate().is_err());
+        assert!(repetitions(0, None).validate().is_err());
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(repetitions(1, None).prefixes("p"), vec!["p"]);
+        assert_eq!(
+            repetitions(3, None).prefixes("p"),
+            vec!["p.run1", "p.run2", "p.run3"]
+        );
+    }
+
+    #[test]
+    fn confidence_interval() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+        let (mean, stddev, ci) = statistics(&[5.0]);
+        assert!(close(mean, 5.0));
+        assert!(close(stddev, 0.0));
+        assert_eq!(ci, None);
+
+        // two values have one degree of freedom
+        let (mean, stddev, ci) = statistics(&[1.0, 3.0]);
+        assert!(close(mean, 2.0));
+        assert!(close(stddev, 2f64.sqrt()));
+        assert!(close(ci.unwrap(), 12.706));
+
+        let (mean, stddev, ci) = statistics(&[1.0, 2.0, 3.0]);
+        assert!(close(mean, 2.0));
+        assert!(close(stddev, 1.0));
+        assert!(close(ci.unwrap(), 4.303 / 3f64.sqrt()));
+
+        // past the end of the table, the normal distribution is close enough
+        let values: Vec<_> = (0..40).map(|v| (v % 2) as f64).collect();
+        let (_, stddev, ci) = statistics(&values);
+        assert!(close(ci.unwrap(), 1.960 * stddev / 40f64.sqrt()));
+    }
+
+    #[test]
+    fn vote_metrics() {
+        let log = "\
+# generated ops/s: 100.5
+# actual ops/s: 100.0
+# op	pct	sojourn	remote
+read	00	900	800
+read	50	1000	900
+read	95	3000	2000
+write	95	5000	4000
+# generated ops/s: 101.5
+# actual ops/s: 99.0
+# op	pct	sojourn	remote
+read	50	1200	900
+read	95	2000	2000
+write	95	7000	4000
+# server load: 10.71 8.49
+";
+        let metrics = metrics(log);
+        // the actual throughput wins over the generated one
+        assert_eq!(metrics["throughput"], 199.0);
+        // the worst client counts
+        assert_eq!(metrics["sojourn read p50"], 1200.0);
+        assert_eq!(metrics["sojourn read p95"], 3000.0);
+        assert_eq!(metrics["sojourn write p95"], 7000.0);
+    }
+
+    #[test]
+    fn lobsters_metrics() {
+        let log = "\
+# target ops/s: 2000.00
+# generated ops/s: 1999.50
+# op	metric	pct	µs
+Frontpage	sojourn	50	800
+Frontpage	sojourn	95	1500
+Frontpage	processing	95	100000
+";
+        let metrics = metrics(log);
+        // the target is not a throughput that was measured
+        assert_eq!(metrics["throughput"], 1999.5);
+        assert_eq!(metrics["sojourn Frontpage p50"], 800.0);
+        assert_eq!(metrics["sojourn Frontpage p95"], 1500.0);
+        assert_eq!(metrics.len(), 3);
+    }
+}
@@ -0,0 +1,321 @@
+//! Where results go.
+//!
+//! Every invocation of the orchestrator is a run, and each run writes the results of a benchmark
+//! to `<output>/<run>/<benchmark>/`, where `<benchmark>` is the directory of `benchmarks/results`
+//! that the analysis scripts for that benchmark live in (`vote`, `vote-redis`, `lobsters`, ...).
+//! What is about the run as a whole, like the cost report and the search state, goes directly in
+//! `<output>/<run>/`. A run is named after when it started unless `--run` names it. Every new run
+//! records its name in `<output>/latest` before its benchmarks start, and `--resume` picks up the
+//! run named there unless told which one. Targets whose results are already in the run are not
+//! run again, and nor are those in the results tree given by `--archived`, if any (see
+//! [`crate::resume`]).
+//!
+//! `orchestration archive <run> <results>` then merges a run into the results tree. Files the tree
+//! does not have yet are copied in, and identical ones are left alone. A file that differs from
+//! the one the tree already has replaces it, and the file it replaces is moved to
+//! `<benchmark>/superseded/<run>/`, or to `<benchmark>/superseded/<run>.<n>/` if the same run has
+//! already superseded a file of that name before, so nothing that was archived is ever lost. The
+//! files about the run as a whole go in `runs/<run>/`.
+
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Where the runs go unless told otherwise.
+pub(crate) const DEFAULT_OUTPUT: &str = "runs";
+
+/// The file in the output directory that names the run that started last.
+const LATEST: &str = "latest";
+
+/// The directories of a single run.
+#[derive(Debug, Clone)]
+pub(crate) struct Layout {
+    run: PathBuf,
+    archived: Option<PathBuf>,
+    /// Whether this is a new run, rather than one that is resumed.
+    fresh: bool,
+}
+
+impl Layout {
+    /// Pick the directory of this run under `output`.
+    ///
+    /// Without a `name`, a resumed run continues in the run that started last, and any other run
+    /// gets a directory of its own. Results already in the results tree at `archived` count
+    /// as done too.
+    pub(crate) fn new(
+        output: &Path,
+        name: Option<&str>,
+        resume: bool,
+        archived: Option<&Path>,
+    ) -> Result<Self, Report> {
+        let archived = archived.map(Path::to_path_buf);
+        if let Some(name) = name {
+            return Ok(Layout {
+                run: output.join(name),
+                archived,
+                fresh: !resume,
+            });
+        }
+
+        if resume {
+            let latest = output.join(LATEST);
+            match std::fs::read_to_string(&latest) {
+                Ok(name) => {
+                    let run = output.join(name.trim());
+                    tracing::info!(run = %run.display(), "resuming earlier run");
+                    return Ok(Layout {
+                        run,
+                        archived,
+                        fresh: false,
+                    });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    tracing::warn!("no earlier run to resume, starting a new one");
+                }
+                Err(e) => {
+                    return Err(e).wrap_err_with(|| format!("failed to read {}", latest.display()));
+                }
+            }
+        }
+
+        let started = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Ok(Layout {
+            run: output.join(started.to_string()),
+            archived,
+            fresh: true,
+        })
+    }
+
+    /// Create the directory of the run, and if it is a new run, record that it is the one to
+    /// resume.
+    ///
+    /// This must happen once, before any benchmark runs.
+    pub(crate) fn start(&self) -> Result<(), Report> {
+        std::fs::create_dir_all(&self.run)
+            .wrap_err_with(|| format!("failed to create {}", self.run.display()))?;
+        if !self.fresh {
+            return Ok(());
+        }
+        let (output, name) = match (self.run.parent(), self.run.file_name()) {
+            (Some(output), Some(name)) => (output, name.to_string_lossy()),
+            _ => eyre::bail!("{} does not name a run", self.run.display()),
+        };
+        let latest = output.join(LATEST);
+        std::fs::write(&latest, format!("{}\n", name))
+            .wrap_err_with(|| format!("failed to write {}", latest.display()))
+    }
+
+    /// The directory for files about the run as a whole.
+    pub(crate) fn run(&self) -> &Path {
+        &self.run
+    }
+
+    /// Create the directory for the results of the given benchmark, and return it.
+    pub(crate) fn dir(&self, benchmark: &str) -> Result<PathBuf, Report> {
+        let dir = self.run.join(benchmark);
+        std::fs::create_dir_all(&dir)
+            .wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+        Ok(dir)
+    }
+
+    /// Where the result file or prefix `name` of the given benchmark is in the archived results
+    /// tree, if there is one.
+    pub(crate) fn archived(&self, benchmark: &str, name: &str) -> Option<PathBuf> {
+        Some(self.archived.as_ref()?.join(benchmark).join(name))
+    }
+}
+
+/// What archiving did with the files of one directory.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Tally {
+    pub(crate) added: usize,
+    pub(crate) replaced: usize,
+    pub(crate) unchanged: usize,
+}
+
+/// Merge the run at `run` into the results tree at `results`.
+///
+/// Returns what happened to the files of each directory of the tree.
+pub(crate) fn archive(run: &Path, results: &Path) -> Result<BTreeMap<String, Tally>, Report> {
+    let name = run
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| eyre::eyre!("{} does not name a run", run.display()))?;
+
+    let mut tallies = BTreeMap::new();
+    for entry in
+        std::fs::read_dir(run).wrap_err_with(|| format!("failed to list {}", run.display()))?
+    {
+        let entry = entry.wrap_err("failed to list run")?;
+        let path = entry.path();
+        if path.is_dir() {
+            let benchmark = entry.file_name().to_string_lossy().into_owned();
+            let tally = tallies.entry(benchmark.clone()).or_default();
+            for file in std::fs::read_dir(&path)
+                .wrap_err_with(|| format!("failed to list {}", path.display()))?
+            {
+                let file = file.wrap_err("failed to list run")?.path();
+                if file.is_dir() {
+                    tracing::warn!(dir = %file.display(), "not archiving nested directory");
+                    continue;
+                }
+                merge(&file, &results.join(&benchmark), name, tally)?;
+            }
+        } else {
+            let runs = format!("runs/{}", name);
+            let tally = tallies.entry(runs.clone()).or_default();
+            merge(&path, &results.join(runs), name, tally)?;
+        }
+    }
+    Ok(tallies)
+}
+
+/// Copy `file` into `dir`, moving aside whatever different file of the same name is there.
+fn merge(file: &Path, dir: &Path, run: &str, tally: &mut Tally) -> Result<(), Report> {
+    let name = file.file_name().expect("listed files have names");
+    let target = dir.join(name);
+    let r: Result<(), Report> = try {
+        if target.exists() {
+            if std::fs::read(&target)? == std::fs::read(file)? {
+                tally.unchanged += 1;
+                return Ok(());
+            }
+
+            // archiving the same run again must not overwrite what it superseded the last time
+            let superseded = (0..)
+                .map(|n| match n {
+                    0 => dir.join("superseded").join(run),
+                    n => dir.join("superseded").join(format!("{}.{}", run, n)),
+                })
+                .find(|superseded| !superseded.join(name).exists())
+                .expect("some suffix is free");
+            std::fs::create_dir_all(&superseded)?;
+            tracing::debug!(
+                file = %target.display(),
+                to = %superseded.display(),
+                "superseding archived result"
+            );
+            std::fs::rename(&target, superseded.join(name))?;
+            tally.replaced += 1;
+        } else {
+            std::fs::create_dir_all(dir)?;
+            tally.added += 1;
+        }
+        std::fs::copy(file, &target)?;
+    };
+    r.wrap_err_with(|| format!("failed to archive {}", file.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh, empty directory for a test to work in.
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("orchestration-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(path: impl AsRef<Path>, contents: &str) {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn read(path: impl AsRef<Path>) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn merge() {
+        let dir = scratch("merge");
+        let results = dir.join("results");
+        write(results.join("vote/same.log"), "same");
+        write(results.join("vote/changed.log"), "old");
+
+        let run = dir.join("42");
+        write(run.join("vote/same.log"), "same");
+        write(run.join("vote/changed.log"), "new");
+        write(run.join("vote/added.log"), "added");
+        write(run.join("cost.txt"), "$1");
+
+        let tallies = archive(&run, &results).unwrap();
+        let vote = tallies["vote"];
+        assert_eq!((vote.added, vote.replaced, vote.unchanged), (1, 1, 1));
+        assert_eq!(tallies["runs/42"].added, 1);
+
+        assert_eq!(read(results.join("vote/same.log")), "same");
+        assert_eq!(read(results.join("vote/changed.log")), "new");
+        assert_eq!(read(results.join("vote/added.log")), "added");
+        assert_eq!(read(results.join("vote/superseded/42/changed.log")), "old");
+        assert_eq!(read(results.join("runs/42/cost.txt")), "$1");
+
+        // archiving again changes nothing
+        let tallies = archive(&run, &results).unwrap();
+        assert_eq!(tallies["vote"].unchanged, 3);
+        assert_eq!(tallies["vote"].replaced, 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn superseded_names() {
+        let dir = scratch("superseded");
+        let results = dir.join("results");
+        write(results.join("vote/x.log"), "0");
+
+        // the same run superseding the same file again must not lose what it superseded before
+        let run = dir.join("42");
+        for version in 1..=3 {
+            write(run.join("vote/x.log"), &version.to_string());
+            let tallies = archive(&run, &results).unwrap();
+            assert_eq!(tallies["vote"].replaced, 1);
+        }
+
+        assert_eq!(read(results.join("vote/x.log")), "3");
+        assert_eq!(read(results.join("vote/superseded/42/x.log")), "0");
+        assert_eq!(read(results.join("vote/superseded/42.1/x.log")), "1");
+        assert_eq!(read(results.join("vote/superseded/42.2/x.log")), "2");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resume_latest() {
+        let output = scratch("latest");
+
+        // nothing to resume yet
+        let first = Layout::new(&output, None, true, None).unwrap();
+        assert!(!output.join(LATEST).exists());
+        first.start().unwrap();
+        assert!(first.run().is_dir());
+
+        let resumed = Layout::new(&output, None, true, None).unwrap();
+        assert_eq!(resumed.run(), first.run());
+
+        // resuming a named run does not make it the latest
+        let named = Layout::new(&output, Some("named"), true, None).unwrap();
+        named.start().unwrap();
+        let resumed = Layout::new(&output, None, true, None).unwrap();
+        assert_eq!(resumed.run(), first.run());
+
+        // looking up where results go has no effect on which run is the latest
+        let other = Layout::new(&output, Some("other"), false, None).unwrap();
+        other.dir("vote").unwrap();
+        assert_eq!(
+            read(output.join(LATEST)).trim(),
+            first.run().file_name().unwrap()
+        );
+        other.start().unwrap();
+        assert_eq!(read(output.join(LATEST)), "other\n");
+
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
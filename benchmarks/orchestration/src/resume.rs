@@ -0,0 +1,121 @@
+//! Picking up an interrupted sweep where it left off.
+//!
+//! Every benchmark target writes its results to files named after the target's prefix in the
+//! run's directory for that benchmark (see [`crate::results`]). Once all of those files are there,
+//! every histogram decodes, and we can tell from the log whether the target was overloaded, there
+//! is no need to run that target again; its outcome can be fed straight back into the search
+//! instead. With `--archived`, targets that an earlier run already archived count as well.
+//!
+//! Logs record whether their target was overloaded in a final [`VERDICT`] line. Logs from before
+//! that line existed are judged again from what they do have, by the benchmark's default overload
+//! rules.
+
+use crate::overload::{Detector, OverloadPolicy};
+use color_eyre::{eyre, Report};
+use std::path::Path;
+
+/// The line at the end of a target's log that records whether it was overloaded.
+pub(crate) const VERDICT: &str = "# overloaded: ";
+
+/// Check whether an earlier run already produced complete results for the target with the given
+/// prefix.
+///
+/// A log without a verdict is judged by `policy`. Each client's throughput is held against
+/// `target`, or against the log's own target line if there is no `target`.
+///
+/// Returns `Some(true)` if it did and the target was not overloaded, `Some(false)` if it did and
+/// the target was overloaded, and `None` if the target still needs to be run.
+pub(crate) fn completed(
+    prefix: &str,
+    histograms: &[String],
+    statistics: bool,
+    policy: OverloadPolicy,
+    target: Option<f64>,
+) -> Option<bool> {
+    let log = format!("{}.log", prefix);
+    if !Path::new(&log).exists() {
+        return None;
+    }
+
+    let r: Result<bool, Report> = try {
+        let log = std::fs::read_to_string(&log)?;
+        let overloaded = match log
+            .lines()
+            .rev()
+            .find_map(|line| line.strip_prefix(VERDICT))
+        {
+            Some(verdict) => verdict.parse::<bool>()?,
+            None => judge(&log, policy, target)?,
+        };
+
+        for histogram in histograms {
+            if crate::validate::decode(&std::fs::read(histogram)?)? == 0 {
+                Err(eyre::eyre!("{} has no histograms", histogram))?;
+            }
+        }
+
+        if statistics {
+            let stats = std::fs::read(format!("{}-statistics.json", prefix))?;
+            serde_json::from_slice::<serde_json::Value>(&stats)?;
+        }
+
+        !overloaded
+    };
+
+    match r {
+        Ok(good) => Some(good),
+        Err(e) => {
+            tracing::debug!(%prefix, "found incomplete results: {}", e);
+            None
+        }
+    }
+}
+
+/// Decide whether the target of a log without a verdict was overloaded.
+///
+/// This only sees what the log has, so it cannot apply an experiment's overrides, and it fails if
+/// the log does not say how much throughput the clients generated.
+fn judge(log: &str, policy: OverloadPolicy, mut target: Option<f64>) -> Result<bool, Report> {
+    let mut overload = Detector::with_policy(policy);
+    let mut overloaded = false;
+    let mut generated = Vec::new();
+    for line in log.lines() {
+        let value = || line.split_whitespace().next_back().map(str::parse::<f64>);
+        if line.starts_with("# target ops/s") {
+            if let Some(Ok(t)) = value() {
+                target = target.or(Some(t));
+            }
+        } else if line.starts_with("# generated ops/s") || line.starts_with("# actual ops/s") {
+            generated.push(value().ok_or_else(|| eyre::eyre!("empty throughput line"))??);
+        } else if line.starts_with("# server crashed") {
+            overloaded = true;
+        } else if let Some(load) = line.strip_prefix("# server load: ") {
+            if let Some(Ok(load1)) = load.split_whitespace().next().map(str::parse) {
+                overloaded |= overload.server_load(load1).is_some();
+            }
+        } else if !line.starts_with('#') {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let (endpoint, pct, us) = match fields[..] {
+                // lobsters: <endpoint> <metric> <pct> <µs>, and the log-ins do not count
+                ["Login", ..] | ["Logout", ..] => continue,
+                [endpoint, "sojourn", pct, us, ..] => (endpoint, pct, us),
+                [_, "processing", ..] => continue,
+                // vote: <endpoint> <pct> <sojourn> <remote>
+                [endpoint, pct, us, _, ..] => (endpoint, pct, us),
+                _ => continue,
+            };
+            if let (Ok(pct), Ok(us)) = (pct.parse(), us.parse()) {
+                overloaded |= overload.sojourn(endpoint, pct, us).is_some();
+            }
+        }
+    }
+
+    if generated.is_empty() {
+        eyre::bail!("log has no verdict and no throughput line");
+    }
+    let target = target.ok_or_else(|| eyre::eyre!("log has no verdict and no target line"))?;
+    for rate in generated {
+        overloaded |= overload.throughput(rate, target).is_some();
+    }
+    Ok(overloaded)
+}
@@ -0,0 +1,119 @@
+//! Telling infrastructure hiccups apart from real failures.
+//!
+//! An experiment runs for hours across a handful of machines, so sooner or later an ssh connection
+//! drops, an sftp transfer fails, or EC2 runs out of capacity. None of those say anything about
+//! the benchmark, so rather than give up on the whole parameter set, we retry at the smallest
+//! [`Scope`] that is likely to fix it.
+
+use color_eyre::Report;
+use std::time::Duration;
+
+/// What needs to be redone to recover from a transient failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Scope {
+    /// Run the current target again on the same machines.
+    Target,
+    /// Reconnect to the same machines, and then run the current target again.
+    Connection,
+    /// Start over on fresh machines, and continue the search from where it was.
+    Instances,
+}
+
+/// Error messages from EC2 that mean "try again later".
+const EC2_TRANSIENT: &[&str] = &[
+    "InsufficientInstanceCapacity",
+    "InstanceLimitExceeded",
+    "RequestLimitExceeded",
+    "Unavailable",
+    "InternalError",
+    // spot request status codes
+    "capacity-not-available",
+    "capacity-oversubscribed",
+];
+
+/// Error messages from EC2 that may go away in a different availability zone.
+const EC2_ZONE_SPECIFIC: &[&str] = &[
+    "InsufficientInstanceCapacity",
+    // the instance type isn't offered in the zone
+    "Unsupported",
+    "capacity-not-available",
+];
+
+/// Decide whether the given error is worth retrying, and if so, at what scope.
+///
+/// Returns `None` for errors that are likely to happen again no matter how often we retry.
+pub(crate) fn classify(e: &Report) -> Option<Scope> {
+    let mut scope = None;
+    for cause in e.chain() {
+        let this = if let Some(e) = cause.downcast_ref::<openssh::Error>() {
+            match *e {
+                openssh::Error::Master(_)
+                | openssh::Error::Connect(_)
+                | openssh::Error::Disconnected => Some(Scope::Connection),
+                openssh::Error::Remote(_) => Some(Scope::Target),
+                // we couldn't even run ssh locally
+                openssh::Error::Ssh(_) => None,
+            }
+        } else if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            match e.kind() {
+                std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::UnexpectedEof => Some(Scope::Target),
+                _ => None,
+            }
+        } else {
+            let msg = cause.to_string();
+            if EC2_TRANSIENT.iter().any(|&code| msg.contains(code)) {
+                Some(Scope::Instances)
+            } else {
+                None
+            }
+        };
+
+        // the innermost causes are usually the most telling, but we should redo whatever the
+        // widest of them calls for
+        scope = scope.max(this);
+    }
+    scope
+}
+
+/// Decide whether the given error might not happen in a different availability zone.
+pub(crate) fn zone_specific(e: &Report) -> bool {
+    e.chain().any(|cause| {
+        let msg = cause.to_string();
+        EC2_ZONE_SPECIFIC.iter().any(|&code| msg.contains(code))
+    })
+}
+
+/// Exponentially increasing waits between a bounded number of retries.
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    delay: Duration,
+    left: usize,
+}
+
+impl Backoff {
+    pub(crate) fn new(initial: Duration, retries: usize) -> Self {
+        Backoff {
+            delay: initial,
+            left: retries,
+        }
+    }
+
+    /// Wait before the next retry.
+    ///
+    /// Returns `false`, without waiting, if we have run out of retries.
+    pub(crate) async fn wait(&mut self) -> bool {
+        if self.left == 0 {
+            return false;
+        }
+        self.left -= 1;
+
+        tracing::debug!(delay = ?self.delay, left = self.left, "backing off");
+        tokio::time::delay_for(self.delay).await;
+        self.delay *= 2;
+        true
+    }
+}
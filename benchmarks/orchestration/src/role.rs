@@ -0,0 +1,237 @@
+//! What the machines of an experiment run on.
+//!
+//! Every machine an experiment spawns plays a role: the noria `server`, a benchmark `client`, or a
+//! `backend` database that stands in for noria. Each benchmark declares what its roles need by
+//! default, and the `[roles.<role>]` tables of a spec can override any of it:
+//!
+//! ```toml
+//! [roles.backend]
+//! instance = "r5n.xlarge"
+//! cpu-options = { core-count = 2, threads-per-core = 1 }
+//!
+//! [roles.server]
+//! ami = "ami-0123456789abcdef0"
+//! username = "ec2-user"
+//! ramdisk = { path = "/mnt", size = "120G" }
+//! ```
+//!
+//! The instance types of the server and the clients are set with `server-instance` and
+//! `client-instance` instead, since those can also be set per experiment.
+
+use crate::host::Command;
+use crate::provider::Setup;
+use crate::Context;
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use serde::Deserialize;
+
+/// The roles that benchmarks assign to their machines.
+const ROLES: &[&str] = &["server", "client", "backend"];
+
+/// The AMI to use for roles that do not name one.
+pub(crate) const DEFAULT_AMI: &str = "ami-0a8091371c4d91a3e";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Role {
+    pub(crate) instance: Option<String>,
+    pub(crate) ami: Option<String>,
+    pub(crate) username: Option<String>,
+    /// A tmpfs to mount before the machine is set up.
+    pub(crate) ramdisk: Option<Ramdisk>,
+    /// Only use some of the instance's cpus.
+    pub(crate) cpu_options: Option<CpuOptions>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Ramdisk {
+    pub(crate) path: String,
+    /// Anything `mount -o size=` accepts, like `60G` or `50%`.
+    pub(crate) size: String,
+}
+
+/// The equivalent of EC2's `CpuOptions` for a machine that has already booted.
+///
+/// EC2 can only limit the cpus of an instance when it is launched, and tsunami gives us no way to
+/// ask for that. Instead, every cpu beyond the first `core-count` cores, and every hyperthread
+/// beyond the first `threads-per-core` of each core, is taken offline before the machine is set
+/// up. Either way, the instance is still billed in full.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct CpuOptions {
+    pub(crate) core_count: usize,
+    pub(crate) threads_per_core: usize,
+}
+
+impl Role {
+    /// A role that runs on the given instance type, and is otherwise left at the defaults.
+    pub(crate) fn new(instance_type: impl ToString) -> Self {
+        Role {
+            instance: Some(instance_type.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn ramdisk(self, path: impl ToString, size: impl ToString) -> Self {
+        Role {
+            ramdisk: Some(Ramdisk {
+                path: path.to_string(),
+                size: size.to_string(),
+            }),
+            ..self
+        }
+    }
+
+    pub(crate) fn cpu_options(self, core_count: usize, threads_per_core: usize) -> Self {
+        Role {
+            cpu_options: Some(CpuOptions {
+                core_count,
+                threads_per_core,
+            }),
+            ..self
+        }
+    }
+
+    /// The instance type to run this role on.
+    pub(crate) fn instance_type(&self) -> &str {
+        self.instance
+            .as_deref()
+            .expect("every role has a default instance type")
+    }
+
+    /// Replace whatever `overrides` sets.
+    fn or(self, overrides: &Role) -> Self {
+        Role {
+            instance: overrides.instance.clone().or(self.instance),
+            ami: overrides.ami.clone().or(self.ami),
+            username: overrides.username.clone().or(self.username),
+            ramdisk: overrides.ramdisk.clone().or(self.ramdisk),
+            cpu_options: overrides.cpu_options.or(self.cpu_options),
+        }
+    }
+}
+
+/// The given role as configured for this run, starting from the benchmark's `defaults`.
+pub(crate) fn resolve(ctx: &Context, role: &str, defaults: Role) -> Role {
+    if let Some(overrides) = ctx.roles.get(role) {
+        defaults.or(overrides)
+    } else {
+        defaults
+    }
+}
+
+/// Describe a machine that plays the given role.
+pub(crate) fn setup(ctx: &Context, name: &str, defaults: Role) -> Setup {
+    let role = resolve(ctx, name, defaults);
+    let mut setup = Setup::default()
+        .role(name)
+        .instance_type(role.instance_type())
+        .ami(
+            role.ami.as_deref().unwrap_or(&ctx.ami),
+            role.username.as_deref().unwrap_or("ubuntu"),
+        )
+        .availability_zone(ctx.az.clone());
+    if let Some(ramdisk) = role.ramdisk {
+        setup = setup.ramdisk(ramdisk);
+    }
+    if let Some(cpu_options) = role.cpu_options {
+        setup = setup.cpu_options(cpu_options);
+    }
+    setup
+}
+
+/// Check the role overrides of a spec.
+pub(crate) fn validate(name: &str, role: &Role) -> Result<(), Report> {
+    if !ROLES.contains(&name) {
+        eyre::bail!("unknown role '{}'", name);
+    }
+    if role.instance.is_some() && (name == "server" || name == "client") {
+        eyre::bail!(
+            "set the instance type of {0}s with {0}-instance, not in [roles.{0}]",
+            name
+        );
+    }
+    if let Some(cpu) = role.cpu_options {
+        if cpu.core_count == 0 || cpu.threads_per_core == 0 {
+            eyre::bail!("cpu-options of {} must leave at least one cpu", name);
+        }
+    }
+    Ok(())
+}
+
+impl Ramdisk {
+    pub(crate) async fn mount(&self, ssh: &openssh::Session) -> Result<(), Report> {
+        tracing::debug!(path = &*self.path, size = &*self.size, "mount ramdisk");
+        crate::output_on_success(Command::from(ssh.shell(format!(
+            "sudo mkdir -p {0} && sudo mount -t tmpfs -o size={1} tmpfs {0}",
+            self.path, self.size
+        ))))
+        .await
+        .wrap_err("mount ramdisk")?;
+        Ok(())
+    }
+}
+
+impl CpuOptions {
+    /// Take the cpus we should not use offline.
+    pub(crate) async fn apply(&self, ssh: &openssh::Session) -> Result<(), Report> {
+        let mut lscpu = Command::from(ssh.command("lscpu"));
+        lscpu.arg("-p=CPU,CORE");
+        let (topology, _) = crate::output_on_success(&mut lscpu)
+            .await
+            .wrap_err("lscpu")?;
+
+        // the threads of each core, in the order the kernel numbers them
+        let mut cores: Vec<(usize, Vec<usize>)> = Vec::new();
+        for line in String::from_utf8_lossy(&topology).lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split(',').map(|f| f.parse::<usize>());
+            let (cpu, core) = match (fields.next(), fields.next()) {
+                (Some(Ok(cpu)), Some(Ok(core))) => (cpu, core),
+                _ => eyre::bail!("bad lscpu output: {}", line),
+            };
+            if let Some((_, threads)) = cores.iter_mut().find(|(c, _)| *c == core) {
+                threads.push(cpu);
+            } else {
+                cores.push((core, vec![cpu]));
+            }
+        }
+
+        let threads = cores.iter().map(|(_, t)| t.len()).min().unwrap_or(0);
+        if cores.len() < self.core_count || threads < self.threads_per_core {
+            eyre::bail!(
+                "asked for {} cores with {} threads each, but instance has {} with {}",
+                self.core_count,
+                self.threads_per_core,
+                cores.len(),
+                threads
+            );
+        }
+
+        let offline: Vec<_> = cores
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (_, threads))| {
+                threads
+                    .iter()
+                    .enumerate()
+                    .filter(move |&(j, _)| i >= self.core_count || j >= self.threads_per_core)
+                    .map(|(_, cpu)| cpu.to_string())
+            })
+            .collect();
+        if offline.is_empty() {
+            return Ok(());
+        }
+
+        tracing::debug!(cpus = %offline.join(","), "taking cpus offline");
+        crate::output_on_success(Command::from(ssh.shell(format!(
+            "for cpu in {}; do echo 0 | sudo tee /sys/devices/system/cpu/cpu$cpu/online > /dev/null; done",
+            offline.join(" ")
+        ))))
+        .await
+        .wrap_err("take cpus offline")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+//! Watching how the processes of a benchmark use their machines over time.
+//!
+//! The `.log` of a target only has a single reading of memory use and load, taken once the
+//! clients are done. A [`Sampler`] instead records the memory use, cpu use, and context switches
+//! of a process, along with the load of its machine, every second for as long as it is watched.
+//! The samples are written to `<prefix>-resources.tsv`.
+
+use crate::host::Session;
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing_futures::Instrument;
+
+/// Prints `hz <clock ticks per second>`, and then a line for every second that it runs:
+///
+/// ```text
+/// <unix time> <load1> [<cpu ticks> <rss kB> <voluntary switches> <involuntary switches>]
+/// ```
+///
+/// The process statistics are left out whenever there is no process by the name given as `$1`.
+const SCRIPT: &str = r#"
+echo "hz $(getconf CLK_TCK)"
+pid=
+while :; do
+    if [ -z "$pid" ] || [ ! -d "/proc/$pid" ]; then
+        pid=$(pgrep -o "$1")
+    fi
+    now=$(date +%s.%N)
+    load=$(cut -d ' ' -f 1 /proc/loadavg)
+    if [ -n "$pid" ] \
+        && stat=$(cat "/proc/$pid/stat" 2> /dev/null) \
+        && status=$(cat "/proc/$pid/status" 2> /dev/null); then
+        ticks=$(echo "$stat" | awk '{print $14 + $15}')
+        rss=$(echo "$status" | awk '/^VmRSS:/ {print $2}')
+        vcsw=$(echo "$status" | awk '/^voluntary_ctxt_switches:/ {print $2}')
+        ivcsw=$(echo "$status" | awk '/^nonvoluntary_ctxt_switches:/ {print $2}')
+        echo "$now $load $ticks $rss $vcsw $ivcsw"
+    else
+        echo "$now $load"
+    fi
+    sleep 1
+done
+"#;
+
+const SCRIPT_PATH: &str = ".resources.sh";
+
+/// Samplers we never got to stop, say because the connection went away, stop on their own after
+/// this many seconds.
+const MAX_RUNTIME: usize = 3600;
+
+struct Watched<'a> {
+    host: String,
+    process: &'static str,
+    session: &'a Session,
+    /// The remote pid of the sampler.
+    pid: String,
+    /// The remote file the samples go to.
+    raw: String,
+}
+
+/// Samples processes on any number of machines until told to stop.
+pub(crate) struct Sampler<'a> {
+    prefix: String,
+    watched: Vec<Watched<'a>>,
+}
+
+/// What a [`Sampler`] saw.
+#[derive(Debug, Default)]
+pub(crate) struct Resources {
+    /// The highest resident set size of each process, in kB, by host and process name.
+    peaks: Vec<(String, &'static str, usize)>,
+}
+
+impl Resources {
+    pub(crate) fn peak_rss(&self, host: &str, process: &str) -> Option<usize> {
+        self.peaks
+            .iter()
+            .find(|&&(ref h, p, _)| h == host && p == process)
+            .map(|&(_, _, rss)| rss)
+    }
+}
+
+impl<'a> Sampler<'a> {
+    pub(crate) fn new(prefix: &str) -> Self {
+        Sampler {
+            prefix: prefix.to_string(),
+            watched: Vec::new(),
+        }
+    }
+
+    /// Start sampling the oldest process with the given name on the given host.
+    ///
+    /// The process does not need to exist yet.
+    pub(crate) async fn watch(
+        &mut self,
+        host: &str,
+        session: &'a Session,
+        process: &'static str,
+    ) -> Result<(), Report> {
+        tracing::trace!(%host, %process, "start sampling");
+        let mut upload = session
+            .shell(format!("cat > {}", SCRIPT_PATH))
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .wrap_err("upload sampler")?;
+        upload
+            .stdin()
+            .take()
+            .expect("set to piped above")
+            .write_all(SCRIPT.as_bytes())
+            .await
+            .wrap_err("write sampler")?;
+        let proc = upload.wait_with_output().await.wrap_err("upload sampler")?;
+        if !proc.status.success() {
+            return Err(eyre::eyre!(
+                String::from_utf8_lossy(&proc.stderr).to_string()
+            ))
+            .wrap_err("upload sampler failed");
+        }
+
+        // a sampler left behind by an earlier, failed, attempt would write to the same file
+        let raw = format!("resources-{}.raw", process);
+        let started = crate::output_on_success(session.shell(format!(
+            "pkill -f '^sh {0} {1}$'; \
+             nohup timeout {2} sh {0} {1} > {3} 2> /dev/null < /dev/null & echo $!",
+            SCRIPT_PATH, process, MAX_RUNTIME, raw
+        )))
+        .await
+        .wrap_err("start sampler")?;
+        let pid = String::from_utf8_lossy(&started.0).trim().to_string();
+
+        self.watched.push(Watched {
+            host: host.to_string(),
+            process,
+            session,
+            pid,
+            raw,
+        });
+        Ok(())
+    }
+
+    /// Stop sampling, and write out everything that was sampled.
+    ///
+    /// Returns the name of the file the samples were written to, if anything was sampled.
+    pub(crate) async fn finish(self) -> Result<(Option<String>, Resources), Report> {
+        let mut resources = Resources::default();
+        if self.watched.is_empty() {
+            return Ok((None, resources));
+        }
+
+        let file = format!("{}-resources.tsv", self.prefix);
+        let mut results = tokio::fs::File::create(&file)
+            .await
+            .wrap_err("failed to create local resources file")?;
+        results
+            .write_all(
+                b"#time\thost\tprocess\trss_kb\tcpu_pct\tvoluntary_csw\tinvoluntary_csw\tload1\n",
+            )
+            .await?;
+
+        for w in self.watched {
+            let sampler_span =
+                tracing::trace_span!("sampler", host = &*w.host, process = w.process);
+            async {
+                tracing::trace!("stop sampling");
+                crate::output_on_success(w.session.command("kill").arg(&w.pid))
+                    .await
+                    .wrap_err("stop sampler")?;
+
+                let mut raw = String::new();
+                w.session
+                    .read_from(&w.raw)
+                    .await
+                    .wrap_err("failed to read remote samples")?
+                    .read_to_string(&mut raw)
+                    .await
+                    .wrap_err("failed to read remote samples")?;
+
+                let (rows, peak) = parse(&raw, &w.host, w.process)?;
+                results.write_all(rows.as_bytes()).await?;
+                if let Some(peak) = peak {
+                    resources.peaks.push((w.host.clone(), w.process, peak));
+                }
+                Ok::<_, Report>(())
+            }
+            .instrument(sampler_span)
+            .await?;
+        }
+        results.flush().await?;
+        Ok((Some(file), resources))
+    }
+}
+
+/// Turn the output of [`SCRIPT`] into rows of `<prefix>-resources.tsv`.
+///
+/// Time is in seconds since the first sample, and cpu use and context switches are per interval.
+/// Also returns the highest resident set size seen.
+fn parse(raw: &str, host: &str, process: &str) -> Result<(String, Option<usize>), Report> {
+    let mut lines = raw.lines();
+    let hz: f64 = lines
+        .next()
+        .and_then(|l| l.strip_prefix("hz "))
+        .and_then(|hz| hz.parse().ok())
+        .ok_or_else(|| eyre::eyre!("sampler output is missing clock rate"))?;
+
+    let mut rows = String::new();
+    let mut peak = None;
+    let mut start = None;
+    let mut previous: Option<(f64, [f64; 3])> = None;
+    for line in lines {
+        let fields: Vec<f64> = match line.split_whitespace().map(|f| f.parse()).collect() {
+            Ok(fields) => fields,
+            // the sampler may have been killed half-way through a line
+            Err(_) => continue,
+        };
+        let (now, load) = match fields[..] {
+            [now, load, ..] => (now, load),
+            _ => continue,
+        };
+        let start = *start.get_or_insert(now);
+
+        let (rss, cpu, vcsw, ivcsw) = if let [_, _, ticks, rss, vcsw, ivcsw] = fields[..] {
+            peak = peak.max(Some(rss as usize));
+            let counters = [ticks, vcsw, ivcsw];
+            let deltas = match previous.replace((now, counters)) {
+                // the process may have restarted since the previous sample
+                Some((then, before)) if counters.iter().zip(&before).all(|(c, b)| c >= b) => {
+                    let dt = now - then;
+                    Some((
+                        (ticks - before[0]) / hz / dt * 100.0,
+                        vcsw - before[1],
+                        ivcsw - before[2],
+                    ))
+                }
+                _ => None,
+            };
+            match deltas {
+                Some((cpu, vcsw, ivcsw)) => (
+                    rss.to_string(),
+                    format!("{:.1}", cpu),
+                    vcsw.to_string(),
+                    ivcsw.to_string(),
+                ),
+                None => (rss.to_string(), String::new(), String::new(), String::new()),
+            }
+        } else {
+            previous = None;
+            Default::default()
+        };
+
+        rows.push_str(&format!(
+            "{:.3}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            now - start,
+            host,
+            process,
+            rss,
+            cpu,
+            vcsw,
+            ivcsw,
+            load
+        ));
+    }
+    Ok((rows, peak))
+}
@@ -0,0 +1,45 @@
+//! Deciding when experiments get to start.
+//!
+//! Asking EC2 for too many instances at once, or in too quick succession, tends to end in
+//! `InsufficientInstanceCapacity` or request throttling. All the experiments of a run therefore
+//! share a single [`Schedule`], even when several benchmarks run side by side.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+
+pub(crate) const DEFAULT_CONCURRENCY: usize = 3;
+pub(crate) const DEFAULT_STAGGER: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub(crate) struct Schedule {
+    slots: Arc<Semaphore>,
+    stagger: Duration,
+    last_start: Mutex<Option<Instant>>,
+}
+
+impl Schedule {
+    /// Run at most `concurrency` experiments at a time, and start them at least `stagger` apart.
+    pub(crate) fn new(concurrency: usize, stagger: Duration) -> Self {
+        Schedule {
+            slots: Arc::new(Semaphore::new(concurrency)),
+            stagger,
+            last_start: Mutex::new(None),
+        }
+    }
+
+    /// Wait until another experiment may start.
+    ///
+    /// The experiment holds on to its slot until the returned permit is dropped.
+    pub(crate) async fn start(&self) -> OwnedSemaphorePermit {
+        let permit = Arc::clone(&self.slots).acquire_owned().await;
+
+        let mut last_start = self.last_start.lock().await;
+        if let Some(wait) = last_start.and_then(|t| self.stagger.checked_sub(t.elapsed())) {
+            tokio::time::delay_for(wait).await;
+        }
+        *last_start = Some(Instant::now());
+
+        permit
+    }
+}
@@ -0,0 +1,229 @@
+//! Cliff searches that survive the orchestrator going away.
+//!
+//! A search over a single experiment can take hours, and all of its progress lives in the
+//! searcher. [`Recorded`] wraps a searcher and writes down every point it has finished, along with
+//! whether that point was overloaded, so that `--resume` can rebuild the searcher later. Since the
+//! searchers are deterministic, replaying the recorded outcomes into a fresh searcher leaves it in
+//! exactly the state it was in before.
+
+use color_eyre::{eyre, Report};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Every point the search has finished, in order.
+    visited: Vec<usize>,
+    /// The visited points that turned out to be overloaded.
+    overloaded: Vec<usize>,
+    /// The search's estimate of where the cliff is, as `[start, end)`.
+    estimate: (usize, usize),
+    /// The most recent point that was not overloaded.
+    last_good: Option<usize>,
+}
+
+/// A cliff searcher that keeps a record of its progress on disk.
+pub(crate) struct Recorded {
+    inner: Box<dyn cliff::CliffSearch + Send>,
+    path: PathBuf,
+    state: State,
+    current: Option<(usize, bool)>,
+}
+
+impl std::fmt::Debug for Recorded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorded")
+            .field("path", &self.path)
+            .field("state", &self.state)
+            .field("current", &self.current)
+            .finish()
+    }
+}
+
+/// The file in `dir` that holds the search state for the experiment with the given parameters
+/// when run according to `plan`.
+///
+/// Two spec entries can share their parameters and differ only in how they are run, so the name
+/// also carries a hash of the plan.
+fn state_file(
+    dir: &Path,
+    benchmark: &str,
+    parameters: &impl std::fmt::Debug,
+    plan: &impl std::fmt::Debug,
+) -> PathBuf {
+    // (100, "skewed", 4) => 100-skewed-4
+    let parameters = format!("{:?}", parameters);
+    let mut name = String::from(benchmark);
+    for part in parameters
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '.')
+        .filter(|part| !part.is_empty())
+    {
+        name.push('-');
+        name.push_str(part);
+    }
+    name.push_str(&format!(
+        "-{:016x}.search.json",
+        fnv1a(&format!("{:?}", plan))
+    ));
+    dir.join(name)
+}
+
+/// A hash that, unlike the one in std, stays the same across Rust releases.
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl Recorded {
+    /// Record the progress of `search` for the experiment with the given parameters and plan in
+    /// `dir`.
+    ///
+    /// If `resume` is set, and an earlier run left its search state behind, the outcomes recorded
+    /// there are first replayed into `search`. This fails if that state is from a search that was
+    /// configured differently.
+    pub(crate) fn new(
+        dir: &Path,
+        benchmark: &str,
+        parameters: &impl std::fmt::Debug,
+        plan: &impl std::fmt::Debug,
+        search: Box<dyn cliff::CliffSearch + Send>,
+        resume: bool,
+    ) -> Result<Self, Report> {
+        let mut this = Recorded {
+            inner: search,
+            path: state_file(dir, benchmark, parameters, plan),
+            state: State::default(),
+            current: None,
+        };
+
+        if resume {
+            match std::fs::read(&this.path) {
+                Ok(state) => match serde_json::from_slice(&state) {
+                    Ok(state) => this.replay(state)?,
+                    Err(e) => {
+                        tracing::warn!(path = %this.path.display(), "ignoring bad search state: {}", e)
+                    }
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    tracing::debug!(path = %this.path.display(), "no search state to resume from");
+                }
+                Err(e) => {
+                    tracing::warn!(path = %this.path.display(), "failed to read search state: {}", e)
+                }
+            }
+        }
+
+        Ok(this)
+    }
+
+    fn replay(&mut self, state: State) -> Result<(), Report> {
+        let mut fresh = State::default();
+        for &point in &state.visited {
+            let overloaded = state.overloaded.contains(&point);
+            match self.inner.next() {
+                Some(p) if p == point => {}
+                p => {
+                    // the search must have been configured differently last time around
+                    eyre::bail!(
+                        "search state in {} expected {}, but search yielded {:?}",
+                        self.path.display(),
+                        point,
+                        p
+                    );
+                }
+            }
+            if overloaded {
+                self.inner.overloaded();
+            }
+            fresh.record(point, overloaded, &*self.inner);
+        }
+
+        tracing::info!(
+            path = %self.path.display(),
+            points = fresh.visited.len(),
+            "resuming search"
+        );
+        self.state = fresh;
+        Ok(())
+    }
+
+    /// Indicate that the point last yielded by `next` was overloaded.
+    pub(crate) fn overloaded(&mut self) {
+        if let Some((_, ref mut overloaded)) = self.current {
+            *overloaded = true;
+        }
+        self.inner.overloaded();
+    }
+
+    /// Record the outcome of the point last yielded by `next`.
+    ///
+    /// A point that never finishes, like one that was interrupted, is not recorded, so that a
+    /// resumed search runs it again.
+    pub(crate) fn finished(&mut self) {
+        if let Some((point, overloaded)) = self.current.take() {
+            self.state.record(point, overloaded, &*self.inner);
+            self.save();
+        }
+    }
+
+    /// The most recent point that was not overloaded, including those of resumed runs.
+    pub(crate) fn last_good(&self) -> Option<usize> {
+        self.state.last_good
+    }
+
+    fn save(&self) {
+        let r = serde_json::to_vec_pretty(&self.state)
+            .map_err(std::io::Error::from)
+            .and_then(|state| write_atomically(&self.path, &state));
+        if let Err(e) = r {
+            tracing::warn!(path = %self.path.display(), "failed to save search state: {}", e);
+        }
+    }
+}
+
+impl State {
+    fn record(&mut self, point: usize, overloaded: bool, search: &dyn cliff::CliffSearch) {
+        self.visited.push(point);
+        if overloaded {
+            self.overloaded.push(point);
+        } else {
+            self.last_good = Some(point);
+        }
+        let estimate = search.estimate();
+        self.estimate = (estimate.start, estimate.end);
+    }
+}
+
+/// Replace the file at `path` such that a crash never leaves it half-written.
+fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(tmp, path)
+}
+
+impl Iterator for Recorded {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((point, _)) = self.current.take() {
+            tracing::warn!(point, "moving on from a point that never finished");
+        }
+
+        let next = self.inner.next();
+        if let Some(point) = next {
+            self.current = Some((point, false));
+        }
+        next
+    }
+}
+
+impl cliff::CliffSearch for Recorded {
+    fn overloaded(&mut self) {
+        Recorded::overloaded(self)
+    }
+
+    fn estimate(&self) -> std::ops::Range<usize> {
+        self.inner.estimate()
+    }
+}
@@ -1,11 +1,13 @@
 use color_eyre::{eyre, eyre::WrapErr, Report};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
 use tracing::instrument;
 
 pub(crate) fn build<'s>(
-    ssh: &'s openssh::Session,
-    host: &'s tsunami::Machine<'s>,
+    ssh: &'s crate::host::Session,
+    host: &'s crate::host::Host,
     dir: Option<&'static str>,
-) -> openssh::Command<'s> {
+) -> crate::host::Command<'s> {
     // Set up the Noria server process
     let mut cmd = crate::noria_bin(ssh, "noria-server");
     cmd.arg("--deployment")
@@ -15,16 +17,133 @@ pub(crate) fn build<'s>(
     if let Some(dir) = dir {
         cmd.arg("-C").arg(dir);
     }
+    cmd.stdout(std::process::Stdio::piped());
     cmd.stderr(std::process::Stdio::piped());
     cmd
 }
 
+/// The local file that everything the server of the given target prints goes to.
+pub(crate) fn log_file(prefix: &str) -> String {
+    format!("{}-server.log", prefix)
+}
+
+/// Collects everything the server of a target prints into `<prefix>-server.log`.
+///
+/// noria-server runs as a process of our own, so its stdout and stderr are streamed to the file
+/// as they are printed. Backends that run as system services log to the journal instead, and their
+/// entries for the duration of the target are appended once it is over.
+pub(crate) struct Log<'a> {
+    file: String,
+    session: &'a crate::host::Session,
+    services: &'static [&'static str],
+    /// When the target started, in seconds since the epoch, as far as the server is concerned.
+    since: Option<String>,
+    streaming: Option<tokio::task::JoinHandle<Result<(), std::io::Error>>>,
+}
+
+impl<'a> Log<'a> {
+    /// Start a fresh log for the server on the given host.
+    pub(crate) async fn start(
+        prefix: &str,
+        session: &'a crate::host::Session,
+        services: &'static [&'static str],
+    ) -> Result<Log<'a>, Report> {
+        let file = log_file(prefix);
+        // this also truncates whatever an earlier attempt at this target left behind
+        tokio::fs::File::create(&file)
+            .await
+            .wrap_err("failed to create local server log")?;
+
+        let since = if services.is_empty() {
+            None
+        } else {
+            let (now, _) = crate::output_on_success(session.command("date").arg("+%s"))
+                .await
+                .wrap_err("date")?;
+            Some(String::from_utf8_lossy(&now).trim().to_string())
+        };
+
+        Ok(Log {
+            file,
+            session,
+            services,
+            since,
+            streaming: None,
+        })
+    }
+
+    /// Stream the stdout and stderr of the given server process to the log.
+    pub(crate) fn capture(&mut self, server: &mut crate::host::Child<'_>) {
+        use tokio::stream::StreamExt;
+
+        let stdout = server.stdout().take().expect("server stdout is piped");
+        let stderr = server.stderr().take().expect("server stderr is piped");
+        let file = self.file.clone();
+        self.streaming = Some(tokio::spawn(async move {
+            let mut log = tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(&file)
+                .await?;
+            let mut lines = tokio::io::BufReader::new(stdout)
+                .lines()
+                .merge(tokio::io::BufReader::new(stderr).lines());
+            while let Some(line) = lines.next().await {
+                log.write_all(line?.as_bytes()).await?;
+                log.write_all(b"\n").await?;
+            }
+            log.flush().await
+        }));
+    }
+
+    /// Finish writing the log, once the server has stopped.
+    #[instrument(level = "trace", name = "server_log", skip(self))]
+    pub(crate) async fn finish(self) -> Result<(), Report> {
+        if let Some(streaming) = self.streaming {
+            // the output ends when the server exits, but a server we failed to stop may linger
+            match tokio::time::timeout(Duration::from_secs(10), streaming).await {
+                Ok(written) => written
+                    .wrap_err("server log writer panicked")?
+                    .wrap_err("failed to write server log")?,
+                Err(_) => tracing::warn!("server output did not end; its log may be incomplete"),
+            }
+        }
+
+        if let Some(since) = self.since {
+            tracing::trace!(services = ?self.services, "fetch service logs");
+            let mut journalctl = self.session.command("sudo");
+            journalctl
+                .arg("journalctl")
+                .arg("--no-pager")
+                .arg("--output=short-precise")
+                .arg(format!("--since=@{}", since));
+            for service in self.services {
+                journalctl.arg("-u").arg(service);
+            }
+            let (entries, _) = crate::output_on_success(&mut journalctl)
+                .await
+                .wrap_err("journalctl")?;
+
+            let mut log = tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(&self.file)
+                .await
+                .wrap_err("failed to open local server log")?;
+            log.write_all(&entries)
+                .await
+                .wrap_err("failed to write server log")?;
+            log.flush().await?;
+        }
+        Ok(())
+    }
+}
+
 #[instrument(level = "trace", skip(ssh, server))]
 pub(crate) async fn stop(
-    ssh: &openssh::Session,
-    mut server: openssh::RemoteChild<'_>,
+    ssh: &crate::host::Session,
+    mut server: crate::host::Child<'_>,
 ) -> Result<(), Report> {
-    let mut stderr = server.stderr().take().unwrap();
+    // the output of the server usually goes to its log instead
+    let stderr = server.stderr().take();
     let srv_exit: Result<_, Report> = try {
         // Check if the server already stopped
         tracing::trace!("check for early termination");
@@ -37,7 +156,7 @@ pub(crate) async fn stop(
                 // openssh::Error::Disconnected. We want to still error if the entire ssh connection
                 // went away, but if it's _just_ this one, we can keep going.
                 if let Err(e) = ssh.check().await {
-                    return Err(e.into());
+                    return Err(e);
                 } else {
                     // The process was terminated prematurely by a signal?
                     // OOM maybe?
@@ -137,10 +256,13 @@ pub(crate) async fn stop(
     if !srv_exit?.success() {
         use tokio::io::AsyncReadExt;
         let mut s = String::new();
-        let e = match stderr.read_to_string(&mut s).await {
-            Ok(_) if s.is_empty() => Err(eyre::eyre!("stderr empty")),
-            Ok(_) => Err(eyre::eyre!(s)),
-            Err(e) => Err(eyre::eyre!(e)).wrap_err("stderr unavailable"),
+        let e = match stderr {
+            None => Err(eyre::eyre!("stderr is in the server log")),
+            Some(mut stderr) => match stderr.read_to_string(&mut s).await {
+                Ok(_) if s.is_empty() => Err(eyre::eyre!("stderr empty")),
+                Ok(_) => Err(eyre::eyre!(s)),
+                Err(e) => Err(eyre::eyre!(e)).wrap_err("stderr unavailable"),
+            },
         };
         return e.wrap_err("noria-server exited with an error");
     }
@@ -154,8 +276,8 @@ pub(crate) async fn stop(
 
 #[instrument(level = "trace", skip(ssh, w))]
 pub(crate) async fn write_stats(
-    ssh: &openssh::Session,
-    server: &tsunami::Machine<'_>,
+    ssh: &crate::host::Session,
+    server: &crate::host::Host,
     w: &mut (impl tokio::io::AsyncWrite + Unpin),
 ) -> Result<bool, Report> {
     let mut curl = ssh
@@ -192,17 +314,22 @@ pub(crate) async fn write_stats(
     Ok(false)
 }
 
-#[instrument(level = "debug", skip(ssh))]
-pub(crate) async fn vmrss_for(ssh: &openssh::Session, process: &str) -> Result<usize, Report> {
+/// The pid of the oldest process with the given name.
+#[instrument(level = "trace", skip(ssh))]
+pub(crate) async fn pid_of(ssh: &crate::host::Session, process: &str) -> Result<usize, Report> {
     let pid = crate::output_on_success(ssh.command("pgrep").arg("-o").arg(process))
         .await
         .wrap_err("pgrep")?;
     let pid = String::from_utf8_lossy(&pid.0);
-    let pid: usize = match pid.trim().parse() {
-        Ok(pid) => pid,
-        Err(_) => Err(eyre::eyre!(pid.to_string()).wrap_err("failed to parse server pid"))?,
-    };
+    match pid.trim().parse() {
+        Ok(pid) => Ok(pid),
+        Err(_) => Err(eyre::eyre!(pid.to_string()).wrap_err("failed to parse server pid")),
+    }
+}
 
+#[instrument(level = "debug", skip(ssh))]
+pub(crate) async fn vmrss_for(ssh: &crate::host::Session, process: &str) -> Result<usize, Report> {
+    let pid = pid_of(ssh, process).await?;
     let vmrss = crate::output_on_success(ssh.shell(format!("grep VmRSS /proc/{}/status", pid)))
         .await
         .wrap_err("grep VmRSS")?;
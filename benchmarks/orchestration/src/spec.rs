@@ -0,0 +1,357 @@
+//! Experiment plans loaded from a spec file.
+//!
+//! By default, each benchmark runs the parameter sets hard-coded in its `main`. A spec file
+//! replaces those for any benchmark that has a section in it, so that plans can be shared and
+//! versioned without touching the orchestrator. A spec is a TOML file that looks like this:
+//!
+//! ```toml
+//! # optional, override --server-instance and --client-instance
+//! server-instance = "r5n.4xlarge"
+//! client-instance = "m5n.4xlarge"
+//! # optional, override --concurrency, --stagger, and --parallel-benchmarks
+//! concurrency = 6
+//! stagger = 5
+//! parallel-benchmarks = true
+//! # optional, overrides --spot
+//! spot = true
+//! # optional, overrides --ami
+//! ami = "ami-0a8091371c4d91a3e"
+//! # optional, overrides --sample-clients
+//! sample-clients = true
+//!
+//! # optional, see the role module
+//! [roles.backend]
+//! instance = "r5n.large"
+//! cpu-options = { core-count = 1, threads-per-core = 1 }
+//!
+//! [[vote]]
+//! write-every = 100
+//! distribution = "skewed"
+//! clients = 4
+//! partial = true
+//! memlimit = 268435456
+//! join = true
+//! durable = false
+//! # everything below is optional
+//! server-instance = "r5n.8xlarge"
+//! spot = false
+//! profile = { after = 150, seconds = 30 }
+//! overload = { min-throughput = 0.9 }
+//! repetitions = { runs = 3, quorum = 2 }
+//! steady-state = { max-runtime = 240 }
+//! search = { exponential = { start = 250000, min-width = 125000, fill-left = true } }
+//! skip = [250000, 500000]
+//! known-bad = [4000000]
+//!
+//! [[lobsters-noria]]
+//! shards = 0
+//! partial = true
+//! memlimit = 0
+//! durable = false
+//! search = { loads = [2000, 4000] }
+//! ```
+//!
+//! Each benchmark module defines the named parameters it accepts as a `Parameters` type, and
+//! every entry is checked against it when the spec is loaded. The keys that are shared by all
+//! benchmarks end up in a [`Plan`].
+
+use crate::Context;
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Keys that configure how an experiment is run rather than what is run.
+const PLAN_KEYS: &[&str] = &[
+    "search",
+    "skip",
+    "known-bad",
+    "server-instance",
+    "client-instance",
+    "spot",
+    "profile",
+    "overload",
+    "repetitions",
+    "steady-state",
+];
+
+/// How to pick the load points of an experiment.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) enum Search {
+    /// Run exactly these points, in order, until one is overloaded.
+    Loads(Vec<usize>),
+    /// Double the load until overloaded, then narrow in on the cliff.
+    #[serde(rename_all = "kebab-case")]
+    Exponential {
+        start: usize,
+        min_width: usize,
+        #[serde(default)]
+        fill_left: bool,
+    },
+    /// Halve the load until it works, then narrow in on the smallest working point.
+    #[serde(rename_all = "kebab-case")]
+    BinaryMin { start: usize, min_width: usize },
+}
+
+impl Search {
+    pub(crate) fn searcher(&self) -> Box<dyn cliff::CliffSearch + Send> {
+        match *self {
+            Search::Loads(ref loads) => Box::new(cliff::LoadIterator::from(loads.clone())),
+            Search::Exponential {
+                start,
+                min_width,
+                fill_left,
+            } => {
+                let mut s = cliff::ExponentialCliffSearcher::until(start, min_width);
+                if fill_left {
+                    s.fill_left();
+                }
+                Box::new(s)
+            }
+            Search::BinaryMin { start, min_width } => {
+                Box::new(cliff::BinaryMinSearcher::until(start, min_width))
+            }
+        }
+    }
+
+    /// Guess which points this search will visit.
+    ///
+    /// Cliff searches only end once they find the cliff, so for those we have to assume where it
+    /// is. The assumption, if any, is returned alongside the points.
+    pub(crate) fn expected(&self) -> (Vec<usize>, Option<String>) {
+        let (overloaded, assuming): (Box<dyn Fn(usize) -> bool>, _) = match *self {
+            Search::Loads(ref loads) => return (loads.clone(), None),
+            Search::Exponential { start, .. } => {
+                let cliff = 8 * start;
+                (
+                    Box::new(move |point| point > cliff),
+                    format!("assuming the cliff is at {}", cliff),
+                )
+            }
+            Search::BinaryMin { start, .. } => {
+                let min = start / 8;
+                (
+                    Box::new(move |point| point < min),
+                    format!("assuming the minimum is at {}", min),
+                )
+            }
+        };
+
+        let mut search = self.searcher();
+        let mut points = Vec::new();
+        // searches that never converge should not make us loop forever
+        while let Some(point) = search.next().filter(|_| points.len() < 100) {
+            points.push(point);
+            if overloaded(point) {
+                search.overloaded();
+            }
+        }
+        (points, Some(assuming))
+    }
+}
+
+/// The benchmark-independent part of a single experiment.
+///
+/// The default plan leaves every decision to the benchmark itself.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Plan {
+    pub(crate) search: Option<Search>,
+    /// Load points to treat as good without running them.
+    #[serde(default)]
+    pub(crate) skip: Vec<usize>,
+    /// Load points that are known to be overloaded.
+    #[serde(default)]
+    pub(crate) known_bad: Vec<usize>,
+    pub(crate) server_instance: Option<String>,
+    pub(crate) client_instance: Option<String>,
+    /// Whether to run on spot instances.
+    pub(crate) spot: Option<bool>,
+    /// Which part of the benchmark to profile the server for.
+    pub(crate) profile: Option<crate::profile::Window>,
+    /// When targets count as overloaded, see the overload module.
+    #[serde(default)]
+    pub(crate) overload: crate::overload::OverloadPolicy,
+    /// How many times to run each point, see the repeat module.
+    #[serde(default)]
+    pub(crate) repetitions: crate::repeat::Repetitions,
+    /// Measure for only as long as needed, see the steady module.
+    pub(crate) steady_state: Option<crate::steady::SteadyState>,
+}
+
+impl Plan {
+    /// Decide whether to run the given load point at all.
+    ///
+    /// Returns `Some(true)` if the point should be skipped as if it succeeded, and `Some(false)`
+    /// if it should be skipped as if it was overloaded.
+    pub(crate) fn skip(&self, point: usize) -> Option<bool> {
+        if self.known_bad.contains(&point) {
+            Some(false)
+        } else if self.skip.contains(&point) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// The search to use for this experiment, falling back to the benchmark's own.
+    pub(crate) fn search_or(&self, default: impl FnOnce() -> Search) -> Search {
+        self.search.clone().unwrap_or_else(default)
+    }
+
+    /// Produce the context to run this experiment in.
+    pub(crate) fn context(&self, ctx: &Context) -> Context {
+        let mut ctx = ctx.clone();
+        if let Some(ref server) = self.server_instance {
+            ctx.server_type = server.clone();
+        }
+        if let Some(ref client) = self.client_instance {
+            ctx.client_type = client.clone();
+        }
+        if let Some(spot) = self.spot {
+            ctx.spot = spot;
+        }
+        if let Some(profile) = self.profile {
+            ctx.profile = Some(profile);
+        }
+        ctx.overload = self.overload.clone();
+        if let Some(steady_state) = self.steady_state {
+            ctx.steady_state = Some(steady_state);
+        }
+        ctx
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Spec {
+    pub(crate) server_instance: Option<String>,
+    pub(crate) client_instance: Option<String>,
+    pub(crate) concurrency: Option<usize>,
+    /// In seconds.
+    pub(crate) stagger: Option<u64>,
+    pub(crate) parallel_benchmarks: Option<bool>,
+    pub(crate) spot: Option<bool>,
+    pub(crate) ami: Option<String>,
+    pub(crate) sample_clients: Option<bool>,
+    #[serde(default)]
+    pub(crate) roles: BTreeMap<String, crate::role::Role>,
+    #[serde(flatten)]
+    benchmarks: BTreeMap<String, Vec<toml::value::Table>>,
+}
+
+impl Spec {
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Self, Report> {
+        let path = path.as_ref();
+        let spec = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read spec {}", path.display()))?;
+        toml::from_str(&spec).wrap_err_with(|| format!("failed to parse spec {}", path.display()))
+    }
+
+    /// The benchmarks that have a section in this spec.
+    pub(crate) fn benchmarks(&self) -> impl Iterator<Item = &str> {
+        self.benchmarks.keys().map(|b| &**b)
+    }
+
+    /// Parse the experiments listed for the given benchmark.
+    ///
+    /// Returns `None` if the spec has no section for the benchmark.
+    pub(crate) fn experiments<P: DeserializeOwned>(
+        &self,
+        benchmark: &str,
+    ) -> Result<Option<Vec<(P, Plan)>>, Report> {
+        let entries = if let Some(entries) = self.benchmarks.get(benchmark) {
+            entries
+        } else {
+            return Ok(None);
+        };
+
+        entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let mut parameters = entry.clone();
+                let mut plan = toml::value::Table::new();
+                for &key in PLAN_KEYS {
+                    if let Some(v) = parameters.remove(key) {
+                        plan.insert(key.to_string(), v);
+                    }
+                }
+                let r: Result<_, Report> = try {
+                    let parameters = toml::Value::Table(parameters)
+                        .try_into()
+                        .wrap_err("bad parameters")?;
+                    let plan: Plan = toml::Value::Table(plan).try_into().wrap_err("bad plan")?;
+                    plan.repetitions.validate().wrap_err("bad plan")?;
+                    if let Some(steady_state) = plan.steady_state {
+                        steady_state.validate().wrap_err("bad plan")?;
+                    }
+                    (parameters, plan)
+                };
+                r.wrap_err_with(|| format!("in {} experiment #{}", benchmark, i + 1))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some)
+    }
+}
+
+/// Look up the experiments the spec (if any) lists for the given benchmark.
+pub(crate) fn experiments<P: DeserializeOwned, T>(
+    ctx: &Context,
+    benchmark: &str,
+    into: impl Fn(P) -> T,
+) -> Result<Option<Vec<(T, Plan)>>, Report> {
+    let spec = if let Some(ref spec) = ctx.spec {
+        spec
+    } else {
+        return Ok(None);
+    };
+
+    Ok(spec.experiments(benchmark)?.map(|experiments| {
+        experiments
+            .into_iter()
+            .map(|(parameters, plan)| (into(parameters), plan))
+            .collect()
+    }))
+}
+
+/// Check that every section of the spec is valid for the benchmark it names.
+pub(crate) fn validate(spec: &Spec) -> Result<(), Report> {
+    if spec.concurrency == Some(0) {
+        eyre::bail!("concurrency must be at least 1");
+    }
+    for (name, role) in &spec.roles {
+        crate::role::validate(name, role)?;
+    }
+
+    for benchmark in spec.benchmarks() {
+        match benchmark {
+            "vote" => spec
+                .experiments::<crate::vote::Parameters>(benchmark)
+                .map(drop),
+            "vote-memory" => spec
+                .experiments::<crate::vote_mem::Parameters>(benchmark)
+                .map(drop),
+            "vote-redis" => spec
+                .experiments::<crate::vote_redis::Parameters>(benchmark)
+                .map(drop),
+            "vote-hybrid" => spec
+                .experiments::<crate::vote_hybrid::Parameters>(benchmark)
+                .map(drop),
+            "lobsters-mysql" => spec
+                .experiments::<crate::lobsters_mysql::Parameters>(benchmark)
+                .map(drop),
+            "lobsters-noria" => spec
+                .experiments::<crate::lobsters_noria::Parameters>(benchmark)
+                .map(drop),
+            "lobsters-noria-memory" => spec
+                .experiments::<crate::lobsters_noria_mem::Parameters>(benchmark)
+                .map(drop),
+            "vote-migration" => Err(eyre::eyre!("vote-migration takes no parameters")),
+            _ => Err(eyre::eyre!("unknown benchmark '{}'", benchmark)),
+        }?;
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! Running experiments on spot instances.
+//!
+//! Spot instances are much cheaper, but EC2 may reclaim them at any time, and defined-duration
+//! ones go away after at most six hours no matter what. A target that was running when that
+//! happened tells us nothing about the benchmark, so its results are thrown away and the target is
+//! run again on fresh instances. If the instances keep being reclaimed, we wait longer and longer
+//! for spot capacity to come back before trying again, and give up after [`REPROVISIONS`] tries.
+//!
+//! Noticing that instances are going away does not depend on how long they were asked to live:
+//! [`interrupted`] checks for EC2's interruption notice, which regular spot instances get too, and
+//! [`watch`] keeps checking for it while a target runs so that the target is cut short. The
+//! version of tsunami we use can only request defined-duration spot instances, though, so that is
+//! what `--spot` asks for until it can request regular ones.
+
+use crate::experiment::Machines;
+use color_eyre::{eyre::WrapErr, Report};
+use std::path::Path;
+use std::time::Duration;
+use tsunami::providers::aws;
+
+/// The longest EC2 lets defined-duration spot instances live.
+const SPOT_HOURS: usize = 6;
+
+/// How often to replace reclaimed instances before giving up on an experiment.
+pub(crate) const REPROVISIONS: usize = 5;
+
+/// How long to wait for spot capacity before the first replacement; this doubles every time.
+pub(crate) const REPROVISION_DELAY: Duration = Duration::from_secs(2 * 60);
+
+/// How often to check for interruptions while a target runs; EC2 gives two minutes of notice.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Instance metadata that only exists once an instance has been told it will be reclaimed.
+const INSTANCE_ACTION: &str = "http://169.254.169.254/latest/meta-data/spot/instance-action";
+
+pub(crate) fn launch_mode(spot: bool) -> aws::LaunchMode {
+    if spot {
+        aws::LaunchMode::duration_spot(SPOT_HOURS)
+    } else {
+        aws::LaunchMode::on_demand()
+    }
+}
+
+/// The error returned when a target was cut short because its machines were reclaimed.
+#[derive(Debug)]
+pub(crate) struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "spot instances were reclaimed")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Check whether any of the machines has been, or is about to be, reclaimed.
+pub(crate) async fn interrupted(machines: &Machines) -> bool {
+    for (name, host) in machines.iter() {
+        if let Err(e) = host.session.check().await {
+            tracing::warn!(%name, "lost machine: {:?}", e);
+            return true;
+        }
+
+        let notice = host
+            .session
+            .command("curl")
+            .arg("-sf")
+            .arg("--max-time")
+            .arg("5")
+            .arg(INSTANCE_ACTION)
+            .output()
+            .await;
+        match notice {
+            Ok(notice) if notice.status.success() => {
+                let notice = String::from_utf8_lossy(&notice.stdout);
+                tracing::warn!(%name, %notice, "machine is being reclaimed");
+                return true;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(%name, "lost machine: {:?}", e);
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Wait until any of the machines has been, or is about to be, reclaimed.
+pub(crate) async fn watch(machines: &Machines) {
+    loop {
+        tokio::time::delay_for(POLL_INTERVAL).await;
+        if interrupted(machines).await {
+            return;
+        }
+    }
+}
+
+/// Remove whatever results a target with the given prefix managed to write.
+pub(crate) fn discard(prefix: &str) -> Result<(), Report> {
+    let prefix = Path::new(prefix);
+    let dir = match prefix.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let prefix = prefix
+        .file_name()
+        .and_then(|prefix| prefix.to_str())
+        .expect("prefixes are file names");
+    for entry in std::fs::read_dir(dir).wrap_err("failed to list results")? {
+        let entry = entry.wrap_err("failed to list results")?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        // <prefix>.log, <prefix>-client0.hist, <prefix>-statistics.json, ...
+        let ours = name
+            .strip_prefix(prefix)
+            .map_or(false, |rest| rest.starts_with('.') || rest.starts_with('-'));
+        if ours {
+            tracing::debug!(file = %name, "discarding partial result");
+            std::fs::remove_file(entry.path())
+                .wrap_err_with(|| format!("failed to remove {}", name))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,409 @@
+//! Measuring only for as long as a target needs.
+//!
+//! By default, every target measures for a fixed 320 seconds, which is far more than a lightly
+//! loaded server needs to settle. In adaptive mode, the clients instead first run short warm-up
+//! windows at the target load until the throughput and the 95th percentile sojourn latency of the
+//! last few windows agree. The clients then measure for at most `max-runtime` seconds, and report
+//! on every `window` seconds of the measurement as they go. Once they have measured for at least
+//! `min-runtime` seconds, and the 95% confidence intervals of the throughput and the latency of the
+//! windows measured so far are within `tolerance` of their means, the clients are told to stop
+//! early. A target that never settles is measured for the full `max-runtime`.
+//!
+//! The clients report on a window with a [`INTERVAL`] line giving the window's throughput in ops/s
+//! and its worst 95th percentile sojourn latency in µs, and they wrap up as if their runtime had
+//! run out when they get `SIGINT`. Clients that do not list `--report-interval` in their `--help`
+//! are measured for the fixed durations instead, and a measurement that shows the clients do not
+//! behave as they should fails rather than produce results that cannot be trusted.
+//!
+//! Adaptive mode is turned on with `--steady-state`, or per experiment in a spec:
+//!
+//! ```toml
+//! [[vote]]
+//! # ...
+//! steady-state = { window = 20, tolerance = 0.05, max-warmup = 300, max-runtime = 320 }
+//! ```
+
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// How many windows in a row must agree for a target to count as steady.
+const STEADY_WINDOWS: usize = 3;
+
+/// How a client's report on a window of the measurement starts.
+pub(crate) const INTERVAL: &str = "# interval: ";
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case", default)]
+pub(crate) struct SteadyState {
+    /// The length of each warm-up window, in seconds.
+    pub(crate) window: u64,
+    /// How far, relative to the mean, measurements may stray and still count as steady.
+    pub(crate) tolerance: f64,
+    /// The most time to spend warming up, in seconds.
+    pub(crate) max_warmup: u64,
+    pub(crate) min_runtime: u64,
+    pub(crate) max_runtime: u64,
+}
+
+impl Default for SteadyState {
+    fn default() -> Self {
+        SteadyState {
+            window: 20,
+            tolerance: 0.05,
+            max_warmup: 300,
+            min_runtime: 60,
+            max_runtime: 320,
+        }
+    }
+}
+
+/// What warming up found.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Warmup {
+    pub(crate) windows: usize,
+    pub(crate) steady: bool,
+}
+
+/// Watches the windows the clients report on while they measure, and decides when to stop.
+#[derive(Debug)]
+pub(crate) struct Measurement {
+    steady_state: SteadyState,
+    /// Whether the target was steady after warming up; if not, it is measured for `max-runtime`.
+    steady: bool,
+    started: Instant,
+    throughput: Vec<f64>,
+    p95: Vec<f64>,
+}
+
+/// How the measurement went.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Measured {
+    pub(crate) windows: usize,
+    /// Whether the measurement stopped early because the windows agreed.
+    pub(crate) converged: bool,
+    /// How long the clients measured for, in seconds.
+    pub(crate) runtime: u64,
+}
+
+impl SteadyState {
+    /// Warm up until the target is steady.
+    ///
+    /// `window` runs the clients for the given number of seconds, and returns their output.
+    pub(crate) async fn warm_up<'a>(
+        &self,
+        mut window: impl FnMut(u64) -> BoxFuture<'a, Result<String, Report>>,
+    ) -> Result<Warmup, Report> {
+        let mut samples = Vec::new();
+        let mut windows = 0;
+        while (windows + 1) as u64 * self.window <= self.max_warmup {
+            windows += 1;
+            let output = window(self.window).await?;
+            let metrics = crate::repeat::metrics(&output);
+            // the clients report one p95 per operation, and the worst of them counts
+            let p95 = metrics
+                .iter()
+                .filter(|(metric, _)| metric.starts_with("sojourn ") && metric.ends_with(" p95"))
+                .map(|(_, &us)| us)
+                .fold(None, |worst: Option<f64>, us| {
+                    Some(worst.map_or(us, |w| w.max(us)))
+                });
+            match (metrics.get("throughput"), p95) {
+                (Some(&throughput), Some(p95)) => samples.push((throughput, p95)),
+                _ => {
+                    tracing::debug!(windows, "warm-up window reported nothing");
+                    samples.clear();
+                    continue;
+                }
+            }
+
+            let last = &samples[samples.len().saturating_sub(STEADY_WINDOWS)..];
+            if last.len() < STEADY_WINDOWS {
+                continue;
+            }
+            let throughput: Vec<_> = last.iter().map(|&(t, _)| t).collect();
+            let p95: Vec<_> = last.iter().map(|&(_, l)| l).collect();
+            if self.agree(&throughput) && self.agree(&p95) {
+                tracing::debug!(windows, "target is steady");
+                return Ok(Warmup {
+                    windows,
+                    steady: true,
+                });
+            }
+        }
+
+        tracing::warn!(windows, "target never became steady");
+        Ok(Warmup {
+            windows,
+            steady: false,
+        })
+    }
+
+    /// Start watching a measurement that follows the given warm-up.
+    ///
+    /// The clients should be started with [`SteadyState::client_args`] right after.
+    pub(crate) fn measure(&self, warmup: &Warmup) -> Measurement {
+        Measurement {
+            steady_state: *self,
+            steady: warmup.steady,
+            started: Instant::now(),
+            throughput: Vec::new(),
+            p95: Vec::new(),
+        }
+    }
+
+    /// The arguments that make a client measure for at most `max-runtime`, and report on every
+    /// window as it goes.
+    pub(crate) fn client_args(&self) -> [String; 2] {
+        [
+            format!("--runtime={}", self.max_runtime),
+            format!("--report-interval={}", self.window),
+        ]
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Report> {
+        if self.window == 0 || self.window > self.max_warmup {
+            eyre::bail!("steady-state window must be between 1 and max-warmup seconds");
+        }
+        if self.tolerance.is_nan() || self.tolerance <= 0.0 {
+            eyre::bail!("steady-state tolerance must be positive");
+        }
+        if self.min_runtime > self.max_runtime {
+            eyre::bail!("steady-state min-runtime cannot be above max-runtime");
+        }
+        Ok(())
+    }
+
+    /// Whether all the values are within the tolerance of their mean.
+    fn agree(&self, values: &[f64]) -> bool {
+        let mean = mean(values);
+        values
+            .iter()
+            .all(|v| (v - mean).abs() <= self.tolerance * mean.abs())
+    }
+
+    /// Whether the 95% confidence interval of the mean of the values is within the tolerance of
+    /// that mean.
+    fn confident(&self, values: &[f64]) -> bool {
+        if values.len() < 2 {
+            return false;
+        }
+        let mean = mean(values);
+        let variance =
+            values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+        let ci = 1.96 * (variance / values.len() as f64).sqrt();
+        ci <= self.tolerance * mean.abs()
+    }
+}
+
+/// Whether the given client can report on windows while it measures.
+pub(crate) async fn client_supports(mut client: crate::host::Command<'_>) -> Result<bool, Report> {
+    let help = client
+        .arg("--help")
+        .output()
+        .await
+        .wrap_err("failed to ask client for its arguments")?;
+    let help = String::from_utf8_lossy(&help.stdout) + String::from_utf8_lossy(&help.stderr);
+    Ok(help.contains("--report-interval"))
+}
+
+impl Measurement {
+    /// Take in a line of client output, and say whether the clients have measured for long
+    /// enough.
+    pub(crate) fn line(&mut self, line: &str) -> bool {
+        let report = match line.strip_prefix(INTERVAL) {
+            Some(report) => report,
+            None => return false,
+        };
+        let mut fields = report.split_whitespace().map(str::parse::<f64>);
+        match (fields.next(), fields.next()) {
+            (Some(Ok(throughput)), Some(Ok(p95))) => {
+                self.throughput.push(throughput);
+                self.p95.push(p95);
+            }
+            _ => {
+                tracing::warn!(%line, "bad interval report");
+                return false;
+            }
+        }
+
+        let config = &self.steady_state;
+        let enough = self.steady
+            && self.started.elapsed().as_secs() >= config.min_runtime
+            && config.confident(&self.throughput)
+            && config.confident(&self.p95);
+        if enough {
+            tracing::debug!(
+                windows = self.throughput.len(),
+                "measurement is tight enough"
+            );
+        }
+        enough
+    }
+
+    /// Finish watching, once the clients have stopped.
+    pub(crate) fn finish(self, converged: bool) -> Measured {
+        Measured {
+            windows: self.throughput.len(),
+            converged,
+            runtime: self.started.elapsed().as_secs(),
+        }
+    }
+}
+
+impl Measured {
+    /// Check that the clients did what adaptive mode relies on them to do.
+    ///
+    /// `reported` is whether the clients exited cleanly with their results.
+    pub(crate) fn check(&self, reported: bool) -> Result<(), Report> {
+        if self.converged && !reported {
+            eyre::bail!("clients did not report their results when told to wrap up early");
+        }
+        if !self.converged && reported && self.windows == 0 {
+            eyre::bail!(
+                "clients measured for {}s without reporting on any window",
+                self.runtime
+            );
+        }
+        Ok(())
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::FutureExt;
+
+    /// Client output for a window with the given throughput and 95th percentile latency.
+    fn window(throughput: f64, p95: f64) -> String {
+        format!(
+            "# generated ops/s: {0}\n# actual ops/s: {0}\nread\t50\t100\t100\nread\t95\t{1}\t{1}\n",
+            throughput, p95
+        )
+    }
+
+    async fn warm_up(config: SteadyState, windows: Vec<String>) -> (Warmup, usize) {
+        let mut windows = windows.into_iter();
+        let mut ran = 0;
+        let warmup = config
+            .warm_up(|seconds| {
+                assert_eq!(seconds, config.window);
+                ran += 1;
+                let output = windows.next().expect("ran more windows than expected");
+                async move { Ok::<_, Report>(output) }.boxed()
+            })
+            .await
+            .unwrap();
+        (warmup, ran)
+    }
+
+    #[tokio::test]
+    async fn steady_after_warm_up() {
+        let config = SteadyState::default();
+        let windows = vec![
+            window(50_000.0, 90_000.0),
+            window(90_000.0, 20_000.0),
+            window(99_000.0, 1_000.0),
+            window(100_000.0, 1_020.0),
+            window(101_000.0, 990.0),
+        ];
+        let (warmup, ran) = warm_up(config, windows).await;
+        assert!(warmup.steady);
+        assert_eq!(warmup.windows, 5);
+        assert_eq!(ran, 5);
+    }
+
+    #[tokio::test]
+    async fn windows_without_results_do_not_count() {
+        let config = SteadyState::default();
+        let windows = vec![
+            window(100_000.0, 1_000.0),
+            window(100_000.0, 1_000.0),
+            String::from("# generated ops/s: 0\n"),
+            window(100_000.0, 1_000.0),
+            window(100_000.0, 1_000.0),
+            window(100_000.0, 1_000.0),
+        ];
+        let (warmup, _) = warm_up(config, windows).await;
+        assert!(warmup.steady);
+        assert_eq!(warmup.windows, 6);
+    }
+
+    #[tokio::test]
+    async fn never_steady() {
+        let config = SteadyState {
+            window: 20,
+            max_warmup: 100,
+            ..SteadyState::default()
+        };
+        // the latency keeps climbing
+        let windows = (1..=5)
+            .map(|i| window(100_000.0, 1_000.0 * 2f64.powi(i)))
+            .collect();
+        let (warmup, ran) = warm_up(config, windows).await;
+        assert!(!warmup.steady);
+        assert_eq!(warmup.windows, 5);
+        assert_eq!(ran, 5);
+    }
+
+    fn measurement(steady: bool) -> Measurement {
+        let config = SteadyState {
+            min_runtime: 0,
+            ..SteadyState::default()
+        };
+        config.measure(&Warmup { windows: 3, steady })
+    }
+
+    #[test]
+    fn measurement_converges() {
+        let mut m = measurement(true);
+        assert!(!m.line("# generated ops/s: 100000"));
+        // a single window says nothing about the spread
+        assert!(!m.line("# interval: 100000 1000"));
+        assert!(m.line("# interval: 100500 1010"));
+        let measured = m.finish(true);
+        assert_eq!(measured.windows, 2);
+        assert!(measured.converged);
+    }
+
+    #[test]
+    fn measurement_keeps_going_while_noisy() {
+        let mut m = measurement(true);
+        assert!(!m.line("# interval: 100000 1000"));
+        assert!(!m.line("# interval: 50000 4000"));
+        assert!(!m.line("# interval: not a number"));
+        assert_eq!(m.finish(false).windows, 2);
+    }
+
+    #[test]
+    fn unsteady_target_is_measured_in_full() {
+        let mut m = measurement(false);
+        for _ in 0..10 {
+            assert!(!m.line("# interval: 100000 1000"));
+        }
+    }
+
+    #[test]
+    fn misbehaving_clients() {
+        let measured = |windows, converged| Measured {
+            windows,
+            converged,
+            runtime: 320,
+        };
+        assert!(measured(5, true).check(true).is_ok());
+        assert!(measured(16, false).check(true).is_ok());
+        // told to wrap up, but never said how it went
+        assert!(measured(5, true).check(false).is_err());
+        // ran to the end without reporting on any window
+        assert!(measured(0, false).check(true).is_err());
+        // a client that failed fails the target on its own
+        assert!(measured(0, false).check(false).is_ok());
+    }
+}
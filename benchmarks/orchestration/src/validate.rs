@@ -0,0 +1,383 @@
+//! Checking a directory of results for runs that need to be run again.
+//!
+//! The analysis scripts skip files that are empty or that they cannot parse, and only say so in
+//! passing, so a bad run can easily go unnoticed until a graph looks off. `orchestration validate
+//! <dir>` instead checks every result prefix in a directory:
+//!
+//!  - the `.log` must have the target and generated throughput lines, a verdict on whether the
+//!    target was overloaded, and a complete table of latency percentiles for every client;
+//!  - every histogram the benchmark writes must exist and decode;
+//!  - the `-statistics.json` must parse, for benchmarks that write one;
+//!  - every file listed in the `.manifest.json`, if there is one, must exist;
+//!  - no file may be empty.
+//!
+//! What it finds is printed as JSON, one entry per problem, and the command fails if there were
+//! any.
+
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use hdrhistogram::serialization::{interval_log, Deserializer};
+use hdrhistogram::Histogram;
+use result_key::ResultKey;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// The percentiles every latency table must have for every operation.
+const PERCENTILES: &[u32] = &[50, 95, 99, 100];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Severity {
+    /// Results are missing or cannot be read, and the target has to be run again.
+    Incomplete,
+    /// Results are there, but look off.
+    Suspicious,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Problem {
+    pub(crate) prefix: String,
+    pub(crate) file: String,
+    pub(crate) severity: Severity,
+    pub(crate) problem: String,
+}
+
+/// What validating a directory found.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Findings {
+    /// How many prefixes were checked.
+    pub(crate) checked: usize,
+    /// Prefixes that were not from a benchmark we know how to check.
+    pub(crate) skipped: Vec<String>,
+    pub(crate) problems: Vec<Problem>,
+}
+
+/// What a prefix is the results of, going by its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Benchmark {
+    Vote { clients: usize, noria: bool },
+    Lobsters { noria: bool },
+}
+
+impl Benchmark {
+    fn of(prefix: &str) -> Option<Self> {
+        // each run of a repeated point has results of its own
+        let (prefix, _) = result_key::split_run(prefix);
+        Some(match prefix.parse().ok()? {
+            ResultKey::Vote { clients, .. } => Benchmark::Vote {
+                clients,
+                noria: true,
+            },
+            ResultKey::VoteBaseline { clients, .. } => Benchmark::Vote {
+                clients,
+                noria: false,
+            },
+            ResultKey::LobstersNoria { .. } => Benchmark::Lobsters { noria: true },
+            ResultKey::LobstersMysql { .. } => Benchmark::Lobsters { noria: false },
+        })
+    }
+
+    /// The histograms the benchmark writes for the given prefix.
+    fn histograms(self, prefix: &str) -> Vec<String> {
+        match self {
+            Benchmark::Vote { clients, .. } => (0..clients)
+                .map(|clienti| format!("{}-client{}.hist", prefix, clienti))
+                .collect(),
+            Benchmark::Lobsters { .. } => vec![format!("{}.hist", prefix)],
+        }
+    }
+}
+
+struct Checker<'a> {
+    dir: &'a Path,
+    prefix: &'a str,
+    problems: &'a mut Vec<Problem>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, file: &str, severity: Severity, problem: impl Into<String>) {
+        self.problems.push(Problem {
+            prefix: self.prefix.to_string(),
+            file: file.to_string(),
+            severity,
+            problem: problem.into(),
+        });
+    }
+
+    /// Read the given file, and report it if it is missing or empty.
+    fn read(&mut self, file: &str) -> Option<Vec<u8>> {
+        match std::fs::read(self.dir.join(file)) {
+            Ok(contents) if contents.is_empty() => {
+                self.report(file, Severity::Incomplete, "empty");
+                None
+            }
+            Ok(contents) => Some(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.report(file, Severity::Incomplete, "missing");
+                None
+            }
+            Err(e) => {
+                self.report(file, Severity::Incomplete, format!("unreadable: {}", e));
+                None
+            }
+        }
+    }
+
+    fn log(&mut self, benchmark: Benchmark) {
+        let file = format!("{}.log", self.prefix);
+        let log = match self.read(&file) {
+            Some(log) => String::from_utf8_lossy(&log).into_owned(),
+            None => return,
+        };
+
+        // each client writes its own section, starting with the throughput it generated
+        let mut sections: Vec<BTreeMap<(String, String), BTreeSet<u32>>> = Vec::new();
+        let mut targets = 0;
+        let mut verdict = None;
+        for line in log.lines() {
+            if let Some(v) = line.strip_prefix(crate::resume::VERDICT) {
+                verdict = Some(v.to_string());
+                continue;
+            } else if line.starts_with("# generated ops/s") {
+                sections.push(BTreeMap::new());
+                continue;
+            } else if line.starts_with("# target ops/s") {
+                targets += 1;
+                continue;
+            } else if line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let (op, metric, pct) = match (benchmark, &fields[..]) {
+                // <op> <pct> <sojourn> <remote>
+                (Benchmark::Vote { .. }, [op, pct, _, _, ..]) => (*op, "sojourn", *pct),
+                // <op> <metric> <pct> <µs>
+                (Benchmark::Lobsters { .. }, [op, metric, pct, _, ..]) => (*op, *metric, *pct),
+                _ => continue,
+            };
+            // vote also writes the mean, as percentile 00
+            let pct = match pct.parse::<u32>() {
+                Ok(_) if pct == "00" => continue,
+                Ok(pct) => pct,
+                Err(_) => continue,
+            };
+            match sections.last_mut() {
+                Some(section) => {
+                    section
+                        .entry((op.to_string(), metric.to_string()))
+                        .or_default()
+                        .insert(pct);
+                }
+                None => {
+                    self.report(&file, Severity::Suspicious, "percentiles before any client");
+                    return;
+                }
+            }
+        }
+
+        if sections.is_empty() {
+            self.report(&file, Severity::Incomplete, "no generated ops/s line");
+            return;
+        }
+        if targets == 0 {
+            self.report(&file, Severity::Incomplete, "no target ops/s line");
+        }
+        match verdict {
+            // logs from before the verdict existed can still be judged, see crate::resume
+            None => self.report(&file, Severity::Suspicious, "no overloaded verdict line"),
+            Some(v) if v.parse::<bool>().is_err() => self.report(
+                &file,
+                Severity::Incomplete,
+                format!("bad overloaded verdict: {:?}", v),
+            ),
+            Some(_) => {}
+        }
+        if let Benchmark::Vote { clients, .. } = benchmark {
+            if sections.len() != clients {
+                self.report(
+                    &file,
+                    Severity::Suspicious,
+                    format!("{} clients reported, expected {}", sections.len(), clients),
+                );
+            }
+        }
+
+        // every client should report on every operation that any client reports on
+        let ops: BTreeSet<_> = sections.iter().flat_map(|section| section.keys()).collect();
+        let none = BTreeSet::new();
+        for (i, section) in sections.iter().enumerate() {
+            if section.is_empty() {
+                self.report(
+                    &file,
+                    Severity::Incomplete,
+                    format!("client {} has no percentile table", i),
+                );
+                continue;
+            }
+            for &(op, metric) in &ops {
+                let pcts = section.get(&(op.clone(), metric.clone())).unwrap_or(&none);
+                let missing: Vec<_> = PERCENTILES
+                    .iter()
+                    .filter(|pct| !pcts.contains(pct))
+                    .map(|pct| format!("p{}", pct))
+                    .collect();
+                if !missing.is_empty() {
+                    self.report(
+                        &file,
+                        Severity::Incomplete,
+                        format!(
+                            "client {} is missing {} {} {}",
+                            i,
+                            op,
+                            metric,
+                            missing.join(", ")
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn histogram(&mut self, file: &str) {
+        let contents = match self.read(file) {
+            Some(contents) => contents,
+            None => return,
+        };
+        match decode(&contents) {
+            Ok(0) => self.report(file, Severity::Incomplete, "no histograms"),
+            Ok(_) => {}
+            Err(e) => self.report(file, Severity::Incomplete, format!("{:#}", e)),
+        }
+    }
+
+    fn statistics(&mut self, file: &str) {
+        if let Some(stats) = self.read(file) {
+            if let Err(e) = serde_json::from_slice::<serde_json::Value>(&stats) {
+                self.report(file, Severity::Incomplete, format!("bad json: {}", e));
+            }
+        }
+    }
+
+    /// Check the files the manifest lists, and return them.
+    fn manifest(&mut self) -> Option<Vec<String>> {
+        let file = format!("{}.manifest.json", self.prefix);
+        if !self.dir.join(&file).exists() {
+            return None;
+        }
+        let manifest = self.read(&file)?;
+        let files: Vec<String> = match serde_json::from_slice::<serde_json::Value>(&manifest) {
+            Ok(manifest) => manifest["files"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|f| f.as_str())
+                // the manifest records the files as they were written, relative to where we ran
+                .filter_map(|f| Some(Path::new(f).file_name()?.to_str()?.to_string()))
+                .collect(),
+            Err(e) => {
+                self.report(&file, Severity::Incomplete, format!("bad json: {}", e));
+                return None;
+            }
+        };
+        // histograms and json are checked more closely below, and a quiet server has an empty log
+        for listed in &files {
+            if ![".hist", ".json", ".log"]
+                .iter()
+                .any(|ext| listed.ends_with(ext))
+            {
+                self.read(listed);
+            }
+        }
+        Some(files)
+    }
+}
+
+/// Decode every histogram in an interval log, and return how many there were.
+pub(crate) fn decode(log: &[u8]) -> Result<usize, Report> {
+    let mut deserializer = Deserializer::new();
+    let mut histograms = 0;
+    for entry in interval_log::IntervalLogIterator::new(log) {
+        let entry = entry.map_err(|e| eyre::eyre!("bad interval log: {:?}", e))?;
+        if let interval_log::LogEntry::Interval(h) = entry {
+            let mut encoded = h.encoded_histogram().as_bytes();
+            let mut decoder = base64::read::DecoderReader::new(&mut encoded, base64::STANDARD);
+            let _: Histogram<u64> = deserializer
+                .deserialize(&mut decoder)
+                .wrap_err_with(|| format!("histogram {} does not decode", histograms))?;
+            histograms += 1;
+        }
+    }
+    Ok(histograms)
+}
+
+/// The result prefixes in the given directory.
+fn prefixes(dir: &Path) -> Result<BTreeSet<String>, Report> {
+    let mut prefixes = BTreeSet::new();
+    for entry in std::fs::read_dir(dir).wrap_err_with(|| format!("failed to read {:?}", dir))? {
+        let name = entry?.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        let prefix = name
+            .strip_suffix(".manifest.json")
+            .or_else(|| name.strip_suffix(".log"));
+        match prefix {
+            // the orchestrator's own log, and the server's log for each target
+            Some("run") => {}
+            Some(prefix) if prefix.ends_with("-server") => {}
+            Some(prefix) => {
+                prefixes.insert(prefix.to_string());
+            }
+            None => {}
+        }
+    }
+    Ok(prefixes)
+}
+
+/// Check every result prefix in `dir`.
+pub(crate) fn validate(dir: impl Into<PathBuf>) -> Result<Findings, Report> {
+    let dir = dir.into();
+    let mut findings = Findings::default();
+    for prefix in prefixes(&dir)? {
+        let benchmark = match Benchmark::of(&prefix) {
+            Some(benchmark) => benchmark,
+            None => {
+                tracing::debug!(%prefix, "skipping unknown result prefix");
+                findings.skipped.push(prefix);
+                continue;
+            }
+        };
+        findings.checked += 1;
+
+        let mut check = Checker {
+            dir: &dir,
+            prefix: &prefix,
+            problems: &mut findings.problems,
+        };
+        let listed = check.manifest();
+        check.log(benchmark);
+
+        let mut histograms = benchmark.histograms(&prefix);
+        let statistics = format!("{}-statistics.json", prefix);
+        let mut expect_statistics = match benchmark {
+            Benchmark::Vote { noria, .. } | Benchmark::Lobsters { noria } => noria,
+        };
+        if let Some(listed) = listed {
+            histograms.extend(listed.iter().filter(|f| f.ends_with(".hist")).cloned());
+            histograms.sort();
+            histograms.dedup();
+            expect_statistics = listed.contains(&statistics);
+        }
+        for histogram in &histograms {
+            check.histogram(histogram);
+        }
+        if expect_statistics || dir.join(&statistics).exists() {
+            check.statistics(&statistics);
+        }
+    }
+    Ok(findings)
+}
@@ -1,200 +1,267 @@
+use crate::experiment::{Experiment, Machines};
+use crate::host::Child;
+use crate::invoke::vote::Distribution;
+use crate::role::Role;
+use crate::spec::Search;
 use crate::Context;
 use color_eyre::{eyre::WrapErr, Report};
+use futures_util::future::BoxFuture;
+use result_key::ResultKey;
 use tracing::instrument;
 use tracing_futures::Instrument;
-use tsunami::providers::aws;
-use tsunami::Tsunami;
+
+/// The parameters of a single vote experiment, as given in a spec file.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Parameters {
+    write_every: usize,
+    distribution: Distribution,
+    clients: usize,
+    partial: bool,
+    memlimit: usize,
+    join: bool,
+    durable: bool,
+}
+
+impl Parameters {
+    fn into_tuple(self) -> (usize, &'static str, usize, bool, usize, bool, bool) {
+        (
+            self.write_every,
+            self.distribution.as_str(),
+            self.clients,
+            self.partial,
+            self.memlimit,
+            self.join,
+            self.durable,
+        )
+    }
+}
+
+/// The experiments to run, from the spec if it has any.
+fn experiments(
+    ctx: &Context,
+) -> Result<
+    Vec<(
+        (usize, &'static str, usize, bool, usize, bool, bool),
+        crate::spec::Plan,
+    )>,
+    Report,
+> {
+    if let Some(experiments) = crate::spec::experiments(ctx, "vote", Parameters::into_tuple)? {
+        return Ok(experiments);
+    }
+
+    Ok(vec![
+        (100, "skewed", 4, false, 0, true, false),
+        // (100, "skewed", 4, true, 0, true, false),
+        (10_000, "skewed", 4, true, 0, false, false),
+        (100, "skewed", 4, true, 256 * 1024 * 1024, true, false),
+        (100, "skewed", 4, true, 320 * 1024 * 1024, true, false),
+        (100, "skewed", 4, true, 384 * 1024 * 1024, true, false),
+        (100, "skewed", 4, true, 448 * 1024 * 1024, true, false),
+        (100, "skewed", 4, true, 448 * 1024 * 1024, true, true),
+    ]
+    .into_iter()
+    .map(|parameters| (parameters, crate::spec::Plan::default()))
+    .collect())
+}
+
+/// The search to use for the given parameters, unless the spec says otherwise.
+fn search(parameters: (usize, &'static str, usize, bool, usize, bool, bool)) -> Search {
+    let (write_every, _, _, partial, _, _, durable) = parameters;
+    if durable {
+        // all we care about is the 1M data point
+        Search::Loads(vec![1_000_000])
+    } else if !partial {
+        Search::Loads(vec![250_000, 1_000_000])
+    } else if write_every == 10_000 {
+        Search::Exponential {
+            start: 1_000_000,
+            min_width: 1_000_000,
+            fill_left: true,
+        }
+    } else {
+        Search::Exponential {
+            start: 250_000,
+            min_width: 125_000,
+            fill_left: true,
+        }
+    }
+}
 
 /// vote; requires at least two machines: a server and 1+ clients
 #[instrument(name = "vote", skip(ctx))]
 pub(crate) async fn main(ctx: Context) -> Result<(), Report> {
-    crate::explore!(
-        [
-            (100, "skewed", 4, false, 0, true, false),
-            // (100, "skewed", 4, true, 0, true, false),
-            (10_000, "skewed", 4, true, 0, false, false),
-            (100, "skewed", 4, true, 256 * 1024 * 1024, true, false),
-            (100, "skewed", 4, true, 320 * 1024 * 1024, true, false),
-            (100, "skewed", 4, true, 384 * 1024 * 1024, true, false),
-            (100, "skewed", 4, true, 448 * 1024 * 1024, true, false),
-            (100, "skewed", 4, true, 448 * 1024 * 1024, true, true),
-        ],
-        one,
-        ctx,
-        false
-    )
+    crate::explore!(@plans experiments(&ctx)?, one, ctx)
 }
 
-#[instrument(err, skip(ctx))]
-pub(crate) async fn one(
-    parameters: (usize, &'static str, usize, bool, usize, bool, bool),
-    loads: Option<Vec<usize>>,
-    mut ctx: Context,
-) -> Result<usize, Report> {
-    let (write_every, distribution, nclients, partial, memlimit, join, durable) = parameters;
-    let mut last_good_target = 0;
+/// Describe what `main` would run.
+pub(crate) fn dry_run(ctx: &Context) -> Result<Vec<crate::dry_run::Experiment>, Report> {
+    Ok(experiments(ctx)?
+        .into_iter()
+        .map(|(parameters, plan)| {
+            let (_, _, nclients, ..) = parameters;
+            let ctx = plan.context(ctx);
+            let search = plan.search_or(|| search(parameters));
+            crate::dry_run::Experiment::searching(&parameters, &search)
+                .repeated(&plan.repetitions)
+                .machine("server", &ctx.server_type, 1)
+                .machine("client", &ctx.client_type, nclients)
+        })
+        .collect())
+}
+
+struct Vote((usize, &'static str, usize, bool, usize, bool, bool));
+
+impl Vote {
+    fn backend(&self) -> crate::invoke::vote::Backend {
+        let (_, _, _, _, _, join, _) = self.0;
+        crate::invoke::vote::Backend::Netsoup { join }
+    }
+}
 
-    let mut aws = crate::launcher();
-    aws.set_mode(aws::LaunchMode::on_demand());
+impl Experiment for Vote {
+    const NAME: &'static str = "vote";
 
-    // try to ensure we do AWS cleanup
-    let result: Result<_, Report> = try {
-        tracing::info!("spinning up aws instances");
-        let mut instances = vec![(
+    fn parameters(&self) -> &(dyn std::fmt::Debug + Sync) {
+        &self.0
+    }
+
+    fn search(&self) -> Search {
+        search(self.0)
+    }
+
+    fn machines(&self, ctx: &Context) -> Vec<(String, crate::provider::Setup)> {
+        let (_, _, nclients, _, _, _, durable) = self.0;
+        let mut server = Role::new(&ctx.server_type);
+        if durable {
+            server = server.ramdisk("/mnt", "60G");
+        }
+        let mut machines = vec![(
             String::from("server"),
-            aws::Setup::default()
-                .instance_type(&ctx.server_type)
-                .ami(crate::AMI, "ubuntu")
-                .availability_zone(ctx.az.clone())
+            crate::role::setup(ctx, "server", server)
                 .setup(crate::noria_setup("noria-server", "noria-server")),
         )];
-        for clienti in 0..nclients {
-            instances.push((
-                format!("client{}", clienti),
-                aws::Setup::default()
-                    .instance_type(&ctx.client_type)
-                    .ami(crate::AMI, "ubuntu")
-                    .availability_zone(ctx.az.clone())
-                    .setup(crate::noria_setup("noria-applications", "vote")),
-            ));
-        }
-        aws.spawn(instances, None)
-            .await
-            .wrap_err("failed to start instances")?;
+        machines.extend(crate::experiment::clients(
+            nclients,
+            crate::role::setup(ctx, "client", Role::new(&ctx.client_type))
+                .setup(crate::noria_setup("noria-applications", "vote")),
+        ));
+        machines
+    }
 
-        tracing::debug!("connecting");
-        let vms = aws.connect_all().await?;
-        let server = vms.get("server").unwrap();
-        let s = &server.ssh;
-        let cs: Vec<_> = (0..nclients)
-            .map(|clienti| &vms.get(&format!("client{}", clienti)).unwrap().ssh)
-            .collect();
-        tracing::debug!("connected");
+    fn results(&self) -> &'static str {
+        let (_, _, _, _, _, join, _) = self.0;
+        if join {
+            "vote"
+        } else {
+            "vote-nojoin"
+        }
+    }
 
-        if durable {
-            tracing::debug!("mount ramdisk");
-            crate::output_on_success(s.shell("sudo mount -t tmpfs -o size=60G tmpfs /mnt"))
-                .await
-                .wrap_err("mount ramdisk")?;
+    fn prefix(&self, target: usize) -> String {
+        let (write_every, distribution, nclients, partial, memlimit, join, durable) = self.0;
+        ResultKey::Vote {
+            partial,
+            join,
+            durable,
+            articles: crate::invoke::vote::ARTICLES,
+            target,
+            write_every,
+            clients: nclients,
+            memlimit,
+            distribution: distribution.to_string(),
         }
+        .to_string()
+    }
 
-        let mut targets = if let Some(loads) = loads {
-            Box::new(cliff::LoadIterator::from(loads)) as Box<dyn cliff::CliffSearch + Send>
-        } else if durable {
-            // all we care about is the 1M data point
-            Box::new(cliff::LoadIterator::from(vec![1_000_000]))
-                as Box<dyn cliff::CliffSearch + Send>
-        } else if !partial {
-            Box::new(cliff::LoadIterator::from(vec![250_000, 1_000_000]))
-                as Box<dyn cliff::CliffSearch + Send>
-        } else if write_every == 10_000 {
-            let mut s = cliff::ExponentialCliffSearcher::until(1_000_000, 1_000_000);
-            s.fill_left();
-            Box::new(s)
-        } else {
-            let mut s = cliff::ExponentialCliffSearcher::until(250_000, 125_000);
-            s.fill_left();
-            Box::new(s)
-        };
-        let result: Result<(), Report> = try {
-            let mut successful_target = None;
-            while let Some(target) = targets.next() {
-                if let Some(target) = successful_target.take() {
-                    // last run succeeded at the given target
-                    last_good_target = target;
-                }
-                successful_target = Some(target);
-
-                if *ctx.exit.borrow() {
-                    tracing::info!("exiting as instructed");
-                    break;
-                }
-
-                let target_span = tracing::info_span!("target", target);
-                async {
-                    tracing::info!("start benchmark target");
-                    let mut backend = if partial { "partial" } else { "full" }.to_string();
-                    if !join {
-                        backend.push_str("_nj");
-                    }
-                    if durable {
-                        backend.push_str("_dur");
-                    }
-                    let prefix = format!(
-                        "{}.10000000a.{}t.{}r.{}c.{}m.{}",
-                        backend, target, write_every, nclients, memlimit, distribution,
-                    );
-
-                    tracing::trace!("starting noria server");
-                    let dir = if durable { Some("/mnt") } else { None };
-                    let mut noria_server = crate::server::build(s, server, dir);
-                    if !partial {
-                        noria_server.arg("--no-partial");
-                    }
-                    let durability = if durable {
-                        "--durability=persistent"
-                    } else {
-                        "--durability=memory"
-                    };
-                    let noria_server = noria_server
-                        .arg(durability)
-                        .arg("--no-reuse")
-                        .arg("--shards=0")
-                        .arg("-m")
-                        .arg(memlimit.to_string())
-                        .spawn()
-                        .wrap_err("failed to start noria-server")?;
-
-                    crate::invoke::vote::run(
-                        &prefix,
-                        target,
-                        distribution,
-                        write_every,
-                        || {
-                            targets.overloaded();
-                            successful_target.take();
-                        },
-                        &cs[..],
-                        &server,
-                        crate::invoke::vote::Backend::Netsoup { join },
-                        &mut ctx,
-                    )
-                    .await?;
-
-                    if !*ctx.exit.borrow() {
-                        tracing::debug!("stopping server");
-                        crate::server::stop(s, noria_server).await?;
-                        tracing::trace!("server stopped");
-                    }
-
-                    Ok::<_, Report>(())
-                }
-                .instrument(target_span)
-                .await?;
-            }
-        };
-
-        tracing::debug!("cleaning up");
-        tracing::trace!("cleaning up ssh connections");
-        for (name, host) in vms {
-            let host_span = tracing::trace_span!("ssh_close", name = &*name);
-            async {
-                tracing::trace!("closing connection");
-                if let Err(e) = host.ssh.close().await {
-                    tracing::warn!("ssh connection failed: {:?}", e);
-                }
+    fn completed(&self, target: usize, prefix: &str) -> Option<bool> {
+        let (_, _, nclients, ..) = self.0;
+        crate::invoke::vote::completed(prefix, target, nclients, self.backend())
+    }
+
+    fn start<'a>(
+        &'a self,
+        _: usize,
+        machines: &'a Machines,
+    ) -> BoxFuture<'a, Result<Option<Child<'a>>, Report>> {
+        let (_, _, _, partial, memlimit, _, durable) = self.0;
+        Box::pin(async move {
+            tracing::trace!("starting noria server");
+            let server = machines.get("server");
+            let dir = if durable { Some("/mnt") } else { None };
+            let mut noria_server = crate::server::build(&server.session, server, dir);
+            if !partial {
+                noria_server.arg("--no-partial");
             }
-            .instrument(host_span)
+            let durability = if durable {
+                "--durability=persistent"
+            } else {
+                "--durability=memory"
+            };
+            let noria_server = noria_server
+                .arg(durability)
+                .arg("--no-reuse")
+                .arg("--shards=0")
+                .arg("-m")
+                .arg(memlimit.to_string())
+                .spawn()
+                .wrap_err("failed to start noria-server")?;
+            Ok(Some(noria_server))
+        })
+    }
+
+    fn invoke<'a>(
+        &'a self,
+        target: usize,
+        prefix: &'a str,
+        on_overloaded: &'a mut (dyn FnMut() + Send),
+        machines: &'a Machines,
+        ctx: &'a mut Context,
+    ) -> BoxFuture<'a, Result<(), Report>> {
+        let (write_every, distribution, nclients, partial, memlimit, join, durable) = self.0;
+        Box::pin(async move {
+            let cs = machines.clients(nclients);
+            crate::invoke::vote::run(
+                prefix,
+                serde_json::json!({
+                    "target": target,
+                    "write-every": write_every,
+                    "distribution": distribution,
+                    "clients": nclients,
+                    "partial": partial,
+                    "memlimit": memlimit,
+                    "join": join,
+                    "durable": durable,
+                }),
+                target,
+                distribution,
+                write_every,
+                on_overloaded,
+                &cs[..],
+                machines.get("server"),
+                self.backend(),
+                ctx,
+            )
             .await
-        }
+        })
+    }
 
-        result?
-    };
+    fn stop<'a>(
+        &'a self,
+        server: Option<Child<'a>>,
+        machines: &'a Machines,
+    ) -> BoxFuture<'a, Result<(), Report>> {
+        let s = &machines.get("server").session;
+        Box::pin(crate::server::stop(s, server.expect("started in start")))
+    }
+}
 
-    tracing::trace!("cleaning up instances");
-    let cleanup = aws.terminate_all().await;
-    tracing::debug!("done");
-    let _ = result?;
-    let _ = cleanup.wrap_err("cleanup failed")?;
-    Ok(last_good_target)
+#[instrument(err, skip(ctx))]
+pub(crate) async fn one(
+    parameters: (usize, &'static str, usize, bool, usize, bool, bool),
+    plan: crate::spec::Plan,
+    ctx: Context,
+) -> Result<usize, Report> {
+    crate::experiment::run(Vote(parameters), plan, ctx).await
 }
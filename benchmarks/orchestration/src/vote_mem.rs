@@ -1,166 +1,225 @@
+use crate::experiment::{Experiment, Machines};
+use crate::host::Child;
+use crate::invoke::vote::Distribution;
+use crate::role::Role;
+use crate::spec::Search;
 use crate::Context;
 use color_eyre::{eyre::WrapErr, Report};
+use futures_util::future::BoxFuture;
+use result_key::ResultKey;
 use tracing::instrument;
 use tracing_futures::Instrument;
-use tsunami::providers::aws;
-use tsunami::Tsunami;
 
 const KB: usize = 1024;
 const MB: usize = 1024 * KB;
 
+/// Memory limits only make sense with partial materialization.
+const PARTIAL: bool = true;
+
+/// The parameters of a single vote-memory experiment, as given in a spec file.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Parameters {
+    target: usize,
+    write_every: usize,
+    distribution: Distribution,
+    clients: usize,
+}
+
+impl Parameters {
+    fn into_tuple(self) -> (usize, usize, &'static str, usize) {
+        (
+            self.target,
+            self.write_every,
+            self.distribution.as_str(),
+            self.clients,
+        )
+    }
+}
+
+/// The experiments to run, from the spec if it has any.
+fn experiments(
+    ctx: &Context,
+) -> Result<Vec<((usize, usize, &'static str, usize), crate::spec::Plan)>, Report> {
+    if let Some(experiments) = crate::spec::experiments(ctx, "vote-memory", Parameters::into_tuple)?
+    {
+        return Ok(experiments);
+    }
+
+    Ok(vec![(250_000, 100, "skewed", 4)]
+        .into_iter()
+        .map(|parameters| (parameters, crate::spec::Plan::default()))
+        .collect())
+}
+
+/// The search to use for the given parameters, unless the spec says otherwise.
+fn search(_parameters: (usize, usize, &'static str, usize)) -> Search {
+    Search::BinaryMin {
+        start: 512 * MB,
+        min_width: 4 * MB,
+    }
+}
+
 /// vote_mem; requires at least two machines: a server and 1+ clients
 #[instrument(name = "vote-mem", skip(ctx))]
 pub(crate) async fn main(ctx: Context) -> Result<(), Report> {
-    crate::explore!([(250_000, 100, "skewed", 4)], one, ctx, true)
+    crate::explore!(@plans experiments(&ctx)?, one, ctx)
 }
 
-#[instrument(err, skip(ctx))]
-pub(crate) async fn one(
-    parameters: (usize, usize, &'static str, usize),
-    limits: Option<Vec<usize>>,
-    mut ctx: Context,
-) -> Result<usize, Report> {
-    let (target, write_every, distribution, nclients) = parameters;
-    let partial = true;
-    let mut last_good_limit = 0;
+/// Describe what `main` would run.
+pub(crate) fn dry_run(ctx: &Context) -> Result<Vec<crate::dry_run::Experiment>, Report> {
+    Ok(experiments(ctx)?
+        .into_iter()
+        .map(|(parameters, plan)| {
+            let (_, _, _, nclients) = parameters;
+            let ctx = plan.context(ctx);
+            let search = plan.search_or(|| search(parameters));
+            crate::dry_run::Experiment::searching(&parameters, &search)
+                .repeated(&plan.repetitions)
+                .machine("server", &ctx.server_type, 1)
+                .machine("client", &ctx.client_type, nclients)
+        })
+        .collect())
+}
 
-    let mut aws = crate::launcher();
-    aws.set_mode(aws::LaunchMode::on_demand());
+struct VoteMemory((usize, usize, &'static str, usize));
 
-    // try to ensure we do AWS cleanup
-    let result: Result<_, Report> = try {
-        tracing::info!("spinning up aws instances");
-        let mut instances = vec![(
+impl Experiment for VoteMemory {
+    const NAME: &'static str = "vote-memory";
+
+    fn parameters(&self) -> &(dyn std::fmt::Debug + Sync) {
+        &self.0
+    }
+
+    fn search(&self) -> Search {
+        search(self.0)
+    }
+
+    fn machines(&self, ctx: &Context) -> Vec<(String, crate::provider::Setup)> {
+        let (_, _, _, nclients) = self.0;
+        let mut machines = vec![(
             String::from("server"),
-            aws::Setup::default()
-                .instance_type(&ctx.server_type)
-                .ami(crate::AMI, "ubuntu")
-                .availability_zone(ctx.az.clone())
+            crate::role::setup(ctx, "server", Role::new(&ctx.server_type))
                 .setup(crate::noria_setup("noria-server", "noria-server")),
         )];
-        for clienti in 0..nclients {
-            instances.push((
-                format!("client{}", clienti),
-                aws::Setup::default()
-                    .instance_type(&ctx.client_type)
-                    .ami(crate::AMI, "ubuntu")
-                    .availability_zone(ctx.az.clone())
-                    .setup(crate::noria_setup("noria-applications", "vote")),
-            ));
+        machines.extend(crate::experiment::clients(
+            nclients,
+            crate::role::setup(ctx, "client", Role::new(&ctx.client_type))
+                .setup(crate::noria_setup("noria-applications", "vote")),
+        ));
+        machines
+    }
+
+    fn baseline(&self) -> Option<usize> {
+        // no memory limit at all
+        Some(0)
+    }
+
+    fn results(&self) -> &'static str {
+        "vote"
+    }
+
+    fn prefix(&self, limit: usize) -> String {
+        let (target, write_every, distribution, nclients) = self.0;
+        ResultKey::Vote {
+            partial: PARTIAL,
+            join: true,
+            durable: false,
+            articles: crate::invoke::vote::ARTICLES,
+            target,
+            write_every,
+            clients: nclients,
+            memlimit: limit,
+            distribution: distribution.to_string(),
         }
-        aws.spawn(instances, None)
-            .await
-            .wrap_err("failed to start instances")?;
-
-        tracing::debug!("connecting");
-        let vms = aws.connect_all().await?;
-        let server = vms.get("server").unwrap();
-        let s = &server.ssh;
-        let cs: Vec<_> = (0..nclients)
-            .map(|clienti| &vms.get(&format!("client{}", clienti)).unwrap().ssh)
-            .collect();
-        tracing::debug!("connected");
-
-        let mut limits = if let Some(limits) = limits {
-            Box::new(cliff::LoadIterator::from(limits)) as Box<dyn cliff::CliffSearch + Send>
-        } else {
-            Box::new(cliff::BinaryMinSearcher::until(512 * MB, 4 * MB))
-                as Box<dyn cliff::CliffSearch + Send>
-        };
-        let mut zero = Some(0);
-        let result: Result<(), Report> = try {
-            let mut successful_limit = None;
-            while let Some(limit) = zero.take().or_else(|| limits.next()) {
-                if let Some(limit) = successful_limit.take() {
-                    // last run succeeded at the given limit
-                    last_good_limit = limit;
-                }
-                successful_limit = Some(limit);
-
-                if limit == 0 && target % 250_000 == 0 && (target / 250_000).is_power_of_two() {
-                    // we already have this
-                    tracing::info!(%target, "skipping non-limited target we already have");
-                    continue;
-                }
-
-                if *ctx.exit.borrow() {
-                    tracing::info!("exiting as instructed");
-                    break;
-                }
-
-                let limit_span = tracing::info_span!("limit", limit);
-                async {
-                    tracing::info!("start benchmark target");
-                    let backend = if partial { "partial" } else { "full" };
-                    let prefix = format!(
-                        "{}.10000000a.{}t.{}r.{}c.{}m.{}",
-                        backend, target, write_every, nclients, limit, distribution,
-                    );
-
-                    tracing::trace!("starting noria server");
-                    let mut noria_server = crate::server::build(s, server, None);
-                    if !partial {
-                        noria_server.arg("--no-partial");
-                    }
-                    let noria_server = noria_server
-                        .arg("--durability=memory")
-                        .arg("--no-reuse")
-                        .arg("--shards=0")
-                        .arg("-m")
-                        .arg(limit.to_string())
-                        .spawn()
-                        .wrap_err("failed to start noria-server")?;
-
-                    crate::invoke::vote::run(
-                        &prefix,
-                        target,
-                        distribution,
-                        write_every,
-                        || {
-                            limits.overloaded();
-                            successful_limit.take();
-                        },
-                        &cs[..],
-                        &server,
-                        crate::invoke::vote::Backend::Netsoup { join: true },
-                        &mut ctx,
-                    )
-                    .await?;
-
-                    if !*ctx.exit.borrow() {
-                        tracing::debug!("stopping server");
-                        crate::server::stop(s, noria_server).await?;
-                        tracing::trace!("server stopped");
-                    }
-
-                    Ok::<_, Report>(())
-                }
-                .instrument(limit_span)
-                .await?;
-            }
-        };
-
-        tracing::debug!("cleaning up");
-        tracing::trace!("cleaning up ssh connections");
-        for (name, host) in vms {
-            let host_span = tracing::trace_span!("ssh_close", name = &*name);
-            async {
-                tracing::trace!("closing connection");
-                if let Err(e) = host.ssh.close().await {
-                    tracing::warn!("ssh connection failed: {:?}", e);
-                }
+        .to_string()
+    }
+
+    fn completed(&self, _: usize, prefix: &str) -> Option<bool> {
+        // without a limit, this is the same target as in the vote sweep, and has the same prefix,
+        // so the sweep's results are found here or in the archive like any other
+        let (target, _, _, nclients) = self.0;
+        crate::invoke::vote::completed(
+            prefix,
+            target,
+            nclients,
+            crate::invoke::vote::Backend::Netsoup { join: true },
+        )
+    }
+
+    fn start<'a>(
+        &'a self,
+        limit: usize,
+        machines: &'a Machines,
+    ) -> BoxFuture<'a, Result<Option<Child<'a>>, Report>> {
+        Box::pin(async move {
+            tracing::trace!("starting noria server");
+            let server = machines.get("server");
+            let mut noria_server = crate::server::build(&server.session, server, None);
+            if !PARTIAL {
+                noria_server.arg("--no-partial");
             }
-            .instrument(host_span)
+            let noria_server = noria_server
+                .arg("--durability=memory")
+                .arg("--no-reuse")
+                .arg("--shards=0")
+                .arg("-m")
+                .arg(limit.to_string())
+                .spawn()
+                .wrap_err("failed to start noria-server")?;
+            Ok(Some(noria_server))
+        })
+    }
+
+    fn invoke<'a>(
+        &'a self,
+        limit: usize,
+        prefix: &'a str,
+        on_overloaded: &'a mut (dyn FnMut() + Send),
+        machines: &'a Machines,
+        ctx: &'a mut Context,
+    ) -> BoxFuture<'a, Result<(), Report>> {
+        let (target, write_every, distribution, nclients) = self.0;
+        Box::pin(async move {
+            let cs = machines.clients(nclients);
+            crate::invoke::vote::run(
+                prefix,
+                serde_json::json!({
+                    "target": target,
+                    "write-every": write_every,
+                    "distribution": distribution,
+                    "clients": nclients,
+                    "partial": PARTIAL,
+                    "memlimit": limit,
+                }),
+                target,
+                distribution,
+                write_every,
+                on_overloaded,
+                &cs[..],
+                machines.get("server"),
+                crate::invoke::vote::Backend::Netsoup { join: true },
+                ctx,
+            )
             .await
-        }
-
-        result?
-    };
+        })
+    }
+
+    fn stop<'a>(
+        &'a self,
+        server: Option<Child<'a>>,
+        machines: &'a Machines,
+    ) -> BoxFuture<'a, Result<(), Report>> {
+        let s = &machines.get("server").session;
+        Box::pin(crate::server::stop(s, server.expect("started in start")))
+    }
+}
 
-    tracing::trace!("cleaning up instances");
-    let cleanup = aws.terminate_all().await;
-    tracing::debug!("done");
-    let _ = result?;
-    let _ = cleanup.wrap_err("cleanup failed")?;
-    Ok(last_good_limit)
+#[instrument(err, skip(ctx))]
+pub(crate) async fn one(
+    parameters: (usize, usize, &'static str, usize),
+    plan: crate::spec::Plan,
+    ctx: Context,
+) -> Result<usize, Report> {
+    crate::experiment::run(VoteMemory(parameters), plan, ctx).await
 }
@@ -2,51 +2,52 @@ use crate::Context;
 use color_eyre::{eyre::WrapErr, Report};
 use tracing::instrument;
 use tracing_futures::Instrument;
-use tsunami::providers::aws;
 use tsunami::providers::Launcher;
 
+/// Describe what `main` would run.
+pub(crate) fn dry_run(ctx: &Context) -> Result<Vec<crate::dry_run::Experiment>, Report> {
+    Ok(vec![crate::dry_run::Experiment::once(
+        std::time::Duration::from_secs(160),
+    )
+    .machine("host", &ctx.server_type, 1)])
+}
+
 /// vote-migration; requires only one machine
 #[instrument(err, name = "vote_migration", skip(ctx))]
-pub(crate) async fn main(ctx: Context) -> Result<(), Report> {
-    let Context {
-        server_type,
-        mut exit,
-        az,
-        ..
-    } = ctx;
-
+pub(crate) async fn main(mut ctx: Context) -> Result<(), Report> {
     // make sure we shouldn't already be exiting.
     // this also sets it up so that _any_ recv from exit means we should exit.
-    if let Some(false) = exit.recv().await {
+    if let Some(false) = ctx.exit.recv().await {
     } else {
         tracing::info!("exiting as instructed");
         return Ok(());
     }
 
-    let mut aws = crate::launcher();
-    aws.set_mode(aws::LaunchMode::on_demand());
+    // other benchmarks may be running alongside this one
+    let _slot = ctx.schedule.start().await;
+
+    // this benchmark is short, and never runs on spot instances
+    ctx.spot = false;
+    let (aws, launched) =
+        crate::experiment::spawn_in_zones(&mut ctx, "vote-migration", &(), |ctx| {
+            let host = crate::role::setup(ctx, "server", crate::role::Role::new(&ctx.server_type))
+                .setup(crate::noria_setup("noria-applications", "vote-migration"));
+            vec![(String::from("host"), host)]
+        })
+        .await?;
+    let Context {
+        mut exit,
+        ledger,
+        results,
+        ..
+    } = ctx;
 
     // try to ensure we do AWS cleanup
     let result: Result<(), Report> = try {
-        tracing::info!("spinning up aws instances");
-        aws.spawn(
-            vec![(
-                String::from("host"),
-                aws::Setup::default()
-                    .instance_type(&server_type)
-                    .ami(crate::AMI, "ubuntu")
-                    .availability_zone(az)
-                    .setup(crate::noria_setup("noria-applications", "vote-migration")),
-            )],
-            None,
-        )
-        .await
-        .wrap_err("failed to start instances")?;
-
         tracing::debug!("connecting");
-        let vms = aws.connect_all().await?;
+        let vms = aws.hosts().await?;
         let host = vms.get("host").unwrap();
-        let ssh = &host.ssh;
+        let ssh = &host.session;
         tracing::debug!("connected");
 
         tracing::info!("running benchmark");
@@ -83,13 +84,11 @@ pub(crate) async fn main(ctx: Context) -> Result<(), Report> {
 
                 // copy out all the log files
                 let files = ssh
-                    .command("ls")
-                    .raw_arg("vote-*.log")
+                    .shell("ls vote-*.log")
                     .output()
                     .await
                     .wrap_err("ls vote-*.log")?;
                 if files.status.success() {
-                    let mut sftp = ssh.sftp();
                     let mut nfiles = 0;
                     tracing::debug!("downloading log files");
                     for file in std::io::BufRead::lines(&*files.stdout) {
@@ -97,15 +96,16 @@ pub(crate) async fn main(ctx: Context) -> Result<(), Report> {
                         let file_span = tracing::trace_span!("file", file = &*file);
                         async {
                             tracing::trace!("downloading");
-                            let mut remote = sftp
+                            let mut remote = ssh
                                 .read_from(&file)
                                 .in_current_span()
                                 .await
                                 .wrap_err("open remote file")?;
-                            let mut local = tokio::fs::File::create(&file)
-                                .in_current_span()
-                                .await
-                                .wrap_err("create local file")?;
+                            let mut local =
+                                tokio::fs::File::create(results.dir("vote-migration")?.join(&file))
+                                    .in_current_span()
+                                    .await
+                                    .wrap_err("create local file")?;
                             tokio::io::copy(&mut remote, &mut local)
                                 .in_current_span()
                                 .await
@@ -130,7 +130,7 @@ pub(crate) async fn main(ctx: Context) -> Result<(), Report> {
             let host_span = tracing::trace_span!("ssh_close", name = &*name);
             async {
                 tracing::trace!("closing connection");
-                if let Err(e) = host.ssh.close().await {
+                if let Err(e) = host.session.close().await {
                     tracing::warn!("ssh connection failed: {}", e);
                 }
             }
@@ -141,6 +141,9 @@ pub(crate) async fn main(ctx: Context) -> Result<(), Report> {
 
     tracing::trace!("cleaning up instances");
     let cleanup = aws.terminate_all().await;
+    if cleanup.is_ok() {
+        ledger.terminated(&launched);
+    }
     tracing::debug!("done");
     let result = result?;
     let _ = cleanup.wrap_err("cleanup failed")?;
@@ -1,207 +1,292 @@
+use crate::experiment::{Experiment, Machines};
+use crate::host::Child;
+use crate::invoke::vote::Distribution;
+use crate::role::Role;
+use crate::spec::Search;
 use crate::Context;
 use color_eyre::{eyre, eyre::WrapErr, Report};
+use futures_util::future::BoxFuture;
+use result_key::{Baseline, ResultKey};
 use tracing::instrument;
 use tracing_futures::Instrument;
-use tsunami::providers::aws;
-use tsunami::Tsunami;
+
+/// The parameters of a single vote-redis experiment, as given in a spec file.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Parameters {
+    write_every: usize,
+    distribution: Distribution,
+    clients: usize,
+}
+
+impl Parameters {
+    fn into_tuple(self) -> (usize, &'static str, usize) {
+        (self.write_every, self.distribution.as_str(), self.clients)
+    }
+}
+
+/// The experiments to run, from the spec if it has any.
+fn experiments(
+    ctx: &Context,
+) -> Result<Vec<((usize, &'static str, usize), crate::spec::Plan)>, Report> {
+    if let Some(experiments) = crate::spec::experiments(ctx, "vote-redis", Parameters::into_tuple)?
+    {
+        return Ok(experiments);
+    }
+
+    Ok(vec![(10_000, "skewed", 4)]
+        .into_iter()
+        .map(|parameters| (parameters, crate::spec::Plan::default()))
+        .collect())
+}
+
+/// The search to use for the given parameters, unless the spec says otherwise.
+fn search(_parameters: (usize, &'static str, usize)) -> Search {
+    Search::Exponential {
+        start: 250_000,
+        min_width: 125_000,
+        fill_left: true,
+    }
+}
+
+/// Redis is single-threaded, so it gets a single core to itself.
+fn backend() -> Role {
+    Role::new("r5n.large").cpu_options(1, 1)
+}
 
 /// vote; requires at least two machines: a server and 1+ clients
 #[instrument(name = "vote-redis", skip(ctx))]
 pub(crate) async fn main(ctx: Context) -> Result<(), Report> {
-    crate::explore!([(10_000, "skewed", 4),], one, ctx, false)
+    crate::explore!(@plans experiments(&ctx)?, one, ctx)
 }
 
-#[instrument(err, skip(ctx))]
-pub(crate) async fn one(
-    parameters: (usize, &'static str, usize),
-    loads: Option<Vec<usize>>,
-    mut ctx: Context,
-) -> Result<usize, Report> {
-    let (write_every, distribution, nclients) = parameters;
-    let mut last_good_target = 0;
+/// Describe what `main` would run.
+pub(crate) fn dry_run(ctx: &Context) -> Result<Vec<crate::dry_run::Experiment>, Report> {
+    Ok(experiments(ctx)?
+        .into_iter()
+        .map(|(parameters, plan)| {
+            let (_, _, nclients) = parameters;
+            let ctx = plan.context(ctx);
+            let search = plan.search_or(|| search(parameters));
+            crate::dry_run::Experiment::searching(&parameters, &search)
+                .repeated(&plan.repetitions)
+                .machine(
+                    "backend",
+                    crate::role::resolve(&ctx, "backend", backend()).instance_type(),
+                    1,
+                )
+                .machine("client", &ctx.client_type, nclients)
+        })
+        .collect())
+}
 
-    let mut aws = crate::launcher();
-    aws.set_mode(aws::LaunchMode::on_demand());
+fn redis_setup<'r>(
+    _ssh: &'r tsunami::Machine<'_>,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Report>> + Send + 'r>> {
+    Box::pin(async { Ok(()) }.in_current_span())
+}
+
+/// Make redis on the given host listen for the clients, and log to the journal.
+pub(crate) async fn configure(server: &crate::host::Host) -> Result<(), Report> {
+    tracing::debug!("adjusting redis config");
+    tracing::trace!("setting bind address");
+    let adj = server
+        .session
+        .command("sudo")
+        .arg("sed")
+        .arg("-i")
+        .arg("-e")
+        .arg(format!(
+            "s/^bind .*/bind {}/",
+            server.private_ip.as_ref().expect("private ip unknown")
+        ))
+        .arg("-e")
+        .arg("/^protected-mode yes/ s/yes/no/")
+        // log to the journal, so that it ends up in the server log
+        .arg("-e")
+        .arg("s/^logfile .*/logfile \"\"/")
+        .arg("/etc/redis/redis.conf")
+        .status()
+        .await
+        .wrap_err("failed to adjust redis conf")?;
+    if !adj.success() {
+        eyre::bail!("redis conf sed");
+    }
+    Ok(())
+}
 
-    fn redis_setup<'r>(
-        _ssh: &'r tsunami::Machine<'_>,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Report>> + Send + 'r>> {
-        Box::pin(async { Ok(()) }.in_current_span())
+/// (Re)start redis on the given host.
+pub(crate) async fn start(server: &crate::host::Host) -> Result<(), Report> {
+    tracing::trace!("starting redis server");
+    let redis = server
+        .session
+        .command("sudo")
+        .arg("systemctl")
+        .arg("restart") // restart in case it was already running
+        .arg("redis")
+        .status()
+        .await
+        .wrap_err("failed to start redis")?;
+    if !redis.success() {
+        eyre::bail!("systemctl start redis failed");
     }
 
-    // try to ensure we do AWS cleanup
-    let result: Result<_, Report> = try {
-        tracing::info!("spinning up aws instances");
-        let mut instances = vec![(
+    // give it a bit to start
+    tokio::time::delay_for(std::time::Duration::from_secs(3)).await;
+    Ok(())
+}
+
+/// Empty redis and stop it.
+pub(crate) async fn stop(server: &crate::host::Host) -> Result<(), Report> {
+    let s = &server.session;
+    let flush = s
+        .command("redis-cli")
+        .arg("-h")
+        .arg(server.private_ip.as_ref().expect("private ip unknown"))
+        .arg("flushall")
+        .output()
+        .await
+        .wrap_err("failed to flush redis")?;
+    if !flush.status.success() {
+        return Err(
+            eyre::eyre!(String::from_utf8_lossy(&flush.stderr).to_string())
+                .wrap_err("failed to flush redis"),
+        );
+    }
+    let stop = s
+        .command("sudo")
+        .arg("systemctl")
+        .arg("stop")
+        .arg("redis")
+        .status()
+        .await
+        .wrap_err("failed to stop redis")?;
+    if !stop.success() {
+        eyre::bail!("systemctl stop redis failed");
+    }
+    Ok(())
+}
+
+struct VoteRedis((usize, &'static str, usize));
+
+impl Experiment for VoteRedis {
+    const NAME: &'static str = "vote-redis";
+
+    fn parameters(&self) -> &(dyn std::fmt::Debug + Sync) {
+        &self.0
+    }
+
+    fn search(&self) -> Search {
+        search(self.0)
+    }
+
+    fn machines(&self, ctx: &Context) -> Vec<(String, crate::provider::Setup)> {
+        let (_, _, nclients) = self.0;
+        let mut machines = vec![(
             String::from("server"),
-            aws::Setup::default()
-                .instance_type("r5n.large")
-                .ami(crate::AMI, "ubuntu")
-                .availability_zone(ctx.az.clone())
-                .setup(redis_setup),
+            crate::role::setup(ctx, "backend", backend()).setup(redis_setup),
         )];
-        for clienti in 0..nclients {
-            instances.push((
-                format!("client{}", clienti),
-                aws::Setup::default()
-                    .instance_type(&ctx.client_type)
-                    .ami(crate::AMI, "ubuntu")
-                    .availability_zone(ctx.az.clone())
-                    .setup(crate::noria_setup("noria-applications", "vote")),
-            ));
-        }
-        aws.spawn(instances, None)
-            .await
-            .wrap_err("failed to start instances")?;
-
-        tracing::debug!("connecting");
-        let vms = aws.connect_all().await?;
-        let server = vms.get("server").unwrap();
-        let s = &server.ssh;
-        let cs: Vec<_> = (0..nclients)
-            .map(|clienti| &vms.get(&format!("client{}", clienti)).unwrap().ssh)
-            .collect();
-        tracing::debug!("connected");
-
-        tracing::debug!("adjusting redis config");
-        tracing::trace!("setting bind address");
-        let adj = s
-            .command("sudo")
-            .arg("sed")
-            .arg("-i")
-            .arg("-e")
-            .arg(format!(
-                "s/^bind .*/bind {}/",
-                server.private_ip.as_ref().expect("private ip unknown")
-            ))
-            .arg("-e")
-            .arg("/^protected-mode yes/ s/yes/no/")
-            .arg("/etc/redis/redis.conf")
-            .status()
-            .await
-            .wrap_err("failed to adjust redis conf")?;
-        if !adj.success() {
-            eyre::bail!("redis conf sed");
+        machines.extend(crate::experiment::clients(
+            nclients,
+            crate::role::setup(ctx, "client", Role::new(&ctx.client_type))
+                .setup(crate::noria_setup("noria-applications", "vote")),
+        ));
+        machines
+    }
+
+    fn prepare<'a>(&'a self, machines: &'a Machines) -> BoxFuture<'a, Result<(), Report>> {
+        Box::pin(configure(machines.get("server")))
+    }
+
+    fn results(&self) -> &'static str {
+        "vote-redis"
+    }
+
+    fn prefix(&self, target: usize) -> String {
+        let (write_every, distribution, nclients) = self.0;
+        ResultKey::VoteBaseline {
+            backend: Baseline::Redis,
+            articles: crate::invoke::vote::ARTICLES,
+            target,
+            write_every,
+            clients: nclients,
+            memlimit: None,
+            distribution: distribution.to_string(),
         }
+        .to_string()
+    }
 
-        let mut targets = if let Some(loads) = loads {
-            Box::new(cliff::LoadIterator::from(loads)) as Box<dyn cliff::CliffSearch + Send>
-        } else {
-            let mut s = cliff::ExponentialCliffSearcher::until(250_000, 125_000);
-            s.fill_left();
-            Box::new(s)
-        };
-        let result: Result<(), Report> = try {
-            let mut successful_target = None;
-            while let Some(target) = targets.next() {
-                if let Some(target) = successful_target.take() {
-                    // last run succeeded at the given target
-                    last_good_target = target;
-                }
-                successful_target = Some(target);
-
-                if *ctx.exit.borrow() {
-                    tracing::info!("exiting as instructed");
-                    break;
-                }
-
-                let target_span = tracing::info_span!("target", target);
-                async {
-                    tracing::info!("start benchmark target");
-                    let backend = "redis";
-                    let prefix = format!(
-                        "{}.10000000a.{}t.{}r.{}c.{}",
-                        backend, target, write_every, nclients, distribution,
-                    );
-
-                    tracing::trace!("starting redis server");
-                    let redis = s
-                        .command("sudo")
-                        .arg("systemctl")
-                        .arg("restart") // restart in case it was already running
-                        .arg("redis")
-                        .status()
-                        .await
-                        .wrap_err("failed to start redis")?;
-                    if !redis.success() {
-                        eyre::bail!("systemctl start redis failed");
-                    }
-
-                    // give it a bit to start
-                    tokio::time::delay_for(std::time::Duration::from_secs(3)).await;
-
-                    crate::invoke::vote::run(
-                        &prefix,
-                        target,
-                        distribution,
-                        write_every,
-                        || {
-                            targets.overloaded();
-                            successful_target.take();
-                        },
-                        &cs[..],
-                        &server,
-                        crate::invoke::vote::Backend::Redis,
-                        &mut ctx,
-                    )
-                    .await?;
-
-                    tracing::debug!("stopping server");
-                    let flush = s
-                        .command("redis-cli")
-                        .arg("-h")
-                        .arg(server.private_ip.as_ref().expect("private ip unknown"))
-                        .arg("flushall")
-                        .output()
-                        .await
-                        .wrap_err("failed to flush redis")?;
-                    if !flush.status.success() {
-                        return Err(
-                            eyre::eyre!(String::from_utf8_lossy(&flush.stderr).to_string())
-                                .wrap_err("failed to flush redis"),
-                        );
-                    }
-                    let stop = s
-                        .command("sudo")
-                        .arg("systemctl")
-                        .arg("stop")
-                        .arg("redis")
-                        .status()
-                        .await
-                        .wrap_err("failed to stop redis")?;
-                    if !stop.success() {
-                        eyre::bail!("systemctl stop redis failed");
-                    }
-                    tracing::trace!("server stopped");
-
-                    Ok::<_, Report>(())
-                }
-                .instrument(target_span)
-                .await?;
-            }
-        };
-
-        tracing::debug!("cleaning up");
-        tracing::trace!("cleaning up ssh connections");
-        for (name, host) in vms {
-            let host_span = tracing::trace_span!("ssh_close", name = &*name);
-            async {
-                tracing::trace!("closing connection");
-                if let Err(e) = host.ssh.close().await {
-                    tracing::warn!("ssh connection failed: {:?}", e);
-                }
-            }
-            .instrument(host_span)
+    fn completed(&self, target: usize, prefix: &str) -> Option<bool> {
+        let (_, _, nclients) = self.0;
+        crate::invoke::vote::completed(
+            prefix,
+            target,
+            nclients,
+            crate::invoke::vote::Backend::Redis,
+        )
+    }
+
+    fn start<'a>(
+        &'a self,
+        _: usize,
+        machines: &'a Machines,
+    ) -> BoxFuture<'a, Result<Option<Child<'a>>, Report>> {
+        Box::pin(async move {
+            start(machines.get("server")).await?;
+            Ok(None)
+        })
+    }
+
+    fn invoke<'a>(
+        &'a self,
+        target: usize,
+        prefix: &'a str,
+        on_overloaded: &'a mut (dyn FnMut() + Send),
+        machines: &'a Machines,
+        ctx: &'a mut Context,
+    ) -> BoxFuture<'a, Result<(), Report>> {
+        let (write_every, distribution, nclients) = self.0;
+        Box::pin(async move {
+            let cs = machines.clients(nclients);
+            crate::invoke::vote::run(
+                prefix,
+                serde_json::json!({
+                    "target": target,
+                    "write-every": write_every,
+                    "distribution": distribution,
+                    "clients": nclients,
+                }),
+                target,
+                distribution,
+                write_every,
+                on_overloaded,
+                &cs[..],
+                machines.get("server"),
+                crate::invoke::vote::Backend::Redis,
+                ctx,
+            )
             .await
-        }
+        })
+    }
+
+    fn services(&self) -> &'static [&'static str] {
+        &["redis-server"]
+    }
 
-        result?
-    };
+    fn stop<'a>(
+        &'a self,
+        _: Option<Child<'a>>,
+        machines: &'a Machines,
+    ) -> BoxFuture<'a, Result<(), Report>> {
+        Box::pin(stop(machines.get("server")))
+    }
+}
 
-    tracing::trace!("cleaning up instances");
-    let cleanup = aws.terminate_all().await;
-    tracing::debug!("done");
-    let _ = result?;
-    let _ = cleanup.wrap_err("cleanup failed")?;
-    Ok(last_good_target)
+#[instrument(err, skip(ctx))]
+pub(crate) async fn one(
+    parameters: (usize, &'static str, usize),
+    plan: crate::spec::Plan,
+    ctx: Context,
+) -> Result<usize, Report> {
+    crate::experiment::run(VoteRedis(parameters), plan, ctx).await
 }
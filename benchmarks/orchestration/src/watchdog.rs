@@ -0,0 +1,125 @@
+//! Noticing right away when the server dies in the middle of a benchmark.
+//!
+//! Otherwise, the clients keep going against a dead server until their runtime is up, and the
+//! crash only shows once we fail to read the server's memory use. A [`Watchdog`] instead waits on
+//! the server's machine for the server processes to exit, so that the benchmark can stop the
+//! clients, save what it has, and report the target as overloaded straight away.
+
+use crate::host::{Child, Session};
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use futures_util::future::BoxFuture;
+use std::process::ExitStatus;
+
+/// The error returned when a target was cut short because the server crashed.
+///
+/// By the time this is returned, the results of the target have been saved, and it should count
+/// as overloaded.
+#[derive(Debug)]
+pub(crate) struct Crashed(pub(crate) String);
+
+impl std::fmt::Display for Crashed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "server crashed: {}", self.0)
+    }
+}
+
+impl std::error::Error for Crashed {}
+
+type Exited<'a> = BoxFuture<'a, (&'static str, usize, Result<ExitStatus, Report>)>;
+
+/// Waits for any of the server processes to exit.
+pub(crate) struct Watchdog<'a> {
+    session: &'a Session,
+    exits: Vec<Exited<'a>>,
+}
+
+impl<'a> Watchdog<'a> {
+    /// Start watching the oldest process with each of the given names.
+    ///
+    /// The processes must already be running.
+    pub(crate) async fn start(
+        session: &'a Session,
+        processes: &[&'static str],
+    ) -> Result<Watchdog<'a>, Report> {
+        let mut exits = Vec::new();
+        for &process in processes {
+            let pid = crate::server::pid_of(session, process).await?;
+            tracing::trace!(%process, pid, "watching server process");
+            // tail exits as soon as the process with the given pid does, and not before
+            let tail: Child<'a> = session
+                .command("tail")
+                .arg(format!("--pid={}", pid))
+                .arg("-f")
+                .arg("/dev/null")
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .spawn()
+                .wrap_err("failed to start watchdog")?;
+            exits.push(Box::pin(async move { (process, pid, tail.wait().await) }) as Exited<'a>);
+        }
+        Ok(Watchdog { session, exits })
+    }
+
+    /// Wait for one of the server processes to exit, and say why it did.
+    ///
+    /// The watchdog is only dropped once the server is stopped, so any exit is unexpected.
+    pub(crate) async fn crashed(self) -> Result<String, Report> {
+        if self.exits.is_empty() {
+            return futures_util::future::pending().await;
+        }
+
+        let ((process, pid, exited), _, _) = futures_util::future::select_all(self.exits).await;
+        match exited {
+            Ok(status) if status.success() => {}
+            Ok(status) => eyre::bail!("watchdog for {} exited with {}", process, status),
+            Err(e) => {
+                // this may just be the connection going away
+                self.session.check().await?;
+                return Err(e.wrap_err(format!("watchdog for {} failed", process)));
+            }
+        }
+
+        let oom = self
+            .session
+            .shell(format!("sudo dmesg | grep -q 'Killed process {} '", pid))
+            .status()
+            .await
+            .wrap_err("dmesg")?;
+        if oom.success() {
+            Ok(format!("{} was killed for running out of memory", process))
+        } else {
+            Ok(format!("{} exited unexpectedly", process))
+        }
+    }
+}
+
+/// Tell the given clients to wrap up and report what they have measured so far.
+pub(crate) async fn finish_clients(clients: &[&Session], process: &str) -> Result<(), Report> {
+    for c in clients {
+        // pkill fails if the client already exited, which is fine
+        let _ = c
+            .command("pkill")
+            .arg("-INT")
+            .arg("-x")
+            .arg(process)
+            .status()
+            .await
+            .wrap_err("pkill")?;
+    }
+    Ok(())
+}
+
+/// Stop the given clients, for when there is no longer a server for them to talk to.
+pub(crate) async fn stop_clients(clients: &[&Session], process: &str) -> Result<(), Report> {
+    for c in clients {
+        // pkill fails if the client already exited, which is fine
+        let _ = c
+            .command("pkill")
+            .arg("-x")
+            .arg(process)
+            .status()
+            .await
+            .wrap_err("pkill")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,569 @@
+//! The names of benchmark results.
+//!
+//! Every benchmark target writes its results to files that start with a prefix naming the target,
+//! like `partial_nj.10000000a.250000t.100r.4c.0m.skewed` or `lobsters-direct_full-4000-0m`. A
+//! [`ResultKey`] is what such a prefix says about the target. It is the one place that knows how
+//! those names are put together, so that the orchestrator that writes results and the tools that
+//! read them back cannot drift apart: [`ToString`] gives the prefix for a key, and [`str::parse`]
+//! gives the key for a prefix.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// What the prefix of a benchmark target's results says about that target.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ResultKey {
+    /// `vote` and `vote-memory` against Noria.
+    ///
+    /// `<full|partial>[_nj][_dur].<articles>a.<target>t.<write-every>r.<clients>c.<memlimit>m.<distribution>`
+    Vote {
+        /// Whether Noria used partial materialization.
+        partial: bool,
+        /// Whether the vote counts were joined with the articles; `_nj` if not.
+        join: bool,
+        /// Whether base tables were durable; `_dur` if so.
+        durable: bool,
+        articles: usize,
+        /// The target load, in ops/s.
+        target: usize,
+        /// How many reads there were for every write.
+        write_every: usize,
+        clients: usize,
+        /// The server's memory limit in bytes, or 0 for none.
+        memlimit: usize,
+        /// The key distribution, `uniform` or `skewed`.
+        distribution: String,
+    },
+    /// `vote-redis` and `vote-hybrid`.
+    ///
+    /// `<redis|hybrid>.<articles>a.<target>t.<write-every>r.<clients>c[.<memlimit>m].<distribution>`
+    VoteBaseline {
+        backend: Baseline,
+        articles: usize,
+        /// The target load, in ops/s.
+        target: usize,
+        /// How many reads there were for every write.
+        write_every: usize,
+        clients: usize,
+        /// The server's memory limit in bytes, if the name has one.
+        memlimit: Option<usize>,
+        /// The key distribution, `uniform` or `skewed`.
+        distribution: String,
+    },
+    /// `lobsters-noria` and `lobsters-noria-memory`.
+    ///
+    /// `lobsters-direct[_<shards>][_full][_durable]-<scale>-<memlimit>m`
+    LobstersNoria {
+        /// How many shards Noria used, or 0 for none.
+        shards: usize,
+        /// Whether Noria used partial materialization; `_full` if not.
+        partial: bool,
+        /// Whether base tables were durable; `_durable` if so.
+        durable: bool,
+        /// The load, as a multiple of the production lobste.rs load.
+        scale: usize,
+        /// The server's memory limit in bytes, or 0 for none.
+        memlimit: usize,
+    },
+    /// `lobsters-mysql`.
+    ///
+    /// `lobsters-mysql[_noopt]-<scale>-<memlimit>m`
+    LobstersMysql {
+        /// Whether the queries were the hand-optimized ones; `_noopt` if not.
+        optimized: bool,
+        /// The load, as a multiple of the production lobste.rs load.
+        scale: usize,
+        /// The server's memory limit in bytes, or 0 for none.
+        memlimit: usize,
+    },
+}
+
+/// The systems Noria's vote results are compared against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Baseline {
+    /// Redis on its own.
+    Redis,
+    /// Redis in front of MariaDB.
+    Hybrid,
+}
+
+impl Baseline {
+    fn as_str(self) -> &'static str {
+        match self {
+            Baseline::Redis => "redis",
+            Baseline::Hybrid => "hybrid",
+        }
+    }
+}
+
+/// The error returned when a prefix does not follow any of the naming schemes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    prefix: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bad result prefix {:?}: {}", self.prefix, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for ResultKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ResultKey::Vote {
+                partial,
+                join,
+                durable,
+                articles,
+                target,
+                write_every,
+                clients,
+                memlimit,
+                ref distribution,
+            } => {
+                write!(f, "{}", if partial { "partial" } else { "full" })?;
+                if !join {
+                    write!(f, "_nj")?;
+                }
+                if durable {
+                    write!(f, "_dur")?;
+                }
+                write!(
+                    f,
+                    ".{}a.{}t.{}r.{}c.{}m.{}",
+                    articles, target, write_every, clients, memlimit, distribution
+                )
+            }
+            ResultKey::VoteBaseline {
+                backend,
+                articles,
+                target,
+                write_every,
+                clients,
+                memlimit,
+                ref distribution,
+            } => {
+                write!(
+                    f,
+                    "{}.{}a.{}t.{}r.{}c",
+                    backend.as_str(),
+                    articles,
+                    target,
+                    write_every,
+                    clients
+                )?;
+                if let Some(memlimit) = memlimit {
+                    write!(f, ".{}m", memlimit)?;
+                }
+                write!(f, ".{}", distribution)
+            }
+            ResultKey::LobstersNoria {
+                shards,
+                partial,
+                durable,
+                scale,
+                memlimit,
+            } => {
+                write!(f, "lobsters-direct")?;
+                if shards != 0 {
+                    write!(f, "_{}", shards)?;
+                }
+                if !partial {
+                    write!(f, "_full")?;
+                }
+                if durable {
+                    write!(f, "_durable")?;
+                }
+                write!(f, "-{}-{}m", scale, memlimit)
+            }
+            ResultKey::LobstersMysql {
+                optimized,
+                scale,
+                memlimit,
+            } => {
+                write!(f, "lobsters-mysql")?;
+                if !optimized {
+                    write!(f, "_noopt")?;
+                }
+                write!(f, "-{}-{}m", scale, memlimit)
+            }
+        }
+    }
+}
+
+impl FromStr for ResultKey {
+    type Err = ParseError;
+
+    fn from_str(prefix: &str) -> Result<Self, Self::Err> {
+        let fail = |reason| ParseError {
+            prefix: prefix.to_string(),
+            reason,
+        };
+
+        if let Some(rest) = prefix.strip_prefix("lobsters-") {
+            let parts: Vec<_> = rest.split('-').collect();
+            let (backend, scale, memlimit) = match parts[..] {
+                [backend, scale, memlimit] => (backend, scale, memlimit),
+                _ => return Err(fail("expected lobsters-<backend>-<scale>-<memlimit>m")),
+            };
+            let scale = scale.parse().map_err(|_| fail("bad scale"))?;
+            let memlimit = number(memlimit, 'm').ok_or_else(|| fail("bad memory limit"))?;
+
+            if let Some(mut flags) = backend.strip_prefix("direct") {
+                let mut shards = 0;
+                if let Some(rest) = flags.strip_prefix('_') {
+                    let digits = rest
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(rest.len());
+                    if digits != 0 {
+                        shards = rest[..digits].parse().map_err(|_| fail("bad shards"))?;
+                        flags = &rest[digits..];
+                    }
+                }
+                let partial = !flag(&mut flags, "_full");
+                let durable = flag(&mut flags, "_durable");
+                if !flags.is_empty() {
+                    return Err(fail("unknown lobsters-direct suffix"));
+                }
+                return Ok(ResultKey::LobstersNoria {
+                    shards,
+                    partial,
+                    durable,
+                    scale,
+                    memlimit,
+                });
+            }
+            if let Some(mut flags) = backend.strip_prefix("mysql") {
+                let optimized = !flag(&mut flags, "_noopt");
+                if !flags.is_empty() {
+                    return Err(fail("unknown lobsters-mysql suffix"));
+                }
+                return Ok(ResultKey::LobstersMysql {
+                    optimized,
+                    scale,
+                    memlimit,
+                });
+            }
+            return Err(fail("unknown lobsters backend"));
+        }
+
+        let parts: Vec<_> = prefix.split('.').collect();
+        let (backend, rest) = match parts.split_first() {
+            Some((&backend, rest)) if rest.len() >= 5 => (backend, rest),
+            _ => {
+                return Err(fail(
+                    "expected <backend>.<articles>a.<target>t.<write-every>r...",
+                ))
+            }
+        };
+        let articles = number(rest[0], 'a').ok_or_else(|| fail("bad articles"))?;
+        let target = number(rest[1], 't').ok_or_else(|| fail("bad target"))?;
+        let write_every = number(rest[2], 'r').ok_or_else(|| fail("bad write-every"))?;
+        let clients = number(rest[3], 'c').ok_or_else(|| fail("bad clients"))?;
+        let (memlimit, distribution) = match rest[4..] {
+            [distribution] => (None, distribution),
+            [memlimit, distribution] => (
+                Some(number(memlimit, 'm').ok_or_else(|| fail("bad memory limit"))?),
+                distribution,
+            ),
+            _ => return Err(fail("too many parts")),
+        };
+        let distribution = distribution.to_string();
+
+        let baseline = match backend {
+            "redis" => Some(Baseline::Redis),
+            "hybrid" => Some(Baseline::Hybrid),
+            _ => None,
+        };
+        if let Some(backend) = baseline {
+            return Ok(ResultKey::VoteBaseline {
+                backend,
+                articles,
+                target,
+                write_every,
+                clients,
+                memlimit,
+                distribution,
+            });
+        }
+
+        let (partial, mut flags) = if let Some(flags) = backend.strip_prefix("partial") {
+            (true, flags)
+        } else if let Some(flags) = backend.strip_prefix("full") {
+            (false, flags)
+        } else {
+            return Err(fail("unknown vote backend"));
+        };
+        let join = !flag(&mut flags, "_nj");
+        let durable = flag(&mut flags, "_dur");
+        if !flags.is_empty() {
+            return Err(fail("unknown vote suffix"));
+        }
+        Ok(ResultKey::Vote {
+            partial,
+            join,
+            durable,
+            articles,
+            target,
+            write_every,
+            clients,
+            memlimit: memlimit.ok_or_else(|| fail("missing memory limit"))?,
+            distribution,
+        })
+    }
+}
+
+/// Parse a number followed by the given unit, like `250000t`.
+fn number(part: &str, unit: char) -> Option<usize> {
+    part.strip_suffix(unit)?.parse().ok()
+}
+
+/// Strip `suffix` off the front of `flags` if it is there, and say whether it was.
+fn flag(flags: &mut &str, suffix: &str) -> bool {
+    match flags.strip_prefix(suffix) {
+        Some(rest) => {
+            *flags = rest;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Split the name of a result file into its prefix and what comes after it.
+///
+/// `partial.10000000a.250000t.100r.4c.0m.skewed-client0.hist` splits into
+/// `partial.10000000a.250000t.100r.4c.0m.skewed` and `-client0.hist`. Returns `None` for files
+/// that are not per-target results.
+pub fn split_file(name: &str) -> Option<(&str, &str)> {
+    const SUFFIXES: &[&str] = &[
+        "-statistics.json",
+        ".manifest.json",
+        "-server.log",
+        "-resources.tsv",
+        "-network.tsv",
+        "-perf.script",
+        "-perf.folded",
+        ".summary.tsv",
+        ".log",
+    ];
+    for suffix in SUFFIXES {
+        if let Some(prefix) = name.strip_suffix(suffix) {
+            return Some((prefix, &name[prefix.len()..]));
+        }
+    }
+
+    let prefix = name.strip_suffix(".hist")?;
+    // vote writes one histogram per client, lobsters just the one
+    if let Some(at) = prefix.rfind("-client") {
+        let client = &prefix[at + "-client".len()..];
+        if !client.is_empty() && client.bytes().all(|b| b.is_ascii_digit()) {
+            return Some((&prefix[..at], &name[at..]));
+        }
+    }
+    Some((prefix, ".hist"))
+}
+
+/// Split off the repetition from the prefix of a run of a point that is run more than once.
+///
+/// `partial.10000000a.250000t.100r.4c.0m.skewed.run2` splits into
+/// `partial.10000000a.250000t.100r.4c.0m.skewed` and `Some(2)`.
+pub fn split_run(prefix: &str) -> (&str, Option<usize>) {
+    if let Some(at) = prefix.rfind(".run") {
+        if let Ok(run) = prefix[at + ".run".len()..].parse() {
+            return (&prefix[..at], Some(run));
+        }
+    }
+    (prefix, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(partial: bool, join: bool, durable: bool, memlimit: usize) -> ResultKey {
+        ResultKey::Vote {
+            partial,
+            join,
+            durable,
+            articles: 10_000_000,
+            target: 250_000,
+            write_every: 100,
+            clients: 4,
+            memlimit,
+            distribution: String::from("skewed"),
+        }
+    }
+
+    fn baseline(backend: Baseline, memlimit: Option<usize>) -> ResultKey {
+        ResultKey::VoteBaseline {
+            backend,
+            articles: 10_000_000,
+            target: 250_000,
+            write_every: 100,
+            clients: 4,
+            memlimit,
+            distribution: String::from("uniform"),
+        }
+    }
+
+    /// Check that `prefix` parses into `key`, and that `key` prints as `prefix`.
+    fn round_trip(prefix: &str, key: ResultKey) {
+        assert_eq!(prefix.parse::<ResultKey>(), Ok(key.clone()));
+        assert_eq!(key.to_string(), prefix);
+    }
+
+    #[test]
+    fn vote_flags() {
+        round_trip(
+            "partial.10000000a.250000t.100r.4c.0m.skewed",
+            vote(true, true, false, 0),
+        );
+        round_trip(
+            "full_nj.10000000a.250000t.100r.4c.0m.skewed",
+            vote(false, false, false, 0),
+        );
+        round_trip(
+            "partial_dur.10000000a.250000t.100r.4c.0m.skewed",
+            vote(true, true, true, 0),
+        );
+        round_trip(
+            "partial_nj_dur.10000000a.250000t.100r.4c.268435456m.skewed",
+            vote(true, false, true, 268_435_456),
+        );
+    }
+
+    #[test]
+    fn vote_baselines() {
+        round_trip(
+            "redis.10000000a.250000t.100r.4c.uniform",
+            baseline(Baseline::Redis, None),
+        );
+        round_trip(
+            "redis.10000000a.250000t.100r.4c.0m.uniform",
+            baseline(Baseline::Redis, Some(0)),
+        );
+        round_trip(
+            "hybrid.10000000a.250000t.100r.4c.uniform",
+            baseline(Baseline::Hybrid, None),
+        );
+        round_trip(
+            "hybrid.10000000a.250000t.100r.4c.536870912m.uniform",
+            baseline(Baseline::Hybrid, Some(536_870_912)),
+        );
+    }
+
+    #[test]
+    fn lobsters() {
+        round_trip(
+            "lobsters-direct-4000-0m",
+            ResultKey::LobstersNoria {
+                shards: 0,
+                partial: true,
+                durable: false,
+                scale: 4000,
+                memlimit: 0,
+            },
+        );
+        round_trip(
+            "lobsters-direct_4_full_durable-4000-134217728m",
+            ResultKey::LobstersNoria {
+                shards: 4,
+                partial: false,
+                durable: true,
+                scale: 4000,
+                memlimit: 134_217_728,
+            },
+        );
+        round_trip(
+            "lobsters-mysql-2000-0m",
+            ResultKey::LobstersMysql {
+                optimized: true,
+                scale: 2000,
+                memlimit: 0,
+            },
+        );
+        round_trip(
+            "lobsters-mysql_noopt-2000-0m",
+            ResultKey::LobstersMysql {
+                optimized: false,
+                scale: 2000,
+                memlimit: 0,
+            },
+        );
+    }
+
+    #[test]
+    fn rejects() {
+        for prefix in &[
+            // the flags have a fixed order
+            "lobsters-direct_full_4-4000-0m",
+            "lobsters-direct_durable_full-4000-0m",
+            "full_dur_nj.10000000a.250000t.100r.4c.0m.skewed",
+            "lobsters-mysql_opt-2000-0m",
+            "lobsters-postgres-2000-0m",
+            "lobsters-direct-4000",
+            "lobsters-direct-4000-0",
+            // noria always has a memory limit, if only 0
+            "partial.10000000a.250000t.100r.4c.skewed",
+            "memcached.10000000a.250000t.100r.4c.skewed",
+            "partial.10000000a.250000t.4c.0m.skewed",
+            "partial.10000000a.250000t.100r.4c.0m.skewed.run2",
+            "partial.10000000a.250000t.100r.4c.0m.skewed.runX",
+            "",
+        ] {
+            assert!(prefix.parse::<ResultKey>().is_err(), "{:?} parsed", prefix);
+        }
+    }
+
+    #[test]
+    fn files() {
+        let prefix = "partial.10000000a.250000t.100r.4c.0m.skewed";
+        for suffix in &[
+            "-client0.hist",
+            "-client12.hist",
+            "-server.log",
+            "-statistics.json",
+            ".manifest.json",
+            ".log",
+        ] {
+            let name = format!("{}{}", prefix, suffix);
+            assert_eq!(split_file(&name), Some((prefix, *suffix)));
+        }
+        assert_eq!(
+            split_file("lobsters-direct-4000-0m.hist"),
+            Some(("lobsters-direct-4000-0m", ".hist"))
+        );
+        // not a client number, so part of the prefix
+        assert_eq!(
+            split_file("lobsters-direct-4000-0m-clientX.hist"),
+            Some(("lobsters-direct-4000-0m-clientX", ".hist"))
+        );
+        assert_eq!(split_file("usage.tsv"), None);
+    }
+
+    #[test]
+    fn runs() {
+        let prefix = "partial.10000000a.250000t.100r.4c.0m.skewed";
+        assert_eq!(split_run(prefix), (prefix, None));
+        assert_eq!(split_run(&format!("{}.run2", prefix)), (prefix, Some(2)));
+        assert_eq!(split_run(&format!("{}.run12", prefix)), (prefix, Some(12)));
+        let bad = format!("{}.runX", prefix);
+        assert_eq!(split_run(&bad), (&*bad, None));
+
+        // a repeated run's files split into the run's prefix first
+        let name = format!("{}.run3-client1.hist", prefix);
+        let (run, suffix) = split_file(&name).unwrap();
+        assert_eq!(suffix, "-client1.hist");
+        assert_eq!(split_run(run), (prefix, Some(3)));
+        let name = format!("{}.run1-server.log", prefix);
+        let (run, suffix) = split_file(&name).unwrap();
+        assert_eq!(suffix, "-server.log");
+        assert_eq!(split_run(run), (prefix, Some(1)));
+        assert_eq!(run.parse::<ResultKey>().ok(), None);
+        assert!(split_run(run).0.parse::<ResultKey>().is_ok());
+    }
+}
@@ -2,6 +2,7 @@ use clap::{App, Arg};
 use hdrhistogram::serialization::interval_log;
 use hdrhistogram::serialization::Deserializer;
 use hdrhistogram::Histogram;
+use result_key::ResultKey;
 use std::collections::HashMap;
 use std::time::Duration;
 use trawler::LobstersRequest;
@@ -112,8 +113,24 @@ fn main() {
             }};
         }
 
+        // the name of the file says which benchmark wrote it
+        let name = std::path::Path::new(filename)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(result_key::split_file);
+        let lobsters = match name.map(|(prefix, _)| result_key::split_run(prefix).0.parse()) {
+            Some(Ok(ResultKey::LobstersNoria { .. }))
+            | Some(Ok(ResultKey::LobstersMysql { .. })) => true,
+            Some(Ok(_)) => false,
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                filename.contains("lobsters")
+            }
+            None => filename.contains("lobsters"),
+        };
+
         // operation|"all" => (processing, sojourn)
-        if filename.contains("lobsters") {
+        if lobsters {
             // lobsters writes out all the histograms in ::all() order.
             for variant in LobstersRequest::all() {
                 ex!(LobstersRequest::variant_name(&variant));